@@ -0,0 +1,700 @@
+use crate::cpu::PROGRAM_START;
+use crate::error::AsmError;
+use crate::instruction::Instruction;
+use crate::ram::XO_CHIP_RAM_SIZE;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// Assembles Cowgod style mnemonics, the same syntax the disassembler lists, into a rom that
+// loads at PROGRAM_START:
+//
+//     ; comments run to the end of the line
+//     SPEED = 2 * 3              ; constants are expressions
+//     include "sprites.asm"      ; paths are relative to the including file
+//     main:
+//         LD V0, SPEED + 1
+//         LD I, ball
+//         DRW V0, V1, ball_end - ball
+//         JP $                   ; $ is the address of the current line
+//     ball:
+//         db 0b11000000, 0xC0, "text"
+//         dw 0x1234
+//     ball_end:
+//
+// Mnemonics, registers and directives are case insensitive, labels and constants aren't.
+
+// Deep enough for any sensible tree of includes, shallow enough to catch a file including itself
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// Assembles source, resolving includes against the current directory
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::new();
+    assembler.read_source(Rc::from("<input>"), Path::new(""), source, 0)?;
+    assembler.finish()
+}
+
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, AsmError> {
+    let path = path.as_ref();
+    let name: Rc<str> = Rc::from(path.display().to_string());
+    let source = fs::read_to_string(path).map_err(|e| AsmError {
+        file: name.to_string(),
+        line: 0,
+        column: 0,
+        message: format!("can't read file: {}", e),
+    })?;
+    let mut assembler = Assembler::new();
+    assembler.read_source(name, path.parent().unwrap_or_else(|| Path::new("")), &source, 0)?;
+    assembler.finish()
+}
+
+#[derive(Debug, Clone)]
+struct Location {
+    file: Rc<str>,
+    line: usize,
+}
+
+impl Location {
+    fn error<S: Into<String>>(&self, column: usize, message: S) -> AsmError {
+        AsmError {
+            file: self.file.to_string(),
+            line: self.line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(Vec<u8>),
+    Punct(&'static str),
+}
+
+// Longest first, so << isn't read as two <
+const PUNCTUATION: [&str; 18] = [
+    "<<", ">>", "=", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")", "[", "]", ",", ":",
+];
+
+fn tokenize(text: &str, location: &Location) -> Result<Vec<(Token, usize)>, AsmError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        if c == ';' {
+            break;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), column));
+            continue;
+        }
+        if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().filter(|c| **c != '_').collect();
+            let lower = text.to_ascii_lowercase();
+            let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+                i64::from_str_radix(hex, 16)
+            } else if let Some(binary) = lower.strip_prefix("0b") {
+                i64::from_str_radix(binary, 2)
+            } else {
+                lower.parse()
+            };
+            let value = parsed.map_err(|_| location.error(column, format!("bad number '{}'", text)))?;
+            tokens.push((Token::Number(value), column));
+            continue;
+        }
+        if c == '"' || c == '\'' {
+            let mut bytes = Vec::new();
+            i += 1;
+            loop {
+                let c = *chars.get(i).ok_or_else(|| location.error(column, "unterminated string"))?;
+                i += 1;
+                if c == chars[column - 1] {
+                    break;
+                }
+                let c = if c == '\\' {
+                    let escaped = *chars.get(i).ok_or_else(|| location.error(column, "unterminated string"))?;
+                    i += 1;
+                    match escaped {
+                        'n' => '\n',
+                        't' => '\t',
+                        '0' => '\0',
+                        '\\' | '"' | '\'' => escaped,
+                        _ => return Err(location.error(i - 1, format!("unknown escape '\\{}'", escaped))),
+                    }
+                } else {
+                    c
+                };
+                if !c.is_ascii() {
+                    return Err(location.error(i, "only ascii characters fit in a byte"));
+                }
+                bytes.push(c as u8);
+            }
+            if c == '\'' {
+                // A character literal is just a number
+                if bytes.len() != 1 {
+                    return Err(location.error(column, "a character literal needs exactly one character"));
+                }
+                tokens.push((Token::Number(bytes[0] as i64), column));
+            } else {
+                tokens.push((Token::Str(bytes), column));
+            }
+            continue;
+        }
+        if c == '$' {
+            tokens.push((Token::Punct("$"), column));
+            i += 1;
+            continue;
+        }
+        let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+        match PUNCTUATION.iter().find(|p| rest.starts_with(**p)) {
+            Some(p) => {
+                tokens.push((Token::Punct(p), column));
+                i += p.len();
+            }
+            None => return Err(location.error(column, format!("unexpected character '{}'", c))),
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Symbol(String, usize),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>, usize),
+}
+
+// Recursive descent over the tokens of one expression. here is the value of $.
+struct ExprParser<'a> {
+    tokens: &'a [(Token, usize)],
+    position: usize,
+    here: u16,
+    location: &'a Location,
+    end_column: usize,
+}
+
+// Binary operators from loosest to tightest binding
+const PRECEDENCE: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+impl<'a> ExprParser<'a> {
+    fn parse(&mut self) -> Result<Expr, AsmError> {
+        let expr = self.binary(0)?;
+        if let Some((_, column)) = self.tokens.get(self.position) {
+            return Err(self.location.error(*column, "unexpected text after expression"));
+        }
+        Ok(expr)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, AsmError> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some((Token::Punct(op), column)) = self.tokens.get(self.position) {
+            if !PRECEDENCE[level].contains(op) {
+                break;
+            }
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right), *column);
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, AsmError> {
+        let (token, column) = match self.tokens.get(self.position) {
+            Some(token) => token.clone(),
+            None => return Err(self.location.error(self.end_column, "expected an expression")),
+        };
+        self.position += 1;
+        match token {
+            Token::Punct("-") => Ok(Expr::Negate(Box::new(self.unary()?))),
+            Token::Punct("~") => Ok(Expr::Not(Box::new(self.unary()?))),
+            Token::Punct("+") => self.unary(),
+            Token::Punct("$") => Ok(Expr::Number(self.here as i64)),
+            Token::Punct("(") => {
+                let expr = self.binary(0)?;
+                match self.tokens.get(self.position) {
+                    Some((Token::Punct(")"), _)) => {
+                        self.position += 1;
+                        Ok(expr)
+                    }
+                    Some((_, column)) => Err(self.location.error(*column, "expected ')'")),
+                    None => Err(self.location.error(self.end_column, "expected ')'")),
+                }
+            }
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Ident(name) => {
+                if is_reserved(&name) {
+                    return Err(self.location.error(column, format!("'{}' can't be used in an expression", name)));
+                }
+                Ok(Expr::Symbol(name, column))
+            }
+            Token::Str(_) => Err(self.location.error(column, "strings can only be used with db")),
+            Token::Punct(p) => Err(self.location.error(column, format!("unexpected '{}'", p))),
+        }
+    }
+}
+
+fn register(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => digit.to_digit(16).map(|d| d as u8),
+        _ => None,
+    }
+}
+
+// Operand keywords, e.g. the DT in LD DT, V0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Special {
+    I,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+}
+
+fn special(name: &str) -> Option<Special> {
+    match name.to_ascii_uppercase().as_str() {
+        "I" => Some(Special::I),
+        "DT" => Some(Special::Dt),
+        "ST" => Some(Special::St),
+        "K" => Some(Special::K),
+        "F" => Some(Special::F),
+        "HF" => Some(Special::Hf),
+        "B" => Some(Special::B),
+        "R" => Some(Special::R),
+        _ => None,
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    register(name).is_some() || special(name).is_some() || name.eq_ignore_ascii_case("long")
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Register(u8),
+    // VX - VY, for SAVE and LOAD
+    Range(u8, u8),
+    Special(Special),
+    // [I]
+    IndirectI,
+    // LONG NNNN, for the XO-CHIP LD I, LONG
+    Long(Expr, usize),
+    Value(Expr, usize),
+}
+
+#[derive(Debug, Clone)]
+enum DataItem {
+    Value(Expr, usize),
+    Str(Vec<u8>),
+}
+
+enum Kind {
+    Instruction { mnemonic: String, column: usize, operands: Vec<Operand> },
+    Bytes(Vec<DataItem>),
+    Words(Vec<(Expr, usize)>),
+}
+
+struct Statement {
+    location: Location,
+    address: u16,
+    kind: Kind,
+}
+
+enum Symbol {
+    Label(u16),
+    // Evaluated when used, so constants can refer to labels further down
+    Constant(Expr, Location),
+}
+
+struct Assembler {
+    statements: Vec<Statement>,
+    symbols: HashMap<String, Symbol>,
+    // Address of the next byte, u32 so running past the end of memory can be noticed
+    address: u32,
+}
+
+impl Assembler {
+    fn new() -> Assembler {
+        Assembler {
+            statements: Vec::new(),
+            symbols: HashMap::new(),
+            address: PROGRAM_START as u32,
+        }
+    }
+
+    // The first pass: splits the source into statements, works out their addresses and
+    // collects the labels and constants. Includes are read in place.
+    fn read_source(&mut self, file: Rc<str>, dir: &Path, source: &str, depth: usize) -> Result<(), AsmError> {
+        for (index, text) in source.lines().enumerate() {
+            let location = Location { file: file.clone(), line: index + 1 };
+            let tokens = tokenize(text, &location)?;
+            self.read_line(&location, dir, &tokens, text.chars().count() + 1, depth)?;
+        }
+        Ok(())
+    }
+
+    fn read_line(&mut self, location: &Location, dir: &Path, tokens: &[(Token, usize)], end_column: usize, depth: usize)
+        -> Result<(), AsmError> {
+        let mut tokens = tokens;
+
+        // label:
+        if let [(Token::Ident(name), column), (Token::Punct(":"), _), ..] = tokens {
+            self.define(location, *column, name, Symbol::Label(self.address as u16))?;
+            tokens = &tokens[2..];
+        }
+
+        let (name, column) = match tokens.first() {
+            None => return Ok(()),
+            Some((Token::Ident(name), column)) => (name.clone(), *column),
+            Some((_, column)) => return Err(location.error(*column, "expected an instruction")),
+        };
+        let rest = &tokens[1..];
+
+        // NAME = expr or NAME equ expr
+        let is_constant = match rest.first() {
+            Some((Token::Ident(word), _)) => word.eq_ignore_ascii_case("equ"),
+            Some((Token::Punct(p), _)) => *p == "=",
+            _ => false,
+        };
+        if is_constant {
+            let expr = self.expression(location, &rest[1..], end_column)?;
+            return self.define(location, column, &name, Symbol::Constant(expr, location.clone()));
+        }
+
+        let (kind, size) = match name.to_ascii_lowercase().as_str() {
+            "include" => {
+                let path = match rest {
+                    [(Token::Str(path), _)] => String::from_utf8_lossy(path).to_string(),
+                    _ => return Err(location.error(column, "include needs a quoted file name")),
+                };
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(location.error(column, "includes are nested too deeply"));
+                }
+                let path: PathBuf = dir.join(path);
+                let source = fs::read_to_string(&path).map_err(|e| {
+                    location.error(column, format!("can't read {}: {}", path.display(), e))
+                })?;
+                let name: Rc<str> = Rc::from(path.display().to_string());
+                let dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+                return self.read_source(name, &dir, &source, depth + 1);
+            }
+            "db" => {
+                let mut items = Vec::new();
+                let mut size = 0;
+                for operand in split_operands(rest, location, end_column)? {
+                    match operand {
+                        [(Token::Str(bytes), _)] => {
+                            size += bytes.len();
+                            items.push(DataItem::Str(bytes.clone()));
+                        }
+                        _ => {
+                            let column = operand[0].1;
+                            items.push(DataItem::Value(self.expression(location, operand, end_column)?, column));
+                            size += 1;
+                        }
+                    }
+                }
+                (Kind::Bytes(items), size)
+            }
+            "dw" => {
+                let mut items = Vec::new();
+                for operand in split_operands(rest, location, end_column)? {
+                    items.push((self.expression(location, operand, end_column)?, operand[0].1));
+                }
+                let size = items.len() * 2;
+                (Kind::Words(items), size)
+            }
+            _ => {
+                let mut operands = Vec::new();
+                for operand in split_operands(rest, location, end_column)? {
+                    operands.push(self.operand(location, operand, end_column)?);
+                }
+                // LD I, LONG NNNN is followed by its 16 bit address
+                let size = if operands.iter().any(|o| matches!(o, Operand::Long(..))) { 4 } else { 2 };
+                (Kind::Instruction { mnemonic: name, column, operands }, size)
+            }
+        };
+
+        self.statements.push(Statement { location: location.clone(), address: self.address as u16, kind });
+        self.address += size as u32;
+        if self.address as usize > XO_CHIP_RAM_SIZE {
+            return Err(location.error(column, "program doesn't fit in memory"));
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, location: &Location, column: usize, name: &str, symbol: Symbol) -> Result<(), AsmError> {
+        if is_reserved(name) {
+            return Err(location.error(column, format!("'{}' is a reserved name", name)));
+        }
+        if self.symbols.contains_key(name) {
+            return Err(location.error(column, format!("'{}' is already defined", name)));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    fn expression(&self, location: &Location, tokens: &[(Token, usize)], end_column: usize) -> Result<Expr, AsmError> {
+        let mut parser = ExprParser {
+            tokens,
+            position: 0,
+            here: self.address as u16,
+            location,
+            end_column,
+        };
+        parser.parse()
+    }
+
+    fn operand(&self, location: &Location, tokens: &[(Token, usize)], end_column: usize) -> Result<Operand, AsmError> {
+        let column = tokens[0].1;
+        match tokens {
+            [(Token::Ident(name), _)] if register(name).is_some() => Ok(Operand::Register(register(name).unwrap())),
+            [(Token::Ident(name), _)] if special(name).is_some() => Ok(Operand::Special(special(name).unwrap())),
+            [(Token::Punct("["), _), (Token::Ident(name), _), (Token::Punct("]"), _)] if special(name) == Some(Special::I) => {
+                Ok(Operand::IndirectI)
+            }
+            [(Token::Ident(x), _), (Token::Punct("-"), _), (Token::Ident(y), _)]
+                if register(x).is_some() && register(y).is_some() => {
+                Ok(Operand::Range(register(x).unwrap(), register(y).unwrap()))
+            }
+            [(Token::Ident(name), _), rest @ ..] if name.eq_ignore_ascii_case("long") => {
+                let column = rest.first().map_or(end_column, |(_, column)| *column);
+                Ok(Operand::Long(self.expression(location, rest, end_column)?, column))
+            }
+            _ => Ok(Operand::Value(self.expression(location, tokens, end_column)?, column)),
+        }
+    }
+
+    // The second pass: evaluates every expression now all the labels are known and encodes
+    // the statements
+    fn finish(self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::new();
+        for statement in self.statements.iter() {
+            debug_assert_eq!(statement.address as usize, PROGRAM_START as usize + rom.len());
+            let location = &statement.location;
+            match &statement.kind {
+                Kind::Bytes(items) => {
+                    for item in items {
+                        match item {
+                            DataItem::Str(bytes) => rom.extend_from_slice(bytes),
+                            DataItem::Value(expr, column) => rom.push(self.byte(location, expr, *column)?),
+                        }
+                    }
+                }
+                Kind::Words(items) => {
+                    for (expr, column) in items {
+                        let value = self.ranged(location, expr, *column, -0x8000, 0xFFFF, "a word")?;
+                        rom.extend_from_slice(&(value as u16).to_be_bytes());
+                    }
+                }
+                Kind::Instruction { mnemonic, column, operands } => {
+                    let (instruction, long) = self.instruction(location, mnemonic, *column, operands)?;
+                    rom.extend_from_slice(&instruction.encode().to_be_bytes());
+                    if let Some(long) = long {
+                        rom.extend_from_slice(&long.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(rom)
+    }
+
+    fn eval(&self, location: &Location, expr: &Expr, visiting: &mut Vec<String>) -> Result<i64, AsmError> {
+        match expr {
+            Expr::Number(value) => Ok(*value),
+            Expr::Symbol(name, column) => match self.symbols.get(name) {
+                Some(Symbol::Label(address)) => Ok(*address as i64),
+                Some(Symbol::Constant(expr, defined_at)) => {
+                    if visiting.contains(name) {
+                        return Err(location.error(*column, format!("'{}' is defined in terms of itself", name)));
+                    }
+                    visiting.push(name.clone());
+                    let value = self.eval(defined_at, expr, visiting);
+                    visiting.pop();
+                    value
+                }
+                None => Err(location.error(*column, format!("undefined name '{}'", name))),
+            },
+            Expr::Negate(expr) => Ok(self.eval(location, expr, visiting)?.wrapping_neg()),
+            Expr::Not(expr) => Ok(!self.eval(location, expr, visiting)?),
+            Expr::Binary(op, left, right, column) => {
+                let left = self.eval(location, left, visiting)?;
+                let right = self.eval(location, right, visiting)?;
+                let value = match *op {
+                    "|" => left | right,
+                    "^" => left ^ right,
+                    "&" => left & right,
+                    "<<" => left.checked_shl(right as u32).unwrap_or(0),
+                    ">>" => left.checked_shr(right as u32).unwrap_or(0),
+                    "+" => left.wrapping_add(right),
+                    "-" => left.wrapping_sub(right),
+                    "*" => left.wrapping_mul(right),
+                    "/" | "%" if right == 0 => return Err(location.error(*column, "division by zero")),
+                    "/" => left.wrapping_div(right),
+                    "%" => left.wrapping_rem(right),
+                    _ => unreachable!("operator {} isn't in PRECEDENCE", op),
+                };
+                Ok(value)
+            }
+        }
+    }
+
+    fn ranged(&self, location: &Location, expr: &Expr, column: usize, min: i64, max: i64, what: &str) -> Result<i64, AsmError> {
+        let value = self.eval(location, expr, &mut Vec::new())?;
+        if value < min || value > max {
+            return Err(location.error(column, format!("{} doesn't fit in {}", value, what)));
+        }
+        Ok(value)
+    }
+
+    // Negative bytes are allowed and wrap, so ADD V0, -1 works
+    fn byte(&self, location: &Location, expr: &Expr, column: usize) -> Result<u8, AsmError> {
+        Ok(self.ranged(location, expr, column, -0x80, 0xFF, "a byte")? as u8)
+    }
+
+    fn nibble(&self, location: &Location, expr: &Expr, column: usize) -> Result<u8, AsmError> {
+        Ok(self.ranged(location, expr, column, 0, 0xF, "a nibble")? as u8)
+    }
+
+    fn address(&self, location: &Location, expr: &Expr, column: usize) -> Result<u16, AsmError> {
+        Ok(self.ranged(location, expr, column, 0, 0xFFF, "a 12 bit address")? as u16)
+    }
+
+    // Returns the instruction along with the address that follows LD I, LONG
+    fn instruction(&self, location: &Location, mnemonic: &str, column: usize, operands: &[Operand])
+        -> Result<(Instruction, Option<u16>), AsmError> {
+        use self::Operand::{IndirectI, Long, Range, Register as V, Special as S, Value};
+        let upper = mnemonic.to_ascii_uppercase();
+        let instruction = match (upper.as_str(), operands) {
+            ("CLS", []) => Instruction::Clear,
+            ("RET", []) => Instruction::Return,
+            ("SCD", [Value(e, c)]) => Instruction::ScrollDown(self.nibble(location, e, *c)?),
+            ("SCU", [Value(e, c)]) => Instruction::ScrollUp(self.nibble(location, e, *c)?),
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::Lores,
+            ("HIGH", []) => Instruction::Hires,
+            ("SYS", [Value(e, c)]) => Instruction::Sys(self.address(location, e, *c)?),
+            ("JP", [Value(e, c)]) => Instruction::Jump(self.address(location, e, *c)?),
+            ("JP", [V(x), Value(e, c)]) => {
+                let nnn = self.address(location, e, *c)?;
+                // BXNN with the jump quirk takes X from the top of the address
+                if *x != 0 && (nnn >> 8) as u8 != *x {
+                    return Err(location.error(*c, format!("a jump using V{:X} needs an address starting with {:X}", x, x)));
+                }
+                Instruction::JumpOffset { x: (nnn >> 8) as u8, nnn }
+            }
+            ("CALL", [Value(e, c)]) => Instruction::Call(self.address(location, e, *c)?),
+            ("SE", [V(x), V(y)]) => Instruction::SkipIfRegistersEqual { x: *x, y: *y },
+            ("SE", [V(x), Value(e, c)]) => Instruction::SkipIfEqual { x: *x, nn: self.byte(location, e, *c)? },
+            ("SNE", [V(x), V(y)]) => Instruction::SkipIfRegistersNotEqual { x: *x, y: *y },
+            ("SNE", [V(x), Value(e, c)]) => Instruction::SkipIfNotEqual { x: *x, nn: self.byte(location, e, *c)? },
+            ("SAVE", [Range(x, y)]) => Instruction::SaveRange { x: *x, y: *y },
+            ("LOAD", [Range(x, y)]) => Instruction::LoadRange { x: *x, y: *y },
+            ("LD", [V(x), V(y)]) => Instruction::Copy { x: *x, y: *y },
+            ("LD", [V(x), Value(e, c)]) => Instruction::Set { x: *x, nn: self.byte(location, e, *c)? },
+            ("LD", [S(Special::I), Long(e, c)]) => {
+                let address = self.ranged(location, e, *c, 0, 0xFFFF, "a 16 bit address")? as u16;
+                return Ok((Instruction::LoadILong, Some(address)));
+            }
+            ("LD", [S(Special::I), Value(e, c)]) => Instruction::LoadI(self.address(location, e, *c)?),
+            ("LD", [V(x), S(Special::Dt)]) => Instruction::GetDelay { x: *x },
+            ("LD", [V(x), S(Special::K)]) => Instruction::WaitKey { x: *x },
+            ("LD", [S(Special::Dt), V(x)]) => Instruction::SetDelay { x: *x },
+            ("LD", [S(Special::St), V(x)]) => Instruction::SetSound { x: *x },
+            ("LD", [S(Special::F), V(x)]) => Instruction::Font { x: *x },
+            ("LD", [S(Special::Hf), V(x)]) => Instruction::BigFont { x: *x },
+            ("LD", [S(Special::B), V(x)]) => Instruction::Bcd { x: *x },
+            ("LD", [IndirectI, V(x)]) => Instruction::Store { x: *x },
+            ("LD", [V(x), IndirectI]) => Instruction::Load { x: *x },
+            ("LD", [S(Special::R), V(x)]) => Instruction::SaveFlags { x: *x },
+            ("LD", [V(x), S(Special::R)]) => Instruction::LoadFlags { x: *x },
+            ("ADD", [V(x), V(y)]) => Instruction::Add { x: *x, y: *y },
+            ("ADD", [V(x), Value(e, c)]) => Instruction::AddImmediate { x: *x, nn: self.byte(location, e, *c)? },
+            ("ADD", [S(Special::I), V(x)]) => Instruction::AddI { x: *x },
+            ("OR", [V(x), V(y)]) => Instruction::Or { x: *x, y: *y },
+            ("AND", [V(x), V(y)]) => Instruction::And { x: *x, y: *y },
+            ("XOR", [V(x), V(y)]) => Instruction::Xor { x: *x, y: *y },
+            ("SUB", [V(x), V(y)]) => Instruction::Sub { x: *x, y: *y },
+            ("SUBN", [V(x), V(y)]) => Instruction::SubReverse { x: *x, y: *y },
+            // Without a VY the shift is of VX itself
+            ("SHR", [V(x)]) => Instruction::ShiftRight { x: *x, y: *x },
+            ("SHR", [V(x), V(y)]) => Instruction::ShiftRight { x: *x, y: *y },
+            ("SHL", [V(x)]) => Instruction::ShiftLeft { x: *x, y: *x },
+            ("SHL", [V(x), V(y)]) => Instruction::ShiftLeft { x: *x, y: *y },
+            ("RND", [V(x), Value(e, c)]) => Instruction::Random { x: *x, nn: self.byte(location, e, *c)? },
+            ("DRW", [V(x), V(y), Value(e, c)]) => Instruction::Draw { x: *x, y: *y, n: self.nibble(location, e, *c)? },
+            ("SKP", [V(x)]) => Instruction::SkipIfKey { x: *x },
+            ("SKNP", [V(x)]) => Instruction::SkipIfNotKey { x: *x },
+            ("PLANE", [Value(e, c)]) => {
+                Instruction::Plane(self.ranged(location, e, *c, 0, 3, "a plane mask")? as u8)
+            }
+            ("AUDIO", []) => Instruction::Audio,
+            ("PITCH", [V(x)]) => Instruction::Pitch { x: *x },
+            (
+                "CLS" | "RET" | "SCD" | "SCU" | "SCR" | "SCL" | "EXIT" | "LOW" | "HIGH" | "SYS" | "JP" | "CALL"
+                | "SE" | "SNE" | "SAVE" | "LOAD" | "LD" | "ADD" | "OR" | "AND" | "XOR" | "SUB" | "SUBN"
+                | "SHR" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP" | "PLANE" | "AUDIO" | "PITCH",
+                _,
+            ) => return Err(location.error(column, format!("wrong operands for {}", upper))),
+            _ => return Err(location.error(column, format!("unknown instruction '{}'", mnemonic))),
+        };
+        Ok((instruction, None))
+    }
+}
+
+// Splits operands on the commas between them. Commas inside brackets don't count.
+fn split_operands<'a>(tokens: &'a [(Token, usize)], location: &Location, end_column: usize)
+    -> Result<Vec<&'a [(Token, usize)]>, AsmError> {
+    let mut operands = Vec::new();
+    if tokens.is_empty() {
+        return Ok(operands);
+    }
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, (token, column)) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct("(") | Token::Punct("[") => depth += 1,
+            Token::Punct(")") | Token::Punct("]") => depth -= 1,
+            Token::Punct(",") if depth == 0 => {
+                if i == start {
+                    return Err(location.error(*column, "missing operand"));
+                }
+                operands.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start == tokens.len() {
+        return Err(location.error(end_column, "missing operand"));
+    }
+    operands.push(&tokens[start..]);
+    Ok(operands)
+}
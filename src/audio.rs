@@ -0,0 +1,265 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// The timers and the beeper all run at 60Hz
+pub const FRAMES_PER_SECOND: u32 = 60;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+pub const DEFAULT_FREQUENCY: f32 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+// The XO-CHIP audio pattern: 128 one bit samples loaded by F002, played back at a rate set by
+// FX3A while the sound timer runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioPattern {
+    pub buffer: [u8; 16],
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+    pub const SAMPLES: usize = 128;
+
+    // Pitch 64 plays the pattern at 4000 samples a second, every 48 steps doubles or halves that
+    pub fn playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    pub fn bit(&self, index: usize) -> bool {
+        let byte = self.buffer[(index / 8) % self.buffer.len()];
+        byte & (0x80 >> (index % 8)) != 0
+    }
+}
+
+impl Default for AudioPattern {
+    fn default() -> AudioPattern {
+        AudioPattern {
+            buffer: [0; 16],
+            pitch: 64,
+        }
+    }
+}
+
+// Somewhere the chip8 beeper can be played. The frontend calls update once per 60Hz frame
+// with whether the sound timer is running, and the XO-CHIP pattern if the rom loaded one.
+pub trait AudioBackend {
+    fn update(&mut self, beeping: bool, pattern: Option<&AudioPattern>) -> io::Result<()>;
+}
+
+// Plays nothing, for when there is no audio device
+pub struct NullAudio;
+
+impl AudioBackend for NullAudio {
+    fn update(&mut self, _beeping: bool, _pattern: Option<&AudioPattern>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Generates the beeper tone one sample at a time
+pub struct SquareWave {
+    sample_rate: u32,
+    frequency: f32,
+    volume: f32,
+    phase: f32,
+}
+
+impl SquareWave {
+    pub fn new(sample_rate: u32, frequency: f32, volume: f32) -> SquareWave {
+        SquareWave {
+            sample_rate,
+            frequency,
+            volume,
+            phase: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Returns the next sample, silence when the beeper is off
+    pub fn next_sample(&mut self, on: bool) -> f32 {
+        if !on {
+            self.phase = 0.0;
+            return 0.0;
+        }
+        let sample = if self.phase < 0.5 { self.volume } else { -self.volume };
+        self.phase = (self.phase + self.frequency / self.sample_rate as f32).fract();
+        sample
+    }
+
+    // Like next_sample, but plays the XO-CHIP pattern instead of the square wave when there is
+    // one. The phase then counts through the pattern's 128 bits.
+    pub fn next_pattern_sample(&mut self, on: bool, pattern: Option<&AudioPattern>) -> f32 {
+        let pattern = match pattern {
+            Some(pattern) => pattern,
+            None => return self.next_sample(on),
+        };
+        if !on {
+            self.phase = 0.0;
+            return 0.0;
+        }
+        let samples = AudioPattern::SAMPLES as f32;
+        let sample = if pattern.bit((self.phase * samples) as usize) { self.volume } else { -self.volume };
+        self.phase = (self.phase + pattern.playback_rate() / samples / self.sample_rate as f32).fract();
+        sample
+    }
+}
+
+// Writes the beeper to a 16 bit mono WAV file, one frame of samples per update. Nothing is
+// played, which makes it useful for headless runs and for checking the audio in tests.
+pub struct WavSink<W: Write + Seek> {
+    writer: W,
+    wave: SquareWave,
+    frames: u64,
+    samples_written: u32,
+}
+
+impl WavSink<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<WavSink<BufWriter<File>>> {
+        WavSink::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(writer: W, sample_rate: u32) -> io::Result<WavSink<W>> {
+        let mut sink = WavSink {
+            writer,
+            wave: SquareWave::new(sample_rate, DEFAULT_FREQUENCY, DEFAULT_VOLUME),
+            frames: 0,
+            samples_written: 0,
+        };
+        // The sizes in the header are filled in by finish
+        sink.write_header()?;
+        Ok(sink)
+    }
+
+    pub fn samples_written(&self) -> u32 {
+        self.samples_written
+    }
+
+    // Patches the header with the final sizes and hands back the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let sample_rate = self.wave.sample_rate();
+        let data_len = self.samples_written * 2;
+        let w = &mut self.writer;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(36 + data_len).to_le_bytes())?;
+        w.write_all(b"WAVE")?;
+        w.write_all(b"fmt ")?;
+        // PCM, mono, 16 bits per sample
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?;
+        w.write_all(&sample_rate.to_le_bytes())?;
+        w.write_all(&(sample_rate * 2).to_le_bytes())?;
+        w.write_all(&2u16.to_le_bytes())?;
+        w.write_all(&16u16.to_le_bytes())?;
+        w.write_all(b"data")?;
+        w.write_all(&data_len.to_le_bytes())
+    }
+}
+
+impl<W: Write + Seek> AudioBackend for WavSink<W> {
+    fn update(&mut self, beeping: bool, pattern: Option<&AudioPattern>) -> io::Result<()> {
+        // Spread the samples so a second of frames is exactly sample_rate samples long
+        let rate = self.wave.sample_rate() as u64;
+        let fps = FRAMES_PER_SECOND as u64;
+        let samples = (self.frames + 1) * rate / fps - self.frames * rate / fps;
+        for _ in 0..samples {
+            let sample = self.wave.next_pattern_sample(beeping, pattern);
+            let value = (sample * i16::MAX as f32) as i16;
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+        self.frames += 1;
+        self.samples_written += samples as u32;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cpal")]
+pub use self::beeper::Beeper;
+
+// Plays the beeper through the host's default output device
+#[cfg(feature = "cpal")]
+mod beeper {
+    use super::{AudioBackend, AudioPattern, SquareWave, DEFAULT_FREQUENCY, DEFAULT_VOLUME};
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::{Sample, SampleFormat, Stream, StreamConfig};
+    use std::error::Error;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    // What the audio thread should be playing
+    #[derive(Default)]
+    struct Playing {
+        beeping: bool,
+        pattern: Option<AudioPattern>,
+    }
+
+    pub struct Beeper {
+        playing: Arc<Mutex<Playing>>,
+        // Dropping the stream stops playback, so it has to live as long as the beeper
+        _stream: Stream,
+    }
+
+    impl Beeper {
+        pub fn new() -> Result<Beeper, Box<dyn Error>> {
+            let host = cpal::default_host();
+            let device = host.default_output_device().ok_or("no audio output device")?;
+            let supported = device.default_output_config()?;
+            let format = supported.sample_format();
+            let config: StreamConfig = supported.into();
+            let playing = Arc::new(Mutex::new(Playing::default()));
+
+            let stream = match format {
+                SampleFormat::F32 => build_stream::<f32>(&device, &config, playing.clone())?,
+                SampleFormat::I16 => build_stream::<i16>(&device, &config, playing.clone())?,
+                SampleFormat::U16 => build_stream::<u16>(&device, &config, playing.clone())?,
+            };
+            stream.play()?;
+
+            Ok(Beeper {
+                playing,
+                _stream: stream,
+            })
+        }
+    }
+
+    impl AudioBackend for Beeper {
+        fn update(&mut self, beeping: bool, pattern: Option<&AudioPattern>) -> io::Result<()> {
+            let mut playing = self.playing.lock().unwrap();
+            playing.beeping = beeping;
+            playing.pattern = pattern.copied();
+            Ok(())
+        }
+    }
+
+    fn build_stream<T: Sample>(device: &cpal::Device, config: &StreamConfig, playing: Arc<Mutex<Playing>>)
+        -> Result<Stream, Box<dyn Error>> {
+        let channels = config.channels as usize;
+        let mut wave = SquareWave::new(config.sample_rate.0, DEFAULT_FREQUENCY, DEFAULT_VOLUME);
+        let stream = device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let playing = playing.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    let next = wave.next_pattern_sample(playing.beeping, playing.pattern.as_ref());
+                    let value: T = Sample::from(&next);
+                    for sample in frame.iter_mut() {
+                        *sample = value;
+                    }
+                }
+            },
+            |e| eprintln!("Audio stream error: {}", e),
+        )?;
+        Ok(stream)
+    }
+}
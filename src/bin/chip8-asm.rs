@@ -0,0 +1,42 @@
+// Assembles a source file of Cowgod style mnemonics into a rom. The output defaults to the
+// source's name with a .ch8 extension.
+use rust_chip8::assembler::assemble_file;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+fn usage() -> ! {
+    eprintln!("usage: chip8-asm SOURCE [-o OUT]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut source = None;
+    let mut output = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "-h" | "--help" => usage(),
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option '{}'", arg);
+                usage()
+            }
+            _ if source.is_none() => source = Some(PathBuf::from(arg)),
+            _ => usage(),
+        }
+    }
+    let source = source.unwrap_or_else(|| usage());
+    let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+
+    let rom = assemble_file(&source).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    if let Err(e) = fs::write(&output, &rom) {
+        eprintln!("Failed to write {}: {}", output.display(), e);
+        process::exit(1);
+    }
+    println!("{}: {} bytes", output.display(), rom.len());
+}
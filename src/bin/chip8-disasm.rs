@@ -0,0 +1,63 @@
+// Prints labeled listings of chip8 roms, telling code and data apart by following the program
+// from its entry point. With no roms given it lists everything in data/.
+use rust_chip8::disasm::Disassembly;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+fn usage() -> ! {
+    eprintln!("usage: chip8-disasm [--octo] [ROM...]");
+    process::exit(1);
+}
+
+fn main() {
+    let mut octo = false;
+    let mut roms = Vec::new();
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--octo" => octo = true,
+            "-h" | "--help" => usage(),
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option '{}'", arg);
+                usage()
+            }
+            _ => roms.push(PathBuf::from(arg)),
+        }
+    }
+
+    if roms.is_empty() {
+        let entries = fs::read_dir("data").unwrap_or_else(|e| {
+            eprintln!("Failed to read data/: {}", e);
+            process::exit(1);
+        });
+        roms = entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect();
+        roms.sort();
+    }
+
+    let comment = if octo { "#" } else { ";" };
+    let mut failed = false;
+    for (i, path) in roms.iter().enumerate() {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                failed = true;
+                continue;
+            }
+        };
+        if i > 0 {
+            println!();
+        }
+        println!("{} {}, {} bytes", comment, path.display(), data.len());
+        let disassembly = Disassembly::new(&data);
+        if octo {
+            print!("{:#}", disassembly);
+        } else {
+            print!("{}", disassembly);
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
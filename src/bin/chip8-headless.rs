@@ -0,0 +1,236 @@
+// Runs a rom as fast as it goes without opening a window, playing back scripted key presses, then
+// saves the screen. Exits with an error status if the rom crashed, after saving whatever it had
+// drawn. Doesn't need the frontend feature, so it builds anywhere the emulator core does.
+use rust_chip8::audio::{AudioBackend, WavSink, DEFAULT_SAMPLE_RATE};
+use rust_chip8::chip8::DEFAULT_INSTRUCTIONS_PER_FRAME;
+use rust_chip8::headless::{self, InputScript, RunLength, DEFAULT_HEADLESS_FRAMES};
+use rust_chip8::image::{self, ImageFormat};
+use rust_chip8::octo::{self, Target};
+use rust_chip8::{Chip8, Palette, Preset};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+struct Options {
+    rom: Option<String>,
+    // None uses the preset the rom is known to need, or modern for roms that aren't known
+    quirks: Option<Preset>,
+    instructions_per_frame: u32,
+    seed: Option<u64>,
+    palette: Palette,
+    length: RunLength,
+    input: Option<String>,
+    output: Option<String>,
+    dump_every: Option<u64>,
+    // Where to record the beeper, as a WAV file
+    wav: Option<String>,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: chip8-headless [--frames N | --instructions N] [--input SCRIPT] [--output IMAGE] [--dump-every N] [--wav FILE]");
+    eprintln!("                      [--quirks vip|chip48|schip|xochip|modern] [--ipf N] [--seed N]");
+    eprintln!("                      [--palette THEME|#RRGGBB,... | --palette-file FILE] ROM | SOURCE.8o");
+    process::exit(1);
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        rom: None,
+        quirks: None,
+        instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+        seed: None,
+        palette: Palette::default(),
+        length: RunLength::Frames(DEFAULT_HEADLESS_FRAMES),
+        input: None,
+        output: None,
+        dump_every: None,
+        wav: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.quirks = Some(value.parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    usage()
+                }));
+            }
+            "--ipf" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.instructions_per_frame = value.parse().unwrap_or_else(|_| {
+                    eprintln!("--ipf expects a number of instructions, got '{}'", value);
+                    usage()
+                });
+            }
+            "--seed" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.seed = Some(value.parse().unwrap_or_else(|_| {
+                    eprintln!("--seed expects a number, got '{}'", value);
+                    usage()
+                }));
+            }
+            "--palette" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.palette = value.parse().unwrap_or_else(|e| {
+                    eprintln!("--palette: {}", e);
+                    usage()
+                });
+            }
+            "--palette-file" => {
+                let path = args.next().unwrap_or_else(|| usage());
+                let text = fs::read_to_string(&path).unwrap_or_else(|e| {
+                    eprintln!("Failed to read {}: {}", path, e);
+                    process::exit(1);
+                });
+                options.palette = Palette::parse_config(&text).unwrap_or_else(|e| {
+                    eprintln!("{}: {}", path, e);
+                    process::exit(1);
+                });
+            }
+            "--frames" | "--instructions" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                let count = value.parse().unwrap_or_else(|_| {
+                    eprintln!("{} expects a number, got '{}'", arg, value);
+                    usage()
+                });
+                options.length = if arg == "--frames" { RunLength::Frames(count) } else { RunLength::Instructions(count) };
+            }
+            "--input" => options.input = Some(args.next().unwrap_or_else(|| usage())),
+            "--output" => options.output = Some(args.next().unwrap_or_else(|| usage())),
+            "--wav" => options.wav = Some(args.next().unwrap_or_else(|| usage())),
+            "--dump-every" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.dump_every = match value.parse() {
+                    Ok(0) | Err(_) => {
+                        eprintln!("--dump-every expects a number of frames, got '{}'", value);
+                        usage()
+                    }
+                    Ok(frames) => Some(frames),
+                };
+            }
+            "-h" | "--help" => usage(),
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option '{}'", arg);
+                usage()
+            }
+            _ => options.rom = Some(arg),
+        }
+    }
+    options
+}
+
+fn write_image(path: &Path, chip8: &Chip8, palette: &Palette) -> io::Result<()> {
+    let format = ImageFormat::from_path(path).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "expected a .png or .pbm file name")
+    })?;
+    let image = image::encode_display(chip8.get_display_buffer(), chip8.display_width(), chip8.display_height(), format, palette);
+    fs::write(path, image)
+}
+
+fn main() {
+    let options = parse_args();
+    let rom = options.rom.clone().unwrap_or_else(|| usage());
+    // Octo source is compiled for whichever interpreter the quirks preset stands for
+    let data = if rom.ends_with(".8o") {
+        octo::compile_file(&rom, Target::for_preset(options.quirks.unwrap_or(Preset::Modern))).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        })
+    } else {
+        fs::read(&rom).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", rom, e);
+            process::exit(1);
+        })
+    };
+
+    let mut chip8 = match options.seed {
+        Some(seed) => Chip8::with_seed(seed),
+        None => Chip8::new(),
+    };
+    // Printed so a run can be replayed with --seed
+    if let Some(seed) = chip8.seed() {
+        eprintln!("Random seed: {}", seed);
+    }
+    let quirks = options.quirks.or_else(|| Preset::for_rom(&data)).unwrap_or(Preset::Modern);
+    if options.quirks.is_none() && quirks != Preset::Modern {
+        eprintln!("Using {} quirks for {}", quirks, rom);
+    }
+    chip8.set_quirks(quirks.quirks());
+    chip8.set_instructions_per_frame(options.instructions_per_frame);
+    chip8.load_rom(&data).unwrap_or_else(|e| {
+        eprintln!("Failed to load {}: {}", rom, e);
+        process::exit(1);
+    });
+
+    let script = match &options.input {
+        Some(path) => {
+            let text = fs::read_to_string(path).unwrap_or_else(|e| {
+                eprintln!("Failed to read {}: {}", path, e);
+                process::exit(1);
+            });
+            InputScript::parse(&text).unwrap_or_else(|e| {
+                eprintln!("{}: {}", path, e);
+                process::exit(1);
+            })
+        }
+        None => InputScript::new(),
+    };
+    let output = PathBuf::from(options.output.clone().unwrap_or_else(|| format!("{}.png", rom)));
+    if ImageFormat::from_path(&output).is_none() {
+        eprintln!("--output expects a .png or .pbm file name, got '{}'", output.display());
+        process::exit(1);
+    }
+
+    let mut wav = options.wav.as_ref().map(|path| {
+        WavSink::create(path, DEFAULT_SAMPLE_RATE).unwrap_or_else(|e| {
+            eprintln!("Failed to create {}: {}", path, e);
+            process::exit(1);
+        })
+    });
+
+    let palette = &options.palette;
+    let mut failed = false;
+    let result = headless::run(&mut chip8, options.length, &script, |chip8, frame| {
+        if let Some(sink) = wav.as_mut() {
+            if let Err(e) = sink.update(chip8.is_beeping(), chip8.audio_pattern().as_ref()) {
+                eprintln!("Failed to write audio: {}", e);
+                failed = true;
+                wav = None;
+            }
+        }
+        match options.dump_every {
+            Some(every) if frame % every == 0 => {
+                let path = headless::frame_path(&output, frame);
+                if let Err(e) = write_image(&path, chip8, palette) {
+                    eprintln!("Failed to write {}: {}", path.display(), e);
+                    failed = true;
+                }
+            }
+            _ => {}
+        }
+    });
+    match result {
+        Ok(summary) => {
+            let exited = if summary.exited { ", exited" } else { "" };
+            println!("Ran {} frames, {} instructions{}", summary.frames, summary.instructions, exited);
+        }
+        Err(e) => {
+            eprintln!("Emulation halted: {}", e);
+            failed = true;
+        }
+    }
+    if let Err(e) = write_image(&output, &chip8, palette) {
+        eprintln!("Failed to write {}: {}", output.display(), e);
+        failed = true;
+    }
+    if let Some(sink) = wav {
+        if let Err(e) = sink.finish() {
+            eprintln!("Failed to write audio: {}", e);
+            failed = true;
+        }
+    }
+    process::exit(if failed { 1 } else { 0 });
+}
@@ -1,8 +1,10 @@
+use crate::audio::AudioPattern;
 use crate::display::Display;
+use crate::error::{ExecError, StateError};
 use crate::keyboard::Keyboard;
 use crate::ram::Ram;
+use crate::state::{StateReader, StateWriter};
 use std::fmt;
-use std::time;
 use std::fmt::Formatter;
 
 pub struct Bus {
@@ -10,8 +12,11 @@ pub struct Bus {
     keyboard: Keyboard,
     display: Display,
     delay_timer: u8,
-    delay_timer_set_time: time::Instant,
-
+    sound_timer: u8,
+    // None until an XO-CHIP rom loads a pattern with F002, the beeper plays a plain tone till then
+    audio_buffer: Option<[u8; 16]>,
+    audio_pitch: u8,
+    vblank: bool,
 }
 
 impl Bus {
@@ -21,33 +26,96 @@ impl Bus {
             keyboard: Keyboard::new(),
             display: Display::new(),
             delay_timer: 0,
-            delay_timer_set_time: time::Instant::now(),
+            sound_timer: 0,
+            audio_buffer: None,
+            audio_pitch: AudioPattern::default().pitch,
+            vblank: false,
         }
     }
 
 
-    pub fn ram_read_byte(&self, address: u16) -> u8 {
+    pub fn ram_size(&self) -> usize {
+        self.ram.size()
+    }
+
+    pub fn set_ram_size(&mut self, size: usize) {
+        self.ram.resize(size)
+    }
+
+    pub fn ram_read_byte(&self, address: u16) -> Result<u8, ExecError> {
         self.ram.read_byte(address)
     }
 
-    pub fn ram_write_byte(&mut self, address: u16, value: u8) {
+    pub fn ram_write_byte(&mut self, address: u16, value: u8) -> Result<(), ExecError> {
         self.ram.write_byte(address, value)
     }
 
-    pub fn debug_draw_byte(&mut self, byte: u8, x: u8, y: u8) -> bool {
-        self.display.debug_draw_byte(byte, x, y)
+    pub fn draw_sprite(&mut self, sprite: &[u8], x: usize, y: usize, wide: bool, plane: u8, clip: bool) -> bool {
+        self.display.draw_sprite(sprite, x, y, wide, plane, clip)
+    }
+
+    pub fn clear_screen(&mut self, planes: u8) {
+        self.display.clear_planes(planes)
+    }
+
+    pub fn set_hires(&mut self, hires: bool) {
+        self.display.set_hires(hires)
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.display.is_hires()
+    }
+
+    pub fn display_width(&self) -> usize {
+        self.display.width()
+    }
+
+    pub fn display_height(&self) -> usize {
+        self.display.height()
     }
 
-    pub fn clear_screen(&mut self) {
-        self.display.clear()
+    pub fn is_display_dirty(&self) -> bool {
+        self.display.is_dirty()
+    }
+
+    pub fn clear_display_dirty(&mut self) {
+        self.display.clear_dirty()
+    }
+
+    pub fn scroll_down(&mut self, n: usize, planes: u8) {
+        self.display.scroll_down(n, planes)
+    }
+
+    pub fn scroll_up(&mut self, n: usize, planes: u8) {
+        self.display.scroll_up(n, planes)
+    }
+
+    pub fn scroll_right(&mut self, n: usize, planes: u8) {
+        self.display.scroll_right(n, planes)
+    }
+
+    pub fn scroll_left(&mut self, n: usize, planes: u8) {
+        self.display.scroll_left(n, planes)
     }
 
     pub fn is_key_pressed(&self, key_code: u8) -> bool {
         self.keyboard.is_key_pressed(key_code)
     }
 
-    pub fn set_key_pressed(&mut self, key: Option<u8>) {
-        self.keyboard.set_key_pressed(key);
+    pub fn key_down(&mut self, key_code: u8) {
+        self.keyboard.key_down(key_code);
+    }
+
+    pub fn key_up(&mut self, key_code: u8) {
+        self.keyboard.key_up(key_code);
+    }
+
+    pub fn get_pressed_keys(&self) -> u16 {
+        self.keyboard.get_pressed_keys()
+    }
+
+    pub fn set_pressed_keys(&mut self, pressed: u16) {
+        self.keyboard.set_pressed_keys(pressed);
     }
 
     pub fn get_key_pressed(&self) -> Option<u8> {
@@ -55,19 +123,80 @@ impl Bus {
     }
 
     pub fn set_delay_timer(&mut self, value: u8) {
-        self.delay_timer_set_time = time::Instant::now();
         self.delay_timer = value;
     }
 
     pub fn get_delay_timer(&self) -> u8 {
-        let diff = time::Instant::now() - self.delay_timer_set_time;
-        let ms = diff.get_millis();
-        let ticks = ms / 16;
-        if ticks >= self.delay_timer as u64 {
-            0
-        } else {
-            self.delay_timer - ticks as u8
-        }
+        self.delay_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    pub fn get_sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_audio_pattern(&mut self, buffer: [u8; 16]) {
+        self.audio_buffer = Some(buffer);
+    }
+
+    pub fn set_audio_pitch(&mut self, pitch: u8) {
+        self.audio_pitch = pitch;
+    }
+
+    pub fn get_audio_pattern(&self) -> Option<AudioPattern> {
+        let pitch = self.audio_pitch;
+        self.audio_buffer.map(|buffer| AudioPattern { buffer, pitch })
+    }
+
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        self.ram.save_state(w);
+        self.keyboard.save_state(w);
+        self.display.save_state(w);
+        w.write_u8(self.delay_timer);
+        w.write_u8(self.sound_timer);
+        w.write_bool(self.audio_buffer.is_some());
+        w.write_bytes(&self.audio_buffer.unwrap_or_default());
+        w.write_u8(self.audio_pitch);
+        w.write_bool(self.vblank);
+    }
+
+    pub(crate) fn load_state(r: &mut StateReader) -> Result<Bus, StateError> {
+        let ram = Ram::load_state(r)?;
+        let keyboard = Keyboard::load_state(r)?;
+        let display = Display::load_state(r)?;
+        let delay_timer = r.read_u8()?;
+        let sound_timer = r.read_u8()?;
+        let has_audio_buffer = r.read_bool()?;
+        let mut buffer = [0; 16];
+        buffer.copy_from_slice(r.read_bytes(16)?);
+        Ok(Bus {
+            ram,
+            keyboard,
+            display,
+            delay_timer,
+            sound_timer,
+            audio_buffer: if has_audio_buffer { Some(buffer) } else { None },
+            audio_pitch: r.read_u8()?,
+            vblank: r.read_bool()?,
+        })
+    }
+
+    // Called once per 60Hz frame. Both timers count down by one until they reach 0, and the
+    // frame ending counts as a vblank.
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.vblank = true;
+    }
+
+    // Returns whether a vblank happened since the last call, and clears it
+    pub fn take_vblank(&mut self) -> bool {
+        let vblank = self.vblank;
+        self.vblank = false;
+        vblank
     }
 
     pub fn get_display_buffer(&self) -> &[u8] {
@@ -75,20 +204,14 @@ impl Bus {
     }
 }
 
-impl fmt::Debug for Bus {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, " delay timer: {:?}", self.delay_timer)
+impl Default for Bus {
+    fn default() -> Bus {
+        Bus::new()
     }
 }
 
-trait Milliseconds {
-    fn get_millis(&self) -> u64;
-}
-
-impl Milliseconds for time::Duration {
-    fn get_millis(&self) -> u64 {
-        let nanos = self.subsec_nanos() as u64;
-        let ms = (1000*1000*1000 * self.as_secs() + nanos)/(1000 * 1000);
-        ms
+impl fmt::Debug for Bus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, " delay timer: {:?} sound timer: {:?}", self.delay_timer, self.sound_timer)
     }
-}
\ No newline at end of file
+}
@@ -1,37 +1,296 @@
-use crate::cpu::Cpu;
-use crate::cpu;
+use crate::audio::AudioPattern;
+use crate::cpu::{Cpu, StepOutcome, PROGRAM_START};
 use crate::bus::Bus;
+use crate::error::{ExecError, StateError};
+use crate::quirks::Quirks;
+use crate::rng::{RandomSource, SeededRng};
+use crate::screenshot::{self, ScreenshotOptions};
+use crate::state::{self, StateReader, StateWriter};
+use std::io;
+use std::path::PathBuf;
+
+// How many instructions run between two timer ticks. At 60 frames a second this gives roughly
+// the speed of the original interpreter.
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
 
 pub struct Chip8 {
     bus: Bus,
     cpu: Cpu,
+    instructions_per_frame: u32,
+    // None once a random source other than the seeded one is in use
+    seed: Option<u64>,
+    // crc32 of the loaded rom, so save states can't be loaded into the wrong game
+    rom_hash: u32,
 }
 
 impl Chip8 {
+    // Starts with a random seed, use seed() to find out which one so the run can be replayed
     pub fn new() -> Chip8 {
-        Chip8 {
+        Chip8::with_seed(SeededRng::random_seed())
+    }
+
+    pub fn with_seed(seed: u64) -> Chip8 {
+        let mut chip8 = Chip8 {
             bus: Bus::new(),
             cpu: Cpu::new(),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            seed: Some(seed),
+            rom_hash: state::crc32(&[]),
+        };
+        chip8.set_seed(seed);
+        chip8
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.set_quirks(quirks);
+        chip8
+    }
+
+    // Also resizes ram to the size the quirks call for
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.bus.set_ram_size(quirks.ram_size());
+        self.cpu.set_quirks(quirks);
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.cpu.quirks()
+    }
+
+    // Restarts the random number generator used by CXNN from the given seed
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+        self.cpu.set_random_source(Box::new(SeededRng::new(seed)));
+    }
+
+    // The seed CXNN's numbers come from, or None after set_random_source
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    // Replaces the seeded generator with any other source of random bytes
+    pub fn set_random_source(&mut self, rng: Box<dyn RandomSource>) {
+        self.seed = None;
+        self.cpu.set_random_source(rng);
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame;
+    }
+
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    // Copies the rom into memory starting at PROGRAM_START, where the cpu begins executing.
+    // Fails if the rom doesn't fit in ram.
+    pub fn load_rom(&mut self, data: &[u8]) -> Result<(), ExecError> {
+        self.rom_hash = state::crc32(data);
+        for (i, byte) in data.iter().enumerate() {
+            let address = PROGRAM_START as usize + i;
+            if address > u16::MAX as usize {
+                return Err(ExecError::MemoryOutOfBounds { addr: u16::MAX });
+            }
+            self.bus.ram_write_byte(address as u16, *byte)?;
         }
+        Ok(())
+    }
+
+    // Snapshots the whole machine: cpu, memory, display, keypad, timers and the random number
+    // generator. The state can only be loaded back while the same rom is loaded.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.write_u32(self.instructions_per_frame);
+        w.write_bool(self.seed.is_some());
+        w.write_u64(self.seed.unwrap_or(0));
+        self.bus.save_state(&mut w);
+        self.cpu.save_state(&mut w);
+        state::encode(self.rom_hash, &w.into_inner())
     }
 
-    pub fn load_rom(&mut self, data: &Vec<u8>) {
-        for i in 0..data.len() {
-            self.bus.ram_write_byte((cpu::PROGRAM_START + (i as u16)) as u16, data[i]);
+    // Restores a snapshot from save_state. Nothing changes if the state is rejected.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let payload = state::decode(data, self.rom_hash)?;
+        let mut r = StateReader::new(payload);
+        let instructions_per_frame = r.read_u32()?;
+        let seeded = r.read_bool()?;
+        let seed = r.read_u64()?;
+        let bus = Bus::load_state(&mut r)?;
+        let (mut cpu, rng_state) = Cpu::load_state(&mut r)?;
+        r.finish()?;
+        if bus.ram_size() != cpu.quirks().ram_size() {
+            return Err(StateError::Invalid("ram size"));
+        }
+
+        // Carry on with the current random source, moved to where it was when the state was saved
+        cpu.set_random_source(self.cpu.take_random_source());
+        if let Some(rng_state) = rng_state {
+            cpu.restore_random_state(rng_state);
         }
+        self.instructions_per_frame = instructions_per_frame;
+        self.seed = if seeded { Some(seed) } else { None };
+        self.bus = bus;
+        self.cpu = cpu;
+        Ok(())
     }
 
-    pub fn run_instruction(&mut self) {
-        self.cpu.run_instruction(&mut self.bus);
-        // println!("CPU state: {:?}", self.cpu);
-        // println!("Bus state: {:?}", self.bus)
+    pub fn run_instruction(&mut self) -> Result<StepOutcome, ExecError> {
+        self.cpu.run_instruction(&mut self.bus)
     }
 
+    // Counts the delay and sound timers down by one and ends the frame, which also releases a
+    // draw held back by the display wait quirk. Frontends that step instructions themselves call
+    // this at 60Hz of emulated time.
+    pub fn tick_timers(&mut self) {
+        self.bus.tick_timers()
+    }
+
+    // Runs one 60Hz frame: instructions_per_frame instructions followed by a timer tick. The
+    // frame ends early if the cpu is waiting for the next vblank.
+    pub fn run_frame(&mut self) -> Result<StepOutcome, ExecError> {
+        let mut outcome = StepOutcome::Executed;
+        for _ in 0..self.instructions_per_frame {
+            outcome = self.run_instruction()?;
+            if outcome == StepOutcome::WaitingForVblank || outcome == StepOutcome::Exited {
+                break;
+            }
+        }
+        self.tick_timers();
+        Ok(outcome)
+    }
+
+    // One byte per pixel, row by row. The size depends on the current resolution, see
+    // display_width and display_height. Each pixel is 0 to 3, one bit per XO-CHIP plane.
     pub fn get_display_buffer(&self) -> &[u8]{
         self.bus.get_display_buffer()
     }
 
-    pub fn set_key_pressed(&mut self, key: Option<u8>) {
-        self.bus.set_key_pressed(key)
+    pub fn display_width(&self) -> usize {
+        self.bus.display_width()
+    }
+
+    pub fn display_height(&self) -> usize {
+        self.bus.display_height()
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.bus.is_hires()
+    }
+
+    // Saves the screen as a png at its native resolution, or scaled up by options.scale, into
+    // options.directory with a timestamped name. Returns the path it was written to.
+    pub fn screenshot(&self, options: &ScreenshotOptions) -> io::Result<PathBuf> {
+        screenshot::save(self.get_display_buffer(), self.display_width(), self.display_height(), options)
+    }
+
+    // Whether the screen changed since the last clear_display_dirty. Starts out set, and loading
+    // a state sets it too.
+    pub fn is_display_dirty(&self) -> bool {
+        self.bus.is_display_dirty()
+    }
+
+    pub fn clear_display_dirty(&mut self) {
+        self.bus.clear_display_dirty()
+    }
+
+    // Whether the rom has run the SUPER-CHIP exit instruction
+    pub fn has_exited(&self) -> bool {
+        self.cpu.has_exited()
+    }
+
+    // Frontends report each key of the 16 key keypad going down and coming back up
+    pub fn key_down(&mut self, key_code: u8) {
+        self.bus.key_down(key_code)
     }
-}
\ No newline at end of file
+
+    pub fn key_up(&mut self, key_code: u8) {
+        self.bus.key_up(key_code)
+    }
+
+    pub fn is_key_pressed(&self, key_code: u8) -> bool {
+        self.bus.is_key_pressed(key_code)
+    }
+
+    // Bit n of the mask is set while key n is held down
+    pub fn get_pressed_keys(&self) -> u16 {
+        self.bus.get_pressed_keys()
+    }
+
+    pub fn set_pressed_keys(&mut self, pressed: u16) {
+        self.bus.set_pressed_keys(pressed)
+    }
+
+    pub fn get_delay_timer(&self) -> u8 {
+        self.bus.get_delay_timer()
+    }
+
+    pub fn get_sound_timer(&self) -> u8 {
+        self.bus.get_sound_timer()
+    }
+
+    // The beeper should be sounding whenever the sound timer is running
+    pub fn is_beeping(&self) -> bool {
+        self.get_sound_timer() > 0
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.bus.set_delay_timer(value)
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.bus.set_sound_timer(value)
+    }
+
+    // The cpu registers and memory, for debuggers and tests to look at and poke
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc()
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.cpu.set_pc(pc)
+    }
+
+    pub fn i(&self) -> u16 {
+        self.cpu.i()
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.cpu.set_i(i)
+    }
+
+    pub fn register(&self, index: u8) -> u8 {
+        self.cpu.read_reg_vx(index)
+    }
+
+    pub fn set_register(&mut self, index: u8, value: u8) {
+        self.cpu.write_reg_vx(index, value)
+    }
+
+    pub fn stack(&self) -> &[u16] {
+        self.cpu.stack()
+    }
+
+    // Whether the cpu is halted in an FX0A waiting for a key
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.cpu.key_wait().is_some()
+    }
+
+    pub fn read_memory(&self, address: u16) -> Result<u8, ExecError> {
+        self.bus.ram_read_byte(address)
+    }
+
+    pub fn write_memory(&mut self, address: u16, value: u8) -> Result<(), ExecError> {
+        self.bus.ram_write_byte(address, value)
+    }
+
+    // The XO-CHIP pattern the beeper plays, None for a plain tone
+    pub fn audio_pattern(&self) -> Option<AudioPattern> {
+        self.bus.get_audio_pattern()
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
+    }
+}
@@ -1,10 +1,75 @@
 use crate::bus::Bus;
+use crate::display::PLANE_COUNT;
+use crate::error::{ExecError, StateError};
+use crate::instruction::{decode, Instruction};
+use crate::quirks::{LoadStoreIncrement, Quirks};
+use crate::ram::{BIG_FONT_START, FONT_START};
+use crate::rng::{RandomSource, SeededRng};
+use crate::state::{StateReader, StateWriter};
 use std::fmt;
 use std::fmt::Formatter;
-use rand;
-use rand::distributions::{IndependentSample, Range};
+use std::mem;
 
 pub const PROGRAM_START: u16 = 0x200;
+// Nesting depth of subroutine calls, the same as the original interpreter
+pub const STACK_SIZE: usize = 16;
+
+// What happened when the cpu was asked to run an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    // The instruction ran and the program counter moved on
+    Executed,
+    // FX0A is waiting for a key, no instructions are fetched until one arrives
+    WaitingForKey,
+    // DXYN is waiting for the next vblank because of the display wait quirk
+    WaitingForVblank,
+    // The rom ran the SUPER-CHIP 00FD exit instruction, the cpu won't run anything else
+    Exited,
+}
+
+// Where the pc goes once an instruction has been carried out
+enum Flow {
+    // On to the following instruction
+    Next,
+    // Past the following instruction if the condition held
+    Skip(bool),
+    Jump(u16),
+    // Nowhere, the instruction didn't finish
+    Stay(StepOutcome),
+}
+
+// The state of an FX0A that hasn't finished yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyWait {
+    // The register the key ends up in
+    pub register: u8,
+    // Keys that were already held when the wait started. They don't count until released.
+    pub ignored: u16,
+    // The key that went down, when the wait is for it to come back up
+    pub pressed: Option<u8>,
+}
+
+// Everything a program can see of the cpu apart from the timers and memory. Handy for putting
+// the cpu into a known state and checking what an instruction did to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub pc: u16,
+    pub i: u16,
+    // Return addresses, innermost last. At most STACK_SIZE of them.
+    pub stack: Vec<u16>,
+}
+
+impl Default for Registers {
+    fn default() -> Registers {
+        Registers {
+            v: [0; 16],
+            pc: PROGRAM_START,
+            i: 0,
+            stack: Vec::new(),
+        }
+    }
+}
 
 //#[derive(Debug)]
 pub struct Cpu {
@@ -12,7 +77,14 @@ pub struct Cpu {
     pc: u16,
     i: u16,
     ret_stack: Vec<u16>,
-    rng: rand::ThreadRng,
+    rng: Box<dyn RandomSource>,
+    quirks: Quirks,
+    key_wait: Option<KeyWait>,
+    // The SUPER-CHIP RPL user flags, saved and loaded by FX75 and FX85
+    rpl_flags: [u8; 16],
+    exited: bool,
+    // XO-CHIP bitplanes that drawing, clearing and scrolling apply to, selected by FN01
+    planes: u8,
 }
 
 impl Cpu {
@@ -22,302 +94,438 @@ impl Cpu {
             pc: PROGRAM_START,
             i: 0,
             ret_stack: Vec::<u16>::new(),
-            rng: rand::thread_rng(),
+            rng: Box::new(SeededRng::new(SeededRng::random_seed())),
+            quirks: Quirks::default(),
+            key_wait: None,
+            rpl_flags: [0; 16],
+            exited: false,
+            planes: 1,
+        }
+    }
+
+    pub fn with_registers(registers: Registers) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.set_registers(registers);
+        cpu
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            v: self.vx,
+            pc: self.pc,
+            i: self.i,
+            stack: self.ret_stack.clone(),
+        }
+    }
+
+    // Panics if the stack is deeper than STACK_SIZE, which no program could have got it to
+    pub fn set_registers(&mut self, registers: Registers) {
+        assert!(registers.stack.len() <= STACK_SIZE, "the stack holds at most {} addresses", STACK_SIZE);
+        self.vx = registers.v;
+        self.pc = registers.pc;
+        self.i = registers.i;
+        self.ret_stack = registers.stack;
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_random_source(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    pub fn take_random_source(&mut self) -> Box<dyn RandomSource> {
+        mem::replace(&mut self.rng, Box::new(SeededRng::new(0)))
+    }
+
+    pub fn restore_random_state(&mut self, state: u64) {
+        self.rng.load_state(state);
+    }
+
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.vx);
+        w.write_u16(self.pc);
+        w.write_u16(self.i);
+        w.write_u8(self.ret_stack.len() as u8);
+        for addr in self.ret_stack.iter() {
+            w.write_u16(*addr);
+        }
+        self.quirks.save_state(w);
+        w.write_bool(self.key_wait.is_some());
+        let key_wait = self.key_wait.unwrap_or(KeyWait { register: 0, ignored: 0, pressed: None });
+        w.write_u8(key_wait.register);
+        w.write_u16(key_wait.ignored);
+        w.write_bool(key_wait.pressed.is_some());
+        w.write_u8(key_wait.pressed.unwrap_or(0));
+        w.write_bytes(&self.rpl_flags);
+        w.write_bool(self.exited);
+        w.write_u8(self.planes);
+        let rng_state = self.rng.save_state();
+        w.write_bool(rng_state.is_some());
+        w.write_u64(rng_state.unwrap_or(0));
+    }
+
+    // Returns the loaded cpu along with where its random number generator was. The cpu comes
+    // back with a placeholder generator, the caller decides which source it carries on with.
+    pub(crate) fn load_state(r: &mut StateReader) -> Result<(Cpu, Option<u64>), StateError> {
+        let mut cpu = Cpu::new();
+        cpu.vx.copy_from_slice(r.read_bytes(16)?);
+        cpu.pc = r.read_u16()?;
+        cpu.i = r.read_u16()?;
+        let depth = r.read_u8()? as usize;
+        if depth > STACK_SIZE {
+            return Err(StateError::Invalid("stack depth"));
+        }
+        for _ in 0..depth {
+            cpu.ret_stack.push(r.read_u16()?);
+        }
+        cpu.quirks = Quirks::load_state(r)?;
+        let waiting = r.read_bool()?;
+        let register = r.read_u8()?;
+        let ignored = r.read_u16()?;
+        let has_pressed = r.read_bool()?;
+        let pressed = r.read_u8()?;
+        if register > 0xF || pressed > 0xF {
+            return Err(StateError::Invalid("key wait"));
+        }
+        if waiting {
+            cpu.key_wait = Some(KeyWait {
+                register,
+                ignored,
+                pressed: if has_pressed { Some(pressed) } else { None },
+            });
+        }
+        cpu.rpl_flags.copy_from_slice(r.read_bytes(16)?);
+        cpu.exited = r.read_bool()?;
+        cpu.planes = r.read_u8()?;
+        if cpu.planes as usize >= 1 << PLANE_COUNT {
+            return Err(StateError::Invalid("plane mask"));
+        }
+        let has_rng_state = r.read_bool()?;
+        let rng_state = r.read_u64()?;
+        Ok((cpu, if has_rng_state { Some(rng_state) } else { None }))
+    }
+
+    // Fetches, decodes and executes the instruction at the pc
+    pub fn run_instruction(&mut self, bus: &mut Bus) -> Result<StepOutcome, ExecError> {
+        if self.exited {
+            return Ok(StepOutcome::Exited);
         }
+        // While FX0A is waiting the cpu is halted, nothing is fetched until the key arrives
+        if let Some(key_wait) = self.key_wait {
+            return Ok(self.poll_key_wait(bus, key_wait));
+        }
+
+        // The whole instruction has to be in ram, which for the XO-CHIP long load is 4 bytes
+        if self.pc as usize + 2 > bus.ram_size() {
+            return Err(ExecError::PcOutOfRange { pc: self.pc });
+        }
+        let opcode = self.read_word(bus, self.pc)?;
+        let instruction = decode(opcode)
+            .map_err(|e| ExecError::UnknownOpcode { pc: self.pc, opcode: e.opcode })?;
+        if self.pc as usize + instruction.size() as usize > bus.ram_size() {
+            return Err(ExecError::PcOutOfRange { pc: self.pc });
+        }
+        self.execute(bus, instruction)
     }
-    // Function that reads a single instruction then increments the program counter by 2
-    // Because each instruction takes 2 bytes, we want to increment the program counter by 2 after each instr
-    pub fn run_instruction(&mut self, bus: &mut Bus) {
-
-        // Read the first byte in the instruction
-        let hi = bus.ram_read_byte(self.pc) as u16;
-        // Read the second byte in the instruction
-        let lo = bus.ram_read_byte(self.pc + 1) as u16;
-        // Create one instr of both bytes
-        let instr: u16 = (hi << 8) | lo;
-
-
-        //println!("Instruction read: {:#X} lo: {:#X} hi: {:#X}", instr, lo, hi);
-
-        let nnn = instr & 0x0FFF;
-        let nn = (instr & 0x0FF) as u8;
-        let n = (instr & 0x00F) as u8;
-        let x = ((instr & 0x0F00) >> 8) as u8;
-        let y = ((instr & 0x00F0) >> 4) as u8;
-        //println!("nnn: {:?}, nn: {:?}, n: {:?}, x: {:?}, y:{:?}", nnn, nn, n, x, y);
-
-        // Error checking to make sure the pc is incremented after each instruction
-
-
-        match (instr & 0xF000) >> 12 {
-            0x0 => {
-                match nn {
-                    0xE0 => {
-                        // Clear screen
-                        bus.clear_screen();
-                        self.pc += 2;
-                    }
-                    0xEE => {
-                        // Return from subroutine
-                        let addr = self.ret_stack.pop().unwrap();
-                        self.pc = addr;
-                    }
-                    _ => panic!("Unrecognized '0x00*' instruction: {:#X}, {:#X}", self.pc, instr)
+
+    // Runs an instruction as if it had been fetched from the pc, then moves the pc on
+    pub fn execute(&mut self, bus: &mut Bus, instruction: Instruction) -> Result<StepOutcome, ExecError> {
+        let next = self.pc.wrapping_add(instruction.size());
+        match self.apply(bus, instruction, next)? {
+            Flow::Next => self.pc = next,
+            Flow::Skip(true) => {
+                // Skipping over the 4 byte XO-CHIP long load means skipping 4 bytes instead of 2
+                let skipped = if self.read_word(bus, next)? == 0xF000 { 4 } else { 2 };
+                self.pc = next.wrapping_add(skipped);
+            }
+            Flow::Skip(false) => self.pc = next,
+            Flow::Jump(address) => self.pc = address,
+            Flow::Stay(outcome) => return Ok(outcome),
+        }
+        if self.key_wait.is_some() {
+            Ok(StepOutcome::WaitingForKey)
+        } else {
+            Ok(StepOutcome::Executed)
+        }
+    }
+
+    // Carries out what the instruction does, apart from moving the pc. next is the address of
+    // the instruction after this one.
+    fn apply(&mut self, bus: &mut Bus, instruction: Instruction, next: u16) -> Result<Flow, ExecError> {
+        match instruction {
+            Instruction::Sys(_) => {
+                // Machine code routines can't be run
+                return Err(ExecError::UnknownOpcode { pc: self.pc, opcode: instruction.encode() });
+            }
+            Instruction::Clear => bus.clear_screen(self.planes),
+            Instruction::Return => {
+                let address = self.ret_stack.pop()
+                    .ok_or(ExecError::StackUnderflow { pc: self.pc })?;
+                return Ok(Flow::Jump(address));
+            }
+            Instruction::ScrollDown(n) => bus.scroll_down(n as usize, self.planes),
+            Instruction::ScrollUp(n) => bus.scroll_up(n as usize, self.planes),
+            // The horizontal scrolls always move 4 pixels
+            Instruction::ScrollRight => bus.scroll_right(4, self.planes),
+            Instruction::ScrollLeft => bus.scroll_left(4, self.planes),
+            Instruction::Exit => {
+                self.exited = true;
+                return Ok(Flow::Stay(StepOutcome::Exited));
+            }
+            Instruction::Lores => bus.set_hires(false),
+            Instruction::Hires => bus.set_hires(true),
+            Instruction::Jump(nnn) => return Ok(Flow::Jump(nnn)),
+            Instruction::Call(nnn) => {
+                if self.ret_stack.len() >= STACK_SIZE {
+                    return Err(ExecError::StackOverflow { pc: self.pc });
                 }
-            },
-            0x1 => {
-                // jmp to nnn
-                self.pc = nnn;
-            },
-            0x2 => {
-                // Call subroutine at nnn
-                self.ret_stack.push(self.pc + 2);
-                self.pc = nnn;
-
-            },
-            0x3 => {
-                // if(Vx == nn)
-                let vx = self.read_reg_vx(x);
-                if vx == nn {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
+                self.ret_stack.push(next);
+                return Ok(Flow::Jump(nnn));
+            }
+            Instruction::SkipIfEqual { x, nn } => return Ok(Flow::Skip(self.read_reg_vx(x) == nn)),
+            Instruction::SkipIfNotEqual { x, nn } => return Ok(Flow::Skip(self.read_reg_vx(x) != nn)),
+            Instruction::SkipIfRegistersEqual { x, y } => {
+                return Ok(Flow::Skip(self.read_reg_vx(x) == self.read_reg_vx(y)));
+            }
+            Instruction::SaveRange { x, y } => {
+                // Store VX to VY in memory starting at I, counting down when X > Y. I is unchanged
+                for (offset, index) in register_range(x, y).enumerate() {
+                    let value = self.read_reg_vx(index);
+                    bus.ram_write_byte(self.i.wrapping_add(offset as u16), value)?;
                 }
-            },
-            0x4 => {
-                // Skip next instr if Vx != nn
+            }
+            Instruction::LoadRange { x, y } => {
+                // Load VX to VY from memory starting at I, counting down when X > Y. I is unchanged
+                for (offset, index) in register_range(x, y).enumerate() {
+                    let value = bus.ram_read_byte(self.i.wrapping_add(offset as u16))?;
+                    self.write_reg_vx(index, value);
+                }
+            }
+            Instruction::Set { x, nn } => self.write_reg_vx(x, nn),
+            Instruction::AddImmediate { x, nn } => {
+                // No change to the carry flag
                 let vx = self.read_reg_vx(x);
-                if vx != nn {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
+                self.write_reg_vx(x, vx.wrapping_add(nn));
+            }
+            Instruction::Copy { x, y } => self.write_reg_vx(x, self.read_reg_vx(y)),
+            Instruction::Or { x, y } => self.logic(x, y, |vx, vy| vx | vy),
+            Instruction::And { x, y } => self.logic(x, y, |vx, vy| vx & vy),
+            Instruction::Xor { x, y } => self.logic(x, y, |vx, vy| vx ^ vy),
+            Instruction::Add { x, y } => {
+                // VF is set to 1 if there is a carry, 0 otherwise
+                let (sum, carry) = self.read_reg_vx(x).overflowing_add(self.read_reg_vx(y));
+                self.write_reg_vx(x, sum);
+                self.write_reg_vx(0xF, carry as u8);
+            }
+            Instruction::Sub { x, y } => {
+                // VX -= VY, VF is set to 0 when there's a borrow and 1 otherwise
+                let (diff, borrow) = self.read_reg_vx(x).overflowing_sub(self.read_reg_vx(y));
+                self.write_reg_vx(x, diff);
+                self.write_reg_vx(0xF, !borrow as u8);
+            }
+            Instruction::ShiftRight { x, y } => {
+                // Shift VY (or VX, depending on quirks) right one into VX. VF is set to the
+                // least significant bit of the source BEFORE the shift
+                let source = self.read_reg_vx(if self.quirks.shift_uses_vy { y } else { x });
+                self.write_reg_vx(x, source >> 1);
+                self.write_reg_vx(0xF, source & 0x1);
+            }
+            Instruction::SubReverse { x, y } => {
+                // VX = VY - VX, VF is set to 0 when there's a borrow and 1 otherwise
+                let (diff, borrow) = self.read_reg_vx(y).overflowing_sub(self.read_reg_vx(x));
+                self.write_reg_vx(x, diff);
+                self.write_reg_vx(0xF, !borrow as u8);
+            }
+            Instruction::ShiftLeft { x, y } => {
+                // Shift VY (or VX, depending on quirks) left one into VX. VF is set to the most
+                // significant bit of the source BEFORE the shift
+                let source = self.read_reg_vx(if self.quirks.shift_uses_vy { y } else { x });
+                self.write_reg_vx(x, source << 1);
+                self.write_reg_vx(0xF, (source & 0x80) >> 7);
+            }
+            Instruction::SkipIfRegistersNotEqual { x, y } => {
+                return Ok(Flow::Skip(self.read_reg_vx(x) != self.read_reg_vx(y)));
+            }
+            Instruction::LoadI(nnn) => self.i = nnn,
+            Instruction::JumpOffset { x, nnn } => {
+                // Jump to nnn + V0, or nnn + VX with the jump quirk
+                let offset_reg = if self.quirks.jump_uses_vx { x } else { 0 };
+                return Ok(Flow::Jump(self.read_reg_vx(offset_reg) as u16 + nnn));
+            }
+            Instruction::Random { x, nn } => {
+                let number = self.rng.next_byte();
+                self.write_reg_vx(x, number & nn);
+            }
+            Instruction::Draw { x, y, n } => {
+                if self.quirks.display_wait && !bus.take_vblank() {
+                    return Ok(Flow::Stay(StepOutcome::WaitingForVblank));
                 }
-            },
-            0x5 => {
-                // Skip next instr if Vx == Vy
                 let vx = self.read_reg_vx(x);
                 let vy = self.read_reg_vx(y);
-                if vx == vy {
-                    self.pc += 4;
+                if n == 0 {
+                    // DXY0 draws a SUPER-CHIP 16x16 sprite, two bytes per row
+                    self.debug_draw_sprite(bus, vx, vy, 16, true)?;
                 } else {
-                    self.pc += 2;
+                    self.debug_draw_sprite(bus, vx, vy, n, false)?;
                 }
-            },
-            0x6 => {
-                // set vx to nn
-                self.write_reg_vx(x, nn);
-                self.pc += 2;
-            },
-            0x7 => {
-                // add nn to vx (no change to carry flag)
-                let vx = self.read_reg_vx(x);
-                self.write_reg_vx(x, vx.wrapping_add(nn));
-                self.pc += 2;
-            },
-            0x8 => {
+            }
+            Instruction::SkipIfKey { x } => return Ok(Flow::Skip(bus.is_key_pressed(self.read_reg_vx(x)))),
+            Instruction::SkipIfNotKey { x } => return Ok(Flow::Skip(!bus.is_key_pressed(self.read_reg_vx(x)))),
+            // The 16 bit address is in the two bytes after the opcode
+            Instruction::LoadILong => self.i = self.read_word(bus, self.pc.wrapping_add(2))?,
+            // X is a mask of planes 1 and 2
+            Instruction::Plane(n) => self.planes = n & ((1 << PLANE_COUNT) - 1),
+            Instruction::Audio => {
+                // Load the 16 byte audio pattern starting at I
+                let mut buffer = [0; 16];
+                for (offset, byte) in buffer.iter_mut().enumerate() {
+                    *byte = bus.ram_read_byte(self.i.wrapping_add(offset as u16))?;
+                }
+                bus.set_audio_pattern(buffer);
+            }
+            Instruction::GetDelay { x } => self.write_reg_vx(x, bus.get_delay_timer()),
+            Instruction::WaitKey { x } => {
+                // Wait for a key press (and release, depending on quirks), then store it in VX.
+                // Keys already held down don't count, they have to be pressed again.
+                self.key_wait = Some(KeyWait {
+                    register: x,
+                    ignored: bus.get_pressed_keys(),
+                    pressed: None,
+                });
+            }
+            Instruction::SetDelay { x } => bus.set_delay_timer(self.read_reg_vx(x)),
+            // The beeper sounds while the sound timer is above 0
+            Instruction::SetSound { x } => bus.set_sound_timer(self.read_reg_vx(x)),
+            Instruction::AddI { x } => self.i = self.i.wrapping_add(self.read_reg_vx(x) as u16),
+            // Each character of the small font is 5 bytes
+            Instruction::Font { x } => self.i = FONT_START + (self.read_reg_vx(x) & 0xF) as u16 * 5,
+            // The big 8x10 characters are 10 bytes each
+            Instruction::BigFont { x } => self.i = BIG_FONT_START + (self.read_reg_vx(x) & 0xF) as u16 * 10,
+            Instruction::Bcd { x } => {
                 let vx = self.read_reg_vx(x);
-                let vy = self.read_reg_vx(y);
+                bus.ram_write_byte(self.i, vx / 100)?;
+                bus.ram_write_byte(self.i.wrapping_add(1), (vx % 100) / 10)?;
+                bus.ram_write_byte(self.i.wrapping_add(2), vx % 10)?;
+            }
+            Instruction::Pitch { x } => bus.set_audio_pitch(self.read_reg_vx(x)),
+            Instruction::Store { x } => {
+                // Stores V0 to VX (including VX) in memory starting at address I
+                for index in 0..=x {
+                    let value = self.read_reg_vx(index);
+                    bus.ram_write_byte(self.i.wrapping_add(index as u16), value)?;
+                }
+                self.increment_i_after_load_store(x);
+            }
+            Instruction::Load { x } => {
+                // Fill V0 to VX with values from memory starting at location I
+                for index in 0..=x {
+                    let value = bus.ram_read_byte(self.i.wrapping_add(index as u16))?;
+                    self.write_reg_vx(index, value);
+                }
+                self.increment_i_after_load_store(x);
+            }
+            Instruction::SaveFlags { x } => {
+                for index in 0..=x {
+                    self.rpl_flags[index as usize] = self.read_reg_vx(index);
+                }
+            }
+            Instruction::LoadFlags { x } => {
+                for index in 0..=x {
+                    self.write_reg_vx(index, self.rpl_flags[index as usize]);
+                }
+            }
+        }
+        Ok(Flow::Next)
+    }
 
-                match n {
-                    0x0 => {
-                        // set VX = VY
-                        self.write_reg_vx(x, vy);
-                    },
-                    0x1 => {
-                        // set VX to VX or VY (bitwise or)
-                        self.write_reg_vx(x, vx | vy);
-                    },
-                    0x2 => {
-                        // set VX to VX and VY (bitwise and)
-                        self.write_reg_vx(x, vx & vy);
-                    },
-                    0x3 => {
-                        // set VX to VX xor VY (bitwise xor)
-                        self.write_reg_vx(x, vx ^ vy);
-                    },
-                    0x4 => {
-                        // add VY to VX, VF is set to 1 if there is a carry, set VF to 0 otherwise
-                        let sum: u16 = vx as u16 + vy as u16;
-                        self.write_reg_vx(x, sum as u8);
-                        if sum > 0xFF {
-                            self.write_reg_vx(0xF, 1);
-                        } else {
-                            self.write_reg_vx(0xF, 0);
-                        }
-                    },
-                    0x5 => {
-                        // subtract VY from VX, VF is set to 0 when theres a borrow, and 1 otherwise
-                        let diff: i8 = vx as i8 - vy as i8;
-                        self.write_reg_vx(x, diff as u8);
-                        if diff < 0 {
-                            self.write_reg_vx(0xF, 1);
-                        } else {
-                            self.write_reg_vx(0xF, 0);
-                        }
-                    },
-                    0x6 => {
-                        // bit shift VY right one and copy that result into VX
-                        // VF is set to the least significant bit in VY BEFORE the shift
-                        self.write_reg_vx(0xF, vx & 0x1);
-                        self.write_reg_vx(x, vy >> 1);
-
-                    },
-                    0x7 => {
-                        // Sets VX to VY minus VX. VF is set to 0 when there's a borrow, and 1 when there is not.
-                        let diff: u8 = vy as u8 - vx as u8;
-                        self.write_reg_vx(x, diff as u8);
-                        if diff < 0 {
-                            self.write_reg_vx(0xF, 1);
-                        } else {
-                            self.write_reg_vx(0xF, 0);
-                        }
-                    },
-                    0xE => {
-                        // Stores the most significant bit of VX in VF and then shifts VX to the left by 1.[b]
-                        self.write_reg_vx(0xF, (vx & 0x80) >> 7);
-                        self.write_reg_vx(x, vx << 1);
-                    },
-
-                    _ => panic!("Unrecognized '0x8XY*' instruction: {:#X}, {:#X}", self.pc, instr)
-                };
-                self.pc += 2;
-            },
-            0x9 => {
-                // Skip the next instr if Vx != Vy
-                let vx = self.read_reg_vx(x);
-                let vy = self.read_reg_vx(y);
-                if vx != vy {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
+    // 8XY1, 8XY2 and 8XY3: VX = op(VX, VY), with VF reset by the quirk
+    fn logic<F: Fn(u8, u8) -> u8>(&mut self, x: u8, y: u8, op: F) {
+        let value = op(self.read_reg_vx(x), self.read_reg_vx(y));
+        self.write_reg_vx(x, value);
+        if self.quirks.vf_reset {
+            self.write_reg_vx(0xF, 0);
+        }
+    }
+
+    fn read_word(&self, bus: &Bus, address: u16) -> Result<u16, ExecError> {
+        let hi = bus.ram_read_byte(address)? as u16;
+        let lo = bus.ram_read_byte(address.wrapping_add(1))? as u16;
+        Ok((hi << 8) | lo)
+    }
+
+    pub fn key_wait(&self) -> Option<KeyWait> {
+        self.key_wait
+    }
+
+    fn poll_key_wait(&mut self, bus: &Bus, mut key_wait: KeyWait) -> StepOutcome {
+        let held = bus.get_pressed_keys();
+        // Once an ignored key is let go it can be used to answer the wait
+        key_wait.ignored &= held;
+
+        let key = match key_wait.pressed {
+            None => {
+                let fresh = held & !key_wait.ignored;
+                if fresh == 0 {
+                    self.key_wait = Some(key_wait);
+                    return StepOutcome::WaitingForKey;
                 }
-            },
-            0xA => {
-                // set i to nnn
-                self.i = nnn;
-                self.pc += 2;
-            },
-            0xB => {
-                // Jump to instr nnn + V0
-                self.pc = self.read_reg_vx(0) as u16 + nnn;
-            },
-            0xC => {
-                // Sets VX to the result of a bitwise and operation on a random number (Typically: 0 to 255) and NN.
-                let interval = Range::new(0,255);
-                let number = interval.ind_sample(&mut self.rng);
-                self.write_reg_vx(x, number & nn);
-                self.pc += 2;
-            },
-            0xD => {
-                // draw sprite at location x, y
-                let vx = self.read_reg_vx(x);
-                let vy = self.read_reg_vx(y);
-                self.debug_draw_sprite(bus, vx, vy,n);
-                self.pc += 2;
-            },
-            0xE => {
-                match nn {
-                    0x9E => {
-                        // skip the next instr if the key stored in is pressed
-                        let key = self.read_reg_vx(x);
-                        if bus.is_key_pressed(key) {
-                            self.pc += 4;
-                        } else {
-                            self.pc += 2;
-                        }
-                    },
-                    0xA1 => {
-                        // skip the next instr if the key stored in isn't pressed
-                        let key = self.read_reg_vx(x);
-                        if !bus.is_key_pressed(key) {
-                            self.pc += 4;
-                        } else {
-                            self.pc += 2;
-                        }
-
-                    },
-                    _ => panic!("Unrecognized '0xEX**' instruction: {:#X}, {:#X}", self.pc, instr)
-                };
-            },
-            0xF => {
-                match nn {
-                    0x07 => {
-                        // Set VX to the value of the delay timer
-                        self.write_reg_vx(x, bus.get_delay_timer());
-                        self.pc += 2;
-                    },
-                    0x0A => {
-                        // Wait for a key press, then store it in VX
-                        if let Some(val) = bus.get_key_pressed() {
-                            self.write_reg_vx(x, val);
-                            self.pc += 2;
-                        }
-                    },
-                    0x15 => {
-                        // set the delay timer to VX
-                        bus.set_delay_timer(self.read_reg_vx(x));
-                        self.pc += 2;
-                    },
-                    0x18 => {
-                        // Sets the sound timer to Vx
-                        //TODO: Sound timer
-                        self.pc += 2;
-                    },
-                    0x29 => {
-                        //i == sprite address for character in Vx
-                        //Multiply by 5 because each sprite has 5 lines, each line
-                        //is 1 byte.
-                        self.i = self.read_reg_vx(x) as u16 * 5;
-                        self.pc += 2;
-                    },
-                    0x33 => {
-                        // Store the binary coded decimal representation of Vx at various places
-                        let vx = self.read_reg_vx(x);
-                        bus.ram_write_byte(self.i, vx / 100);
-                        bus.ram_write_byte(self.i + 1, (vx % 100) / 10);
-                        bus.ram_write_byte(self.i + 2, vx % 10);
-                        self.pc += 2;
-                    },
-                    0x55 => {
-                        // Stores V0 to VX (including VX) in memory starting at address I
-                        for index in 0..x + 1 {
-                            let value = self.read_reg_vx(index);
-                            bus.ram_write_byte(self.i + index as u16, value);
-                        }
-                        self.i += x as u16 + 1;
-                        self.pc += 2;
-                    },
-                    0x65 => {
-                        // Fill V0 to VX with values from memory starting at location I
-                        for index in 0..x+1 {
-                            let value = bus.ram_read_byte(self.i + index as u16);
-                            self.write_reg_vx(index, value);
-                        }
-                        self.i += x as u16 + 1;
-                        self.pc += 2;
-                    },
-                    0x1E => {
-                        // i += Vx
-                        let vx = self.read_reg_vx(x);
-                        self.i += vx as u16;
-                        self.pc += 2;
-                    }
-
-                    _ => panic!("Unrecognized '0xFX**' instruction: {:#X}, {:#X}", self.pc, instr)
+                let key = fresh.trailing_zeros() as u8;
+                if self.quirks.key_wait_release {
+                    key_wait.pressed = Some(key);
+                    self.key_wait = Some(key_wait);
+                    return StepOutcome::WaitingForKey;
                 }
+                key
+            }
+            Some(key) => {
+                if bus.is_key_pressed(key) {
+                    self.key_wait = Some(key_wait);
+                    return StepOutcome::WaitingForKey;
+                }
+                key
+            }
+        };
 
-            },
+        self.write_reg_vx(key_wait.register, key);
+        self.key_wait = None;
+        StepOutcome::Executed
+    }
 
-            _ => panic!("Unrecognized instruction: {:#X}, {:#X}", self.pc, instr)
+    fn increment_i_after_load_store(&mut self, x: u8) {
+        match self.quirks.load_store_increment {
+            LoadStoreIncrement::None => {}
+            LoadStoreIncrement::X => self.i = self.i.wrapping_add(x as u16),
+            LoadStoreIncrement::XPlusOne => self.i = self.i.wrapping_add(x as u16 + 1),
         }
+    }
 
+    pub fn has_exited(&self) -> bool {
+        self.exited
     }
-    pub fn debug_draw_sprite(&mut self, bus: &mut Bus, x: u8, y: u8, height: u8) {
-        // println!("Drawing sprite at ({}, {})", x, y);
+
+    // Draws a sprite of height rows from I, 8 pixels wide or 16 when wide is set. With more than
+    // one XO-CHIP plane selected, the data for each plane follows on from the previous one's.
+    pub fn debug_draw_sprite(&mut self, bus: &mut Bus, x: u8, y: u8, height: u8, wide: bool) -> Result<(), ExecError> {
+        let bytes_per_row = if wide { 2 } else { 1 };
+        let mut sprite = vec![0; height as usize * bytes_per_row];
         let mut should_set_vf = false;
-        for sprite_y in 0..height {
-            let b = bus.ram_read_byte(self.i + sprite_y as u16);
-            if bus.debug_draw_byte(b, x, y + sprite_y) {
+        let mut address = self.i;
+        for plane_index in 0..PLANE_COUNT {
+            let plane = 1 << plane_index;
+            if self.planes & plane == 0 {
+                continue;
+            }
+            for byte in sprite.iter_mut() {
+                *byte = bus.ram_read_byte(address)?;
+                address = address.wrapping_add(1);
+            }
+            if bus.draw_sprite(&sprite, x as usize, y as usize, wide, plane, self.quirks.clip_sprites) {
                 should_set_vf = true;
             }
         }
@@ -326,25 +534,72 @@ impl Cpu {
         } else {
             self.write_reg_vx(0xF, 0);
         }
+        Ok(())
     }
 
     pub fn write_reg_vx(&mut self, index: u8, value: u8) {
         self.vx[index as usize] = value;
     }
 
-    pub fn read_reg_vx(&mut self, index: u8) -> u8 {
+    pub fn read_reg_vx(&self, index: u8) -> u8 {
         self.vx[index as usize]
     }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    // Return addresses of the subroutines currently being run, innermost last
+    pub fn stack(&self) -> &[u16] {
+        &self.ret_stack
+    }
+
+    // The XO-CHIP planes selected by FN01
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    // The SUPER-CHIP user flags written by FX75
+    pub fn rpl_flags(&self) -> &[u8; 16] {
+        &self.rpl_flags
+    }
+}
+
+// The registers touched by 5XY2 and 5XY3, in order from X to Y
+fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = u8>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
 }
 
 impl fmt::Debug for Cpu {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "\n{:#X}\n", self.pc);
-        write!(f, "vx: ");
+        writeln!(f, "\n{:#X}", self.pc)?;
+        write!(f, "vx: ")?;
         for item in self.vx.iter() {
-            write!(f, "{:#X}", *item);
+            write!(f, "{:#X}", *item)?;
         }
-        write!(f, "\n");
-        write!(f, "i: {:#X}\n", self.i)
+        writeln!(f)?;
+        writeln!(f, "i: {:#X}", self.i)
     }
-}
\ No newline at end of file
+}
+
+impl Default for Cpu {
+    fn default() -> Cpu {
+        Cpu::new()
+    }
+}
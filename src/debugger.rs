@@ -0,0 +1,595 @@
+use crate::chip8::Chip8;
+use crate::cpu::StepOutcome;
+use crate::instruction::{decode, Instruction};
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+const HELP: &str = "\
+Commands (numbers are decimal, or hex with a 0x prefix):
+  s, step [N]            run N instructions, 1 by default
+  c, continue            run until a breakpoint, a watch or an error
+  pause                  stop a running program
+  b, break [ADDR]        break when the pc reaches ADDR, lists breakpoints without one
+  bo, breakop PATTERN    break before an opcode matching PATTERN, e.g. D01F or DXYN
+  d, delete [ADDR|PAT]   remove a breakpoint, or all of them
+  w, watch ADDR          break when the byte at ADDR changes
+  unwatch [ADDR]         remove a watch, or all of them
+  r, regs                print the registers, stack, I and timers
+  stack                  print the call stack
+  x ADDR [LEN]           dump LEN bytes of memory, 16 by default
+  dis, u [ADDR] [N]      disassemble N instructions around the pc or from ADDR
+  set REG VALUE          set V0-VF, I, PC, DT or ST
+  poke ADDR BYTE...      write bytes to memory
+  h, help                print this
+  q, quit                exit the emulator
+An empty line repeats the last command.
+";
+
+// Matches opcodes against four hex digits, where any other character (X, Y, N, ?) matches
+// anything, so DXYN breaks on every draw
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodePattern {
+    mask: u16,
+    value: u16,
+}
+
+impl OpcodePattern {
+    pub fn parse(pattern: &str) -> Option<OpcodePattern> {
+        if pattern.chars().count() != 4 {
+            return None;
+        }
+        let mut mask = 0;
+        let mut value = 0;
+        for c in pattern.chars() {
+            mask <<= 4;
+            value <<= 4;
+            if let Some(digit) = c.to_digit(16) {
+                mask |= 0xF;
+                value |= digit as u16;
+            }
+        }
+        Some(OpcodePattern { mask, value })
+    }
+
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl std::fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for shift in [12, 8, 4, 0].iter() {
+            if (self.mask >> shift) & 0xF == 0 {
+                write!(f, "?")?;
+            } else {
+                write!(f, "{:X}", (self.value >> shift) & 0xF)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// A register that set can change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Dt,
+    St,
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Register, String> {
+        let name = s.to_ascii_uppercase();
+        match name.as_str() {
+            "I" => Ok(Register::I),
+            "PC" => Ok(Register::Pc),
+            "DT" => Ok(Register::Dt),
+            "ST" => Ok(Register::St),
+            _ => name.strip_prefix('V')
+                .filter(|digit| digit.len() == 1)
+                .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+                .map(Register::V)
+                .ok_or_else(|| format!("unknown register '{}', expected V0-VF, I, PC, DT or ST", s)),
+        }
+    }
+}
+
+// What delete was given, which can be an address, an opcode pattern or both (0200 is either)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakpointTarget {
+    pub address: Option<u16>,
+    pub pattern: Option<OpcodePattern>,
+    pub text: String,
+}
+
+// A line typed at the debugger prompt, see HELP for what each one does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Continue,
+    Pause,
+    // Lists the breakpoints without an address
+    Break(Option<u16>),
+    BreakOp(OpcodePattern),
+    // Deletes everything without a target
+    Delete(Option<BreakpointTarget>),
+    Watch(u16),
+    Unwatch(Option<u16>),
+    Registers,
+    Stack,
+    Examine { address: u16, len: usize },
+    // Around the pc without an address
+    Disassemble { address: Option<u16>, count: usize },
+    // The value has been checked to fit the register
+    Set(Register, u16),
+    Poke { address: u16, bytes: Vec<u8> },
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, args) = words.split_first().ok_or("no command, try help")?;
+        match *name {
+            "s" | "step" => match args.first() {
+                Some(arg) => Ok(Command::Step(parse_number(arg)?)),
+                None => Ok(Command::Step(1)),
+            },
+            "c" | "continue" => Ok(Command::Continue),
+            "pause" => Ok(Command::Pause),
+            "b" | "break" => match args.first() {
+                Some(arg) => Ok(Command::Break(Some(parse_address(arg)?))),
+                None => Ok(Command::Break(None)),
+            },
+            "bo" | "breakop" => {
+                let arg = args.first().ok_or("breakop needs an opcode pattern, e.g. DXYN")?;
+                let pattern = OpcodePattern::parse(arg).ok_or_else(|| format!("bad opcode pattern '{}'", arg))?;
+                Ok(Command::BreakOp(pattern))
+            }
+            "d" | "delete" => match args.first() {
+                Some(arg) => {
+                    let target = BreakpointTarget {
+                        address: parse_address(arg).ok(),
+                        pattern: OpcodePattern::parse(arg),
+                        text: arg.to_string(),
+                    };
+                    if target.address.is_none() && target.pattern.is_none() {
+                        return Err(format!("'{}' isn't an address or an opcode pattern", arg));
+                    }
+                    Ok(Command::Delete(Some(target)))
+                }
+                None => Ok(Command::Delete(None)),
+            },
+            "w" | "watch" => {
+                let arg = args.first().ok_or("watch needs an address")?;
+                Ok(Command::Watch(parse_address(arg)?))
+            }
+            "unwatch" => match args.first() {
+                Some(arg) => Ok(Command::Unwatch(Some(parse_address(arg)?))),
+                None => Ok(Command::Unwatch(None)),
+            },
+            "r" | "regs" => Ok(Command::Registers),
+            "stack" => Ok(Command::Stack),
+            "x" => {
+                let arg = args.first().ok_or("x needs an address")?;
+                let address = parse_address(arg)?;
+                let len = match args.get(1) {
+                    Some(arg) => parse_number(arg)?,
+                    None => 16,
+                };
+                Ok(Command::Examine { address, len })
+            }
+            "dis" | "u" => {
+                let address = match args.first() {
+                    Some(arg) => Some(parse_address(arg)?),
+                    None => None,
+                };
+                let count = match args.get(1) {
+                    Some(arg) => parse_number(arg)?,
+                    None => 10,
+                };
+                Ok(Command::Disassemble { address, count })
+            }
+            "set" => {
+                if args.len() != 2 {
+                    return Err(String::from("usage: set REG VALUE"));
+                }
+                let register: Register = args[0].parse()?;
+                let value = match register {
+                    Register::I | Register::Pc => parse_address(args[1])?,
+                    Register::V(_) | Register::Dt | Register::St => parse_byte(args[1])? as u16,
+                };
+                Ok(Command::Set(register, value))
+            }
+            "poke" => {
+                let (address, bytes) = args.split_first().ok_or("usage: poke ADDR BYTE...")?;
+                let address = parse_address(address)?;
+                let bytes = bytes.iter().map(|byte| parse_byte(byte)).collect::<Result<_, _>>()?;
+                Ok(Command::Poke { address, bytes })
+            }
+            "h" | "help" => Ok(Command::Help),
+            "q" | "quit" => Ok(Command::Quit),
+            _ => Err(format!("unknown command '{}', try help", name)),
+        }
+    }
+}
+
+struct Watch {
+    address: u16,
+    value: u8,
+}
+
+// A gdb style debugger. The frontend feeds it lines typed by the user with command and, while
+// it isn't paused, lets it run the emulator a frame at a time with run_frame, which stops at
+// breakpoints and watches.
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    opcode_breakpoints: Vec<OpcodePattern>,
+    watches: Vec<Watch>,
+    paused: bool,
+    quit: bool,
+    // Instructions run since the last timer tick, so stepping keeps the timers in time
+    frame_instructions: u32,
+    // Set on resuming, so the breakpoint that stopped the program doesn't stop it again at once
+    skip_breakpoint: bool,
+    last_command: String,
+}
+
+impl Debugger {
+    // Starts paused, so breakpoints can be set before the rom runs
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            opcode_breakpoints: Vec::new(),
+            watches: Vec::new(),
+            paused: true,
+            quit: false,
+            frame_instructions: 0,
+            skip_breakpoint: false,
+            last_command: String::new(),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    // Whether the user asked to exit the emulator
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn add_opcode_breakpoint(&mut self, pattern: OpcodePattern) {
+        if !self.opcode_breakpoints.contains(&pattern) {
+            self.opcode_breakpoints.push(pattern);
+        }
+    }
+
+    pub fn add_watch(&mut self, chip8: &Chip8, address: u16) {
+        self.watches.retain(|watch| watch.address != address);
+        let value = chip8.read_memory(address).unwrap_or(0);
+        self.watches.push(Watch { address, value });
+    }
+
+    // Runs what's left of the current frame, then ticks the timers. Returns a message saying why
+    // if it stopped early, after which the debugger is paused.
+    pub fn run_frame(&mut self, chip8: &mut Chip8) -> Option<String> {
+        while !self.paused {
+            if let Some(message) = self.check_breakpoints(chip8) {
+                self.paused = true;
+                return Some(message);
+            }
+            let (frame_done, message) = self.run_instruction(chip8);
+            if let Some(message) = message {
+                self.paused = true;
+                return Some(message);
+            }
+            if frame_done {
+                return None;
+            }
+        }
+        None
+    }
+
+    // Parses one line typed by the user into a Command, runs it and returns what to print
+    pub fn command(&mut self, chip8: &mut Chip8, line: &str) -> String {
+        let line = line.trim();
+        let line = if line.is_empty() { self.last_command.clone() } else { line.to_string() };
+        self.last_command = line.clone();
+        if line.is_empty() {
+            return String::new();
+        }
+        match line.parse().and_then(|command| self.run_command(chip8, command)) {
+            Ok(output) => output,
+            Err(message) => format!("{}\n", message),
+        }
+    }
+
+    fn run_command(&mut self, chip8: &mut Chip8, command: Command) -> Result<String, String> {
+        match command {
+            Command::Step(count) => {
+                self.paused = true;
+                let mut out = String::new();
+                for _ in 0..count {
+                    let (_, message) = self.run_instruction(chip8);
+                    if let Some(message) = message {
+                        out.push_str(&message);
+                        break;
+                    }
+                }
+                out.push_str(&self.disassemble_around(chip8, chip8.pc(), 0, 1));
+                Ok(out)
+            }
+            Command::Continue => {
+                self.paused = false;
+                self.skip_breakpoint = true;
+                Ok(String::from("Continuing\n"))
+            }
+            Command::Pause => {
+                self.paused = true;
+                Ok(self.disassemble_around(chip8, chip8.pc(), 0, 1))
+            }
+            Command::Break(Some(address)) => {
+                self.add_breakpoint(address);
+                Ok(format!("Breakpoint at {:#05X}\n", address))
+            }
+            Command::Break(None) => Ok(self.list_breakpoints()),
+            Command::BreakOp(pattern) => {
+                self.add_opcode_breakpoint(pattern);
+                Ok(format!("Breakpoint on opcode {}\n", pattern))
+            }
+            Command::Delete(Some(target)) => {
+                if let Some(address) = target.address {
+                    if self.breakpoints.remove(&address) {
+                        return Ok(format!("Deleted breakpoint at {:#05X}\n", address));
+                    }
+                }
+                if let Some(pattern) = target.pattern {
+                    let before = self.opcode_breakpoints.len();
+                    self.opcode_breakpoints.retain(|p| *p != pattern);
+                    if self.opcode_breakpoints.len() != before {
+                        return Ok(format!("Deleted breakpoint on opcode {}\n", pattern));
+                    }
+                }
+                Err(format!("no breakpoint at '{}'", target.text))
+            }
+            Command::Delete(None) => {
+                self.breakpoints.clear();
+                self.opcode_breakpoints.clear();
+                Ok(String::from("Deleted all breakpoints\n"))
+            }
+            Command::Watch(address) => {
+                self.add_watch(chip8, address);
+                Ok(format!("Watching {:#05X}\n", address))
+            }
+            Command::Unwatch(Some(address)) => {
+                self.watches.retain(|watch| watch.address != address);
+                Ok(format!("Stopped watching {:#05X}\n", address))
+            }
+            Command::Unwatch(None) => {
+                self.watches.clear();
+                Ok(String::from("Removed all watches\n"))
+            }
+            Command::Registers => Ok(registers(chip8)),
+            Command::Stack => Ok(stack(chip8)),
+            Command::Examine { address, len } => Ok(dump_memory(chip8, address, len)),
+            Command::Disassemble { address: Some(address), count } => Ok(self.disassemble_around(chip8, address, 0, count)),
+            Command::Disassemble { address: None, count } => Ok(self.disassemble_around(chip8, chip8.pc(), 4, count)),
+            Command::Set(register, value) => {
+                match register {
+                    Register::V(index) => chip8.set_register(index, value as u8),
+                    Register::I => chip8.set_i(value),
+                    Register::Pc => chip8.set_pc(value),
+                    Register::Dt => chip8.set_delay_timer(value as u8),
+                    Register::St => chip8.set_sound_timer(value as u8),
+                }
+                Ok(String::new())
+            }
+            Command::Poke { address, bytes } => {
+                for (offset, byte) in bytes.iter().enumerate() {
+                    chip8.write_memory(address.wrapping_add(offset as u16), *byte).map_err(|e| e.to_string())?;
+                }
+                // Watches only report changes made by the program
+                for watch in self.watches.iter_mut() {
+                    watch.value = chip8.read_memory(watch.address).unwrap_or(watch.value);
+                }
+                Ok(String::new())
+            }
+            Command::Help => Ok(String::from(HELP)),
+            Command::Quit => {
+                self.quit = true;
+                Ok(String::new())
+            }
+        }
+    }
+
+    fn check_breakpoints(&mut self, chip8: &Chip8) -> Option<String> {
+        if self.skip_breakpoint {
+            self.skip_breakpoint = false;
+            return None;
+        }
+        // Don't stop over and over on an FX0A that is waiting for a key
+        if chip8.is_waiting_for_key() {
+            return None;
+        }
+        let pc = chip8.pc();
+        if self.breakpoints.contains(&pc) {
+            return Some(format!("Breakpoint at {:#05X}\n{}", pc, self.disassemble_around(chip8, pc, 0, 1)));
+        }
+        let opcode = read_opcode(chip8, pc);
+        if let Some(pattern) = self.opcode_breakpoints.iter().find(|p| p.matches(opcode)) {
+            return Some(format!("Breakpoint on opcode {}\n{}", pattern, self.disassemble_around(chip8, pc, 0, 1)));
+        }
+        None
+    }
+
+    // Runs one instruction, ticking the timers whenever a frame's worth have run. Returns
+    // whether the frame ended, along with a message if the program should stop.
+    fn run_instruction(&mut self, chip8: &mut Chip8) -> (bool, Option<String>) {
+        let pc = chip8.pc();
+        let outcome = chip8.run_instruction();
+        self.frame_instructions += 1;
+        let frame_done = match outcome {
+            Ok(StepOutcome::WaitingForVblank) | Ok(StepOutcome::Exited) => true,
+            _ => self.frame_instructions >= chip8.instructions_per_frame(),
+        };
+        if frame_done {
+            chip8.tick_timers();
+            self.frame_instructions = 0;
+        }
+        let message = match outcome {
+            Err(e) => Some(format!("Error at {:#05X}: {}\n", pc, e)),
+            Ok(StepOutcome::Exited) => Some(String::from("The program exited\n")),
+            Ok(_) => self.check_watches(chip8, pc),
+        };
+        (frame_done, message)
+    }
+
+    fn check_watches(&mut self, chip8: &Chip8, pc: u16) -> Option<String> {
+        let mut message = String::new();
+        for watch in self.watches.iter_mut() {
+            let value = chip8.read_memory(watch.address).unwrap_or(watch.value);
+            if value != watch.value {
+                message.push_str(&format!(
+                    "Watch {:#05X} changed from {:#04X} to {:#04X} by the instruction at {:#05X}\n",
+                    watch.address, watch.value, value, pc));
+                watch.value = value;
+            }
+        }
+        if message.is_empty() { None } else { Some(message) }
+    }
+
+    fn list_breakpoints(&self) -> String {
+        let mut out = String::new();
+        for address in self.breakpoints.iter() {
+            out.push_str(&format!("Breakpoint at {:#05X}\n", address));
+        }
+        for pattern in self.opcode_breakpoints.iter() {
+            out.push_str(&format!("Breakpoint on opcode {}\n", pattern));
+        }
+        for watch in self.watches.iter() {
+            out.push_str(&format!("Watch {:#05X} = {:#04X}\n", watch.address, watch.value));
+        }
+        if out.is_empty() {
+            out.push_str("No breakpoints or watches\n");
+        }
+        out
+    }
+
+    // Lists count instructions starting before instructions ahead of address, marking the pc
+    // and breakpoints
+    fn disassemble_around(&self, chip8: &Chip8, address: u16, before: u16, count: usize) -> String {
+        let pc = chip8.pc();
+        let mut out = String::new();
+        let mut address = address.wrapping_sub(before * 2);
+        for _ in 0..count {
+            let opcode = read_opcode(chip8, address);
+            let next = read_opcode(chip8, address.wrapping_add(2));
+            let marker = if address == pc { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&address) { '*' } else { ' ' };
+            let (text, size) = match decode(opcode) {
+                Ok(Instruction::LoadILong) => (format!("{} 0x{:04X}", Instruction::LoadILong, next), 4),
+                Ok(instruction) => (instruction.to_string(), instruction.size()),
+                Err(_) => (format!("DW 0x{:04X}", opcode), 2),
+            };
+            out.push_str(&format!("{}{}{:#05X}: {:04X}  {}\n", marker, breakpoint, address, opcode, text));
+            address = address.wrapping_add(size);
+        }
+        out
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+fn read_opcode(chip8: &Chip8, address: u16) -> u16 {
+    let hi = chip8.read_memory(address).unwrap_or(0) as u16;
+    let lo = chip8.read_memory(address.wrapping_add(1)).unwrap_or(0) as u16;
+    (hi << 8) | lo
+}
+
+fn registers(chip8: &Chip8) -> String {
+    let mut out = String::new();
+    for row in 0..2u8 {
+        let values: Vec<String> = (row * 8..row * 8 + 8)
+            .map(|x| format!("V{:X}={:02X}", x, chip8.register(x)))
+            .collect();
+        out.push_str(&values.join(" "));
+        out.push('\n');
+    }
+    out.push_str(&format!(
+        "PC={:04X} I={:04X} DT={:02X} ST={:02X} SP={}\n",
+        chip8.pc(), chip8.i(), chip8.get_delay_timer(), chip8.get_sound_timer(), chip8.stack().len()));
+    out.push_str(&stack(chip8));
+    out
+}
+
+fn stack(chip8: &Chip8) -> String {
+    if chip8.stack().is_empty() {
+        return String::from("Stack is empty\n");
+    }
+    let mut out = String::from("Stack:");
+    for address in chip8.stack().iter().rev() {
+        out.push_str(&format!(" {:04X}", address));
+    }
+    out.push('\n');
+    out
+}
+
+fn dump_memory(chip8: &Chip8, address: u16, len: usize) -> String {
+    // Anything longer would only go round the same memory again
+    let len = len.min(chip8.quirks().ram_size());
+    let mut out = String::new();
+    for row in 0..len.div_ceil(16) {
+        let start = address.wrapping_add((row as u16).wrapping_mul(16));
+        out.push_str(&format!("{:04X}:", start));
+        for offset in 0..(len - row * 16).min(16) {
+            let value = chip8.read_memory(start.wrapping_add(offset as u16)).unwrap_or(0);
+            out.push_str(&format!(" {:02X}", value));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn parse_number(text: &str) -> Result<usize, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("'{}' isn't a number", text))
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    let number = parse_number(text)?;
+    if number > u16::MAX as usize {
+        return Err(format!("{} is past the end of memory", text));
+    }
+    Ok(number as u16)
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    let number = parse_number(text)?;
+    if number > u8::MAX as usize {
+        return Err(format!("{} doesn't fit in a byte", text));
+    }
+    Ok(number as u8)
+}
@@ -0,0 +1,204 @@
+use crate::cpu::PROGRAM_START;
+use crate::instruction::{decode, Instruction};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Formatter;
+
+// What a label marks, which decides its name. When an address is reached several ways the
+// earliest kind in this list wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Subroutine,
+    Code,
+    Data,
+}
+
+// A rom split into code and data by following the flow of the program from its entry point.
+// Anything never reached by a jump, call, skip or fall through is treated as data. Display
+// gives a Cowgod style listing, {:#} gives Octo syntax.
+pub struct Disassembly {
+    rom: Vec<u8>,
+    // Where each instruction starts, decoded
+    instructions: BTreeMap<u16, Instruction>,
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+impl Disassembly {
+    pub fn new(rom: &[u8]) -> Disassembly {
+        let mut disassembly = Disassembly {
+            rom: rom.to_vec(),
+            instructions: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        };
+        disassembly.trace(PROGRAM_START);
+        disassembly
+    }
+
+    // Decoded instructions by address
+    pub fn instructions(&self) -> &BTreeMap<u16, Instruction> {
+        &self.instructions
+    }
+
+    // The label at address. Addresses outside the rom, like the font, don't get one, and neither
+    // do addresses in the middle of an instruction since the listing has nowhere to put them.
+    pub fn label(&self, address: u16) -> Option<String> {
+        if address < PROGRAM_START || address as u32 >= self.end() {
+            return None;
+        }
+        if let Some((start, instruction)) = self.instructions.range(..address).next_back() {
+            if *start as u32 + instruction.size() as u32 > address as u32 {
+                return None;
+            }
+        }
+        self.labels.get(&address).map(|kind| label_name(address, *kind))
+    }
+
+    fn end(&self) -> u32 {
+        PROGRAM_START as u32 + self.rom.len() as u32
+    }
+
+    fn word(&self, address: u16) -> Option<u16> {
+        let offset = (address as usize).checked_sub(PROGRAM_START as usize)?;
+        let hi = *self.rom.get(offset)?;
+        let lo = *self.rom.get(offset + 1)?;
+        Some((hi as u16) << 8 | lo as u16)
+    }
+
+    fn add_label(&mut self, address: u16, kind: LabelKind) {
+        let entry = self.labels.entry(address).or_insert(kind);
+        *entry = (*entry).min(kind);
+    }
+
+    // Follows every path through the program from start. Each path runs until it jumps, returns
+    // or hits something that isn't an instruction, queuing up the other paths it finds.
+    fn trace(&mut self, start: u16) {
+        self.add_label(start, LabelKind::Code);
+        let mut pending = vec![start];
+        while let Some(mut address) = pending.pop() {
+            loop {
+                if self.instructions.contains_key(&address) {
+                    break;
+                }
+                let instruction = match self.word(address).map(decode) {
+                    Some(Ok(instruction)) => instruction,
+                    _ => break,
+                };
+                if address as u32 + instruction.size() as u32 > self.end() {
+                    break;
+                }
+                self.instructions.insert(address, instruction);
+                let next = address.wrapping_add(instruction.size());
+
+                match instruction {
+                    Instruction::Jump(target) => {
+                        self.add_label(target, LabelKind::Code);
+                        pending.push(target);
+                        break;
+                    }
+                    Instruction::Call(target) => {
+                        self.add_label(target, LabelKind::Subroutine);
+                        pending.push(target);
+                    }
+                    // The jump table at the target can't be followed any further without knowing V0
+                    Instruction::JumpOffset { nnn, .. } => {
+                        self.add_label(nnn, LabelKind::Code);
+                        pending.push(nnn);
+                        break;
+                    }
+                    Instruction::LoadI(target) => self.add_label(target, LabelKind::Data),
+                    Instruction::LoadILong => {
+                        if let Some(target) = self.word(address.wrapping_add(2)) {
+                            self.add_label(target, LabelKind::Data);
+                        }
+                    }
+                    Instruction::Return | Instruction::Exit => break,
+                    // 0NNN only ever turns up when data gets run
+                    Instruction::Sys(_) => {
+                        self.instructions.remove(&address);
+                        break;
+                    }
+                    _ if instruction.is_skip() => {
+                        let skipped = match self.word(next).map(decode) {
+                            Some(Ok(skipped)) => skipped.size(),
+                            _ => 2,
+                        };
+                        pending.push(next.wrapping_add(skipped));
+                    }
+                    _ => {}
+                }
+                address = next;
+            }
+        }
+    }
+
+    fn write_listing(&self, f: &mut Formatter, octo: bool) -> fmt::Result {
+        let comment = if octo { "#" } else { ";" };
+        let mut address = PROGRAM_START as u32;
+        while address < self.end() {
+            let at = address as u16;
+            if let Some(label) = self.label(at) {
+                if octo {
+                    writeln!(f, ": {}", label)?;
+                } else {
+                    writeln!(f, "{}:", label)?;
+                }
+            }
+
+            if let Some(instruction) = self.instructions.get(&at) {
+                let mut text = match instruction.target().and_then(|target| self.label(target)) {
+                    Some(label) if octo => format!("{:#}", instruction.with_label(&label)),
+                    Some(label) => format!("{}", instruction.with_label(&label)),
+                    None if octo => format!("{:#}", instruction),
+                    None => format!("{}", instruction),
+                };
+                let mut raw = format!("{:04X}", self.word(at).unwrap_or(0));
+                if *instruction == Instruction::LoadILong {
+                    let long = self.word(at.wrapping_add(2)).unwrap_or(0);
+                    let target = self.label(long).unwrap_or_else(|| format!("0x{:04X}", long));
+                    text = format!("{} {}", text, target);
+                    raw = format!("{} {:04X}", raw, long);
+                }
+                writeln!(f, "    {:<28}{} {:03X}: {}", text, comment, at, raw)?;
+                address += instruction.size() as u32;
+                continue;
+            }
+
+            // Data runs up to the next label or instruction, at most 8 bytes to a line
+            let mut bytes = Vec::new();
+            while address < self.end() && bytes.len() < 8 {
+                let here = address as u16;
+                if !bytes.is_empty() && (self.labels.contains_key(&here) || self.instructions.contains_key(&here)) {
+                    break;
+                }
+                bytes.push(self.rom[address as usize - PROGRAM_START as usize]);
+                address += 1;
+            }
+            let text = if octo {
+                bytes.iter().map(|b| format!("0x{:02X}", b)).collect::<Vec<_>>().join(" ")
+            } else {
+                let values: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+                format!("DB {}", values.join(", "))
+            };
+            writeln!(f, "    {:<28}{} {:03X}", text, comment, at)?;
+        }
+        Ok(())
+    }
+}
+
+fn label_name(address: u16, kind: LabelKind) -> String {
+    if address == PROGRAM_START {
+        return String::from("main");
+    }
+    match kind {
+        LabelKind::Subroutine => format!("sub_{:03X}", address),
+        LabelKind::Code => format!("label_{:03X}", address),
+        LabelKind::Data => format!("data_{:03X}", address),
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let octo = f.alternate();
+        self.write_listing(f, octo)
+    }
+}
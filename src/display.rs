@@ -1,53 +1,238 @@
+use crate::error::StateError;
+use crate::state::{StateReader, StateWriter};
+use std::ops::Range;
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+// Low resolution, the original chip8 screen
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+// High resolution, switched on by the SUPER-CHIP 00FF instruction
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+// XO-CHIP draws on two bitplanes. Each pixel holds one bit per plane, so it is 0 to 3.
+pub const PLANE_COUNT: usize = 2;
+pub const ALL_PLANES: u8 = 0b11;
+
+// One row of one plane. The leftmost pixel is the top bit, so at low resolution only the top 64
+// bits are used.
+type Row = u128;
+const ROW_BITS: usize = Row::BITS as usize;
 
 pub struct Display {
-    screen: [u8; WIDTH * HEIGHT],
+    // Each plane's rows, top to bottom
+    planes: [Vec<Row>; PLANE_COUNT],
+    width: usize,
+    height: usize,
+    // The planes unpacked into a byte per pixel, kept up to date as they change
+    pixels: Vec<u8>,
+    dirty: bool,
 }
 
 impl Display {
     pub fn new() -> Display {
         Display {
-            screen: [0; WIDTH * HEIGHT],
+            planes: [vec![0; HEIGHT], vec![0; HEIGHT]],
+            width: WIDTH,
+            height: HEIGHT,
+            pixels: vec![0; WIDTH * HEIGHT],
+            dirty: true,
         }
     }
 
-    pub fn get_index_from_coords(x: usize, y: usize) -> usize {
-        y * WIDTH + x
+    pub fn width(&self) -> usize {
+        self.width
     }
 
-    pub fn debug_draw_byte(&mut self, byte: u8, x: u8, y: u8) -> bool {
-        let mut erased = false;
-        let mut coord_x = x as usize;
-        let mut coord_y = y as usize;
-        let mut b = byte;
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_WIDTH
+    }
+
+    // Switches between 64x32 and 128x64. The screen is cleared when the resolution changes.
+    pub fn set_hires(&mut self, hires: bool) {
+        let (width, height) = if hires { (HIRES_WIDTH, HIRES_HEIGHT) } else { (WIDTH, HEIGHT) };
+        if width != self.width {
+            self.width = width;
+            self.height = height;
+            for rows in self.planes.iter_mut() {
+                *rows = vec![0; height];
+            }
+            self.pixels = vec![0; width * height];
+            self.changed();
+        }
+    }
 
+    // Whether anything on screen changed since the last clear_dirty, so frontends can skip
+    // redrawing the same thing
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
 
-        for _ in 0..8 {
-            coord_x %= WIDTH;
-            coord_y %= HEIGHT;
-            let index = Display::get_index_from_coords(coord_x, coord_y);
-            let bit =  (b & 0b1000_0000) >> 7;
-            let prev_value = self.screen[index];
-            self.screen[index] ^= bit;
+    fn changed(&mut self) {
+        self.changed_rows(0..self.height);
+    }
 
-            if prev_value == 1 && self.screen[index] == 0 {
-                erased = true;
+    // Unpacks the given rows of the planes into pixels again
+    fn changed_rows(&mut self, rows: Range<usize>) {
+        self.dirty = true;
+        let width = self.width;
+        for y in rows {
+            let line = &mut self.pixels[y * width..(y + 1) * width];
+            for (x, pixel) in line.iter_mut().enumerate() {
+                let bit = ROW_BITS - 1 - x;
+                *pixel = self.planes.iter().enumerate().fold(0, |pixel, (plane_index, rows)| {
+                    pixel | ((rows[y] >> bit) as u8 & 1) << plane_index
+                });
             }
+        }
+    }
+
+    // The bits of a row that are on screen
+    fn row_mask(&self) -> Row {
+        !0 << (ROW_BITS - self.width)
+    }
+
+    // XORs a sprite onto the given plane (1 or 2) with its top left corner at x, y. Each row is 1
+    // byte, or 2 when wide is set for 16 pixel SUPER-CHIP sprites. The sprite starts wherever x, y
+    // wrap to, and the parts past the edges either wrap around too or are dropped when clip is
+    // set. Returns whether any pixel was erased.
+    pub fn draw_sprite(&mut self, sprite: &[u8], x: usize, y: usize, wide: bool, plane: u8, clip: bool) -> bool {
+        let x = x % self.width;
+        let y = y % self.height;
+        let bytes_per_row = if wide { 2 } else { 1 };
+        let sprite_width = bytes_per_row * 8;
+        let mask = self.row_mask();
+        let width = self.width;
+        let height = self.height;
 
-            coord_x += 1;
-            b = b << 1;
+        let mut erased = false;
+        let mut drawn = false;
+        for (plane_index, rows) in self.planes.iter_mut().enumerate() {
+            if plane & (1 << plane_index) == 0 {
+                continue;
+            }
+            for (sprite_y, bytes) in sprite.chunks(bytes_per_row).enumerate() {
+                let row_y = y + sprite_y;
+                if clip && row_y >= height {
+                    break;
+                }
+                let bits = bytes.iter().fold(0, |bits, byte| (bits << 8) | *byte as Row) << (ROW_BITS - sprite_width);
+                let shifted = if width == ROW_BITS {
+                    if clip { bits >> x } else { bits.rotate_right(x as u32) }
+                } else {
+                    // Whatever ends up past the right edge is still in the row, below the mask
+                    let shifted = bits >> x;
+                    if clip { shifted & mask } else { (shifted & mask) | ((shifted & !mask) << width) }
+                };
+                let row = &mut rows[row_y % height];
+                erased |= *row & shifted != 0;
+                drawn |= shifted != 0;
+                *row ^= shifted;
+            }
+        }
+        if drawn {
+            // Only the rows under the sprite changed, which may wrap around to the top
+            let rows = sprite.len().div_ceil(bytes_per_row);
+            let end = if clip { (y + rows).min(height) } else { y + rows };
+            self.changed_rows(y..end.min(height));
+            if end > height {
+                self.changed_rows(0..end - height);
+            }
         }
         erased
     }
 
     pub fn clear(&mut self) {
-        for pixel in self.screen.iter_mut() {
-            *pixel = 0;
+        self.clear_planes(ALL_PLANES)
+    }
+
+    // Clears only the planes set in the mask, the others are left alone
+    pub fn clear_planes(&mut self, planes: u8) {
+        self.each_plane(planes, |rows, _| rows.iter_mut().for_each(|row| *row = 0));
+    }
+
+    // Moves the selected planes down by n rows, blank rows come in at the top
+    pub fn scroll_down(&mut self, n: usize, planes: u8) {
+        self.each_plane(planes, |rows, _| {
+            let n = n.min(rows.len());
+            rows.rotate_right(n);
+            rows[..n].iter_mut().for_each(|row| *row = 0);
+        });
+    }
+
+    // Moves the selected planes up by n rows, blank rows come in at the bottom
+    pub fn scroll_up(&mut self, n: usize, planes: u8) {
+        self.each_plane(planes, |rows, _| {
+            let n = n.min(rows.len());
+            rows.rotate_left(n);
+            let len = rows.len();
+            rows[len - n..].iter_mut().for_each(|row| *row = 0);
+        });
+    }
+
+    // Moves the selected planes right by n pixels, blank pixels come in on the left
+    pub fn scroll_right(&mut self, n: usize, planes: u8) {
+        self.each_plane(planes, |rows, mask| {
+            rows.iter_mut().for_each(|row| *row = row.checked_shr(n as u32).unwrap_or(0) & mask)
+        });
+    }
+
+    // Moves the selected planes left by n pixels, blank pixels come in on the right
+    pub fn scroll_left(&mut self, n: usize, planes: u8) {
+        self.each_plane(planes, |rows, _| rows.iter_mut().for_each(|row| *row = row.checked_shl(n as u32).unwrap_or(0)));
+    }
+
+    // Runs change on the rows of each plane in the mask, along with the row mask
+    fn each_plane<F: FnMut(&mut Vec<Row>, Row)>(&mut self, planes: u8, mut change: F) {
+        let mask = self.row_mask();
+        for (plane_index, rows) in self.planes.iter_mut().enumerate() {
+            if planes & (1 << plane_index) != 0 {
+                change(rows, mask);
+            }
         }
+        self.changed();
     }
+
+    // Saved as whether it is hires, then a byte per pixel row by row with plane 1 in bit 0
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.is_hires());
+        w.write_bytes(self.get_display_buffer());
+    }
+
+    pub(crate) fn load_state(r: &mut StateReader) -> Result<Display, StateError> {
+        let mut display = Display::new();
+        display.set_hires(r.read_bool()?);
+        let width = display.width;
+        let screen = r.read_bytes(width * display.height)?;
+        if screen.iter().any(|pixel| *pixel > ALL_PLANES) {
+            return Err(StateError::Invalid("pixel"));
+        }
+        for (plane_index, rows) in display.planes.iter_mut().enumerate() {
+            for (row, pixels) in rows.iter_mut().zip(screen.chunks(width)) {
+                *row = pixels.iter().enumerate().fold(0, |row, (x, pixel)| {
+                    row | (((*pixel >> plane_index) & 1) as Row) << (ROW_BITS - 1 - x)
+                });
+            }
+        }
+        display.changed();
+        Ok(display)
+    }
+
+    // One byte per pixel, row by row, width() * height() long. Bit 0 of each pixel is plane 1
+    // and bit 1 is plane 2.
     pub fn get_display_buffer(&self) -> &[u8] {
-        &self.screen
+        &self.pixels
+    }
+}
+
+impl Default for Display {
+    fn default() -> Display {
+        Display::new()
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,108 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+
+// Everything that can stop the cpu from executing an instruction. Frontends get one of these
+// back from Chip8::run_instruction instead of the emulator panicking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecError {
+    // The opcode at pc doesn't decode to any known instruction
+    UnknownOpcode { pc: u16, opcode: u16 },
+    // 00EE was executed with nothing on the return stack
+    StackUnderflow { pc: u16 },
+    // 2NNN was executed with the return stack already full
+    StackOverflow { pc: u16 },
+    // A read or write past the end of ram
+    MemoryOutOfBounds { addr: u16 },
+    // The program counter points somewhere an instruction can't be fetched from
+    PcOutOfRange { pc: u16 },
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            ExecError::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {:#06X} at {:#05X}", opcode, pc)
+            }
+            ExecError::StackUnderflow { pc } => {
+                write!(f, "return with an empty stack at {:#05X}", pc)
+            }
+            ExecError::StackOverflow { pc } => {
+                write!(f, "call with a full stack at {:#05X}", pc)
+            }
+            ExecError::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:#06X}", addr)
+            }
+            ExecError::PcOutOfRange { pc } => {
+                write!(f, "program counter out of range: {:#06X}", pc)
+            }
+        }
+    }
+}
+
+impl Error for ExecError {}
+
+// Why a save state couldn't be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    // The data doesn't start with the save state magic number
+    BadMagic,
+    // The state was written by a different version of the format
+    UnsupportedVersion(u16),
+    // The state was saved while a different rom was loaded
+    RomMismatch,
+    // The data has been corrupted
+    ChecksumMismatch,
+    // The data ends before the state does
+    Truncated,
+    // A value in the state is out of range, the str says which
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::RomMismatch => write!(f, "save state is for a different rom"),
+            StateError::ChecksumMismatch => write!(f, "save state checksum mismatch"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(what) => write!(f, "save state has an invalid {}", what),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+// An opcode that doesn't decode to any instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "unknown opcode {:#06X}", self.opcode)
+    }
+}
+
+impl Error for DecodeError {}
+
+// An error in assembly source, with where it was found. Lines and columns count from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+impl Error for AsmError {}
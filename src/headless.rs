@@ -0,0 +1,131 @@
+use crate::chip8::Chip8;
+use crate::cpu::StepOutcome;
+use crate::error::ExecError;
+use crate::keyboard::KEY_COUNT;
+use std::path::{Path, PathBuf};
+
+// Ten seconds, when neither a frame nor an instruction count is given
+pub const DEFAULT_HEADLESS_FRAMES: u64 = 600;
+
+// How long to run for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunLength {
+    Frames(u64),
+    Instructions(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KeyEvent {
+    frame: u64,
+    key: u8,
+    down: bool,
+}
+
+// Key presses to play back, one per line as FRAME down|up KEY with the key in hex. Events
+// happen at the start of their frame, counting from 0. # starts a comment.
+//
+//     0 down 5     # hold 5 from the start
+//     30 up 5
+#[derive(Debug, Clone, Default)]
+pub struct InputScript {
+    // In frame order
+    events: Vec<KeyEvent>,
+}
+
+impl InputScript {
+    pub fn new() -> InputScript {
+        InputScript::default()
+    }
+
+    pub fn parse(text: &str) -> Result<InputScript, String> {
+        let mut events = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (frame, action, key) = match words.as_slice() {
+                [] => continue,
+                [frame, action, key] => (frame, action, key),
+                _ => return Err(format!("line {}: expected FRAME down|up KEY", index + 1)),
+            };
+            let frame = frame.parse().map_err(|_| format!("line {}: bad frame number '{}'", index + 1, frame))?;
+            let down = match *action {
+                "down" => true,
+                "up" => false,
+                _ => return Err(format!("line {}: expected down or up, got '{}'", index + 1, action)),
+            };
+            let key = match u8::from_str_radix(key, 16) {
+                Ok(key) if key < KEY_COUNT => key,
+                _ => return Err(format!("line {}: bad key '{}', expected 0 to F", index + 1, key)),
+            };
+            events.push(KeyEvent { frame, key, down });
+        }
+        // Stable, so events on the same frame keep the order they were written in
+        events.sort_by_key(|event| event.frame);
+        Ok(InputScript { events })
+    }
+
+    fn apply(&self, chip8: &mut Chip8, frame: u64) {
+        let start = self.events.partition_point(|event| event.frame < frame);
+        for event in self.events[start..].iter().take_while(|event| event.frame == frame) {
+            if event.down {
+                chip8.key_down(event.key);
+            } else {
+                chip8.key_up(event.key);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunSummary {
+    pub frames: u64,
+    pub instructions: u64,
+    // Whether the rom stopped itself with the SUPER-CHIP exit instruction
+    pub exited: bool,
+}
+
+// Runs chip8 flat out for length, playing back script. after_frame is called with the frame
+// number after each complete frame. A run by instructions can stop part way through a frame, in
+// which case the last frame isn't passed to after_frame.
+pub fn run<F: FnMut(&Chip8, u64)>(chip8: &mut Chip8, length: RunLength, script: &InputScript, mut after_frame: F)
+    -> Result<RunSummary, ExecError> {
+    let mut summary = RunSummary { frames: 0, instructions: 0, exited: false };
+    loop {
+        if let RunLength::Frames(frames) = length {
+            if summary.frames >= frames {
+                return Ok(summary);
+            }
+        }
+        script.apply(chip8, summary.frames);
+
+        for _ in 0..chip8.instructions_per_frame() {
+            if let RunLength::Instructions(instructions) = length {
+                if summary.instructions >= instructions {
+                    return Ok(summary);
+                }
+            }
+            let outcome = chip8.run_instruction()?;
+            summary.instructions += 1;
+            if outcome == StepOutcome::Exited {
+                summary.exited = true;
+                return Ok(summary);
+            }
+            if outcome == StepOutcome::WaitingForVblank {
+                break;
+            }
+        }
+        chip8.tick_timers();
+        summary.frames += 1;
+        after_frame(chip8, summary.frames);
+    }
+}
+
+// Where the dump of a frame goes, e.g. out.png becomes out.000120.png for frame 120
+pub fn frame_path(output: &Path, frame: u64) -> PathBuf {
+    let stem = output.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().to_string());
+    let name = match output.extension() {
+        Some(extension) => format!("{}.{:06}.{}", stem, frame, extension.to_string_lossy()),
+        None => format!("{}.{:06}", stem, frame),
+    };
+    output.with_file_name(name)
+}
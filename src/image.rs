@@ -0,0 +1,122 @@
+use crate::palette::Palette;
+use crate::state::crc32;
+use std::path::Path;
+
+// The biggest block deflate can store uncompressed
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    // Full color, every plane in its own color
+    Png,
+    // Black and white, lit wherever any plane is set
+    Pbm,
+}
+
+impl ImageFormat {
+    // Picks the format from a file's extension
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ImageFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "pbm" => Some(ImageFormat::Pbm),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Pbm => "pbm",
+        }
+    }
+}
+
+// Encodes a display buffer, one byte per pixel as from Chip8::get_display_buffer, at its native
+// resolution. Pbm has no colors, so palette only matters for png.
+pub fn encode_display(buffer: &[u8], width: usize, height: usize, format: ImageFormat, palette: &Palette) -> Vec<u8> {
+    match format {
+        ImageFormat::Png => {
+            let pixels: Vec<u32> = buffer.iter().map(|pixel| palette.color(*pixel)).collect();
+            encode_png(width, height, &pixels)
+        }
+        ImageFormat::Pbm => {
+            let lit: Vec<bool> = buffer.iter().map(|pixel| *pixel != 0).collect();
+            encode_pbm(width, height, &lit)
+        }
+    }
+}
+
+// An 8 bit RGB png of 0xRRGGBB pixels, row by row. The image data is stored rather than
+// compressed, which keeps this small and chip8 screens are tiny anyway.
+pub fn encode_png(width: usize, height: usize, pixels: &[u32]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height, "pixel count doesn't match the size");
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in pixels.chunks(width.max(1)) {
+        // Each row starts with its filter type, 0 for none
+        raw.push(0);
+        for pixel in row {
+            raw.extend_from_slice(&pixel.to_be_bytes()[1..]);
+        }
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, then the only compression, filter and interlace methods png has
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(if blocks.peek().is_none() { 1 } else { 0 });
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+// A binary (P4) pbm, where set bits are black
+pub fn encode_pbm(width: usize, height: usize, lit: &[bool]) -> Vec<u8> {
+    assert_eq!(lit.len(), width * height, "pixel count doesn't match the size");
+    let mut pbm = format!("P4\n{} {}\n", width, height).into_bytes();
+    for row in lit.chunks(width.max(1)) {
+        for bits in row.chunks(8) {
+            let byte = bits.iter().enumerate().fold(0u8, |byte, (i, on)| byte | (*on as u8) << (7 - i));
+            pbm.push(byte);
+        }
+    }
+    pbm
+}
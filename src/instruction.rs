@@ -0,0 +1,440 @@
+use crate::error::DecodeError;
+use std::fmt;
+use std::fmt::Formatter;
+
+// Every instruction of CHIP-8, SUPER-CHIP 1.1 and XO-CHIP. x and y are register numbers, the
+// rest are the immediate values from the opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    // 0NNN, a machine code routine on the original interpreter. Not supported, but it shows up
+    // in data that gets decoded.
+    Sys(u16),
+    // 00E0
+    Clear,
+    // 00EE
+    Return,
+    // 00CN
+    ScrollDown(u8),
+    // 00DN
+    ScrollUp(u8),
+    // 00FB
+    ScrollRight,
+    // 00FC
+    ScrollLeft,
+    // 00FD
+    Exit,
+    // 00FE
+    Lores,
+    // 00FF
+    Hires,
+    // 1NNN
+    Jump(u16),
+    // 2NNN
+    Call(u16),
+    // 3XNN
+    SkipIfEqual { x: u8, nn: u8 },
+    // 4XNN
+    SkipIfNotEqual { x: u8, nn: u8 },
+    // 5XY0
+    SkipIfRegistersEqual { x: u8, y: u8 },
+    // 5XY2
+    SaveRange { x: u8, y: u8 },
+    // 5XY3
+    LoadRange { x: u8, y: u8 },
+    // 6XNN
+    Set { x: u8, nn: u8 },
+    // 7XNN
+    AddImmediate { x: u8, nn: u8 },
+    // 8XY0
+    Copy { x: u8, y: u8 },
+    // 8XY1
+    Or { x: u8, y: u8 },
+    // 8XY2
+    And { x: u8, y: u8 },
+    // 8XY3
+    Xor { x: u8, y: u8 },
+    // 8XY4
+    Add { x: u8, y: u8 },
+    // 8XY5
+    Sub { x: u8, y: u8 },
+    // 8XY6
+    ShiftRight { x: u8, y: u8 },
+    // 8XY7
+    SubReverse { x: u8, y: u8 },
+    // 8XYE
+    ShiftLeft { x: u8, y: u8 },
+    // 9XY0
+    SkipIfRegistersNotEqual { x: u8, y: u8 },
+    // ANNN
+    LoadI(u16),
+    // BNNN, x is only used with the jump_uses_vx quirk
+    JumpOffset { x: u8, nnn: u16 },
+    // CXNN
+    Random { x: u8, nn: u8 },
+    // DXYN
+    Draw { x: u8, y: u8, n: u8 },
+    // EX9E
+    SkipIfKey { x: u8 },
+    // EXA1
+    SkipIfNotKey { x: u8 },
+    // F000 NNNN, the address is the word following the opcode
+    LoadILong,
+    // FN01
+    Plane(u8),
+    // F002
+    Audio,
+    // FX07
+    GetDelay { x: u8 },
+    // FX0A
+    WaitKey { x: u8 },
+    // FX15
+    SetDelay { x: u8 },
+    // FX18
+    SetSound { x: u8 },
+    // FX1E
+    AddI { x: u8 },
+    // FX29
+    Font { x: u8 },
+    // FX30
+    BigFont { x: u8 },
+    // FX33
+    Bcd { x: u8 },
+    // FX3A
+    Pitch { x: u8 },
+    // FX55
+    Store { x: u8 },
+    // FX65
+    Load { x: u8 },
+    // FX75
+    SaveFlags { x: u8 },
+    // FX85
+    LoadFlags { x: u8 },
+}
+
+pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+    let nnn = opcode & 0x0FFF;
+    let nn = (opcode & 0x00FF) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let unknown = DecodeError { opcode };
+
+    let instruction = match (opcode & 0xF000) >> 12 {
+        0x0 => match nnn {
+            0x0E0 => Instruction::Clear,
+            0x0EE => Instruction::Return,
+            0x0C0..=0x0CF => Instruction::ScrollDown(n),
+            0x0D0..=0x0DF => Instruction::ScrollUp(n),
+            0x0FB => Instruction::ScrollRight,
+            0x0FC => Instruction::ScrollLeft,
+            0x0FD => Instruction::Exit,
+            0x0FE => Instruction::Lores,
+            0x0FF => Instruction::Hires,
+            _ => Instruction::Sys(nnn),
+        },
+        0x1 => Instruction::Jump(nnn),
+        0x2 => Instruction::Call(nnn),
+        0x3 => Instruction::SkipIfEqual { x, nn },
+        0x4 => Instruction::SkipIfNotEqual { x, nn },
+        0x5 => match n {
+            0x0 => Instruction::SkipIfRegistersEqual { x, y },
+            0x2 => Instruction::SaveRange { x, y },
+            0x3 => Instruction::LoadRange { x, y },
+            _ => return Err(unknown),
+        },
+        0x6 => Instruction::Set { x, nn },
+        0x7 => Instruction::AddImmediate { x, nn },
+        0x8 => match n {
+            0x0 => Instruction::Copy { x, y },
+            0x1 => Instruction::Or { x, y },
+            0x2 => Instruction::And { x, y },
+            0x3 => Instruction::Xor { x, y },
+            0x4 => Instruction::Add { x, y },
+            0x5 => Instruction::Sub { x, y },
+            0x6 => Instruction::ShiftRight { x, y },
+            0x7 => Instruction::SubReverse { x, y },
+            0xE => Instruction::ShiftLeft { x, y },
+            _ => return Err(unknown),
+        },
+        0x9 if n == 0 => Instruction::SkipIfRegistersNotEqual { x, y },
+        0xA => Instruction::LoadI(nnn),
+        0xB => Instruction::JumpOffset { x, nnn },
+        0xC => Instruction::Random { x, nn },
+        0xD => Instruction::Draw { x, y, n },
+        0xE => match nn {
+            0x9E => Instruction::SkipIfKey { x },
+            0xA1 => Instruction::SkipIfNotKey { x },
+            _ => return Err(unknown),
+        },
+        0xF => match nn {
+            0x00 if x == 0 => Instruction::LoadILong,
+            0x01 => Instruction::Plane(x),
+            0x02 if x == 0 => Instruction::Audio,
+            0x07 => Instruction::GetDelay { x },
+            0x0A => Instruction::WaitKey { x },
+            0x15 => Instruction::SetDelay { x },
+            0x18 => Instruction::SetSound { x },
+            0x1E => Instruction::AddI { x },
+            0x29 => Instruction::Font { x },
+            0x30 => Instruction::BigFont { x },
+            0x33 => Instruction::Bcd { x },
+            0x3A => Instruction::Pitch { x },
+            0x55 => Instruction::Store { x },
+            0x65 => Instruction::Load { x },
+            0x75 => Instruction::SaveFlags { x },
+            0x85 => Instruction::LoadFlags { x },
+            _ => return Err(unknown),
+        },
+        _ => return Err(unknown),
+    };
+    Ok(instruction)
+}
+
+impl Instruction {
+    // Size in bytes, including the address that follows F000
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LoadILong => 4,
+            _ => 2,
+        }
+    }
+
+    // The opcode, the reverse of decode. Operands are masked to the bits the opcode has room
+    // for. The address that follows LoadILong isn't included.
+    pub fn encode(&self) -> u16 {
+        use self::Instruction::*;
+        let xy = |base: u16, x: u8, y: u8| base | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4;
+        let xnn = |base: u16, x: u8, nn: u8| base | (x as u16 & 0xF) << 8 | nn as u16;
+        let fx = |x: u8, low: u16| 0xF000 | (x as u16 & 0xF) << 8 | low;
+        match *self {
+            Sys(nnn) => nnn & 0x0FFF,
+            Clear => 0x00E0,
+            Return => 0x00EE,
+            ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            Lores => 0x00FE,
+            Hires => 0x00FF,
+            Jump(nnn) => 0x1000 | (nnn & 0x0FFF),
+            Call(nnn) => 0x2000 | (nnn & 0x0FFF),
+            SkipIfEqual { x, nn } => xnn(0x3000, x, nn),
+            SkipIfNotEqual { x, nn } => xnn(0x4000, x, nn),
+            SkipIfRegistersEqual { x, y } => xy(0x5000, x, y),
+            SaveRange { x, y } => xy(0x5002, x, y),
+            LoadRange { x, y } => xy(0x5003, x, y),
+            Set { x, nn } => xnn(0x6000, x, nn),
+            AddImmediate { x, nn } => xnn(0x7000, x, nn),
+            Copy { x, y } => xy(0x8000, x, y),
+            Or { x, y } => xy(0x8001, x, y),
+            And { x, y } => xy(0x8002, x, y),
+            Xor { x, y } => xy(0x8003, x, y),
+            Add { x, y } => xy(0x8004, x, y),
+            Sub { x, y } => xy(0x8005, x, y),
+            ShiftRight { x, y } => xy(0x8006, x, y),
+            SubReverse { x, y } => xy(0x8007, x, y),
+            ShiftLeft { x, y } => xy(0x800E, x, y),
+            SkipIfRegistersNotEqual { x, y } => xy(0x9000, x, y),
+            LoadI(nnn) => 0xA000 | (nnn & 0x0FFF),
+            // The x of BXNN is the top nibble of the address
+            JumpOffset { nnn, .. } => 0xB000 | (nnn & 0x0FFF),
+            Random { x, nn } => xnn(0xC000, x, nn),
+            Draw { x, y, n } => xy(0xD000, x, y) | (n as u16 & 0xF),
+            SkipIfKey { x } => xnn(0xE000, x, 0x9E),
+            SkipIfNotKey { x } => xnn(0xE000, x, 0xA1),
+            LoadILong => 0xF000,
+            Plane(n) => fx(n, 0x01),
+            Audio => 0xF002,
+            GetDelay { x } => fx(x, 0x07),
+            WaitKey { x } => fx(x, 0x0A),
+            SetDelay { x } => fx(x, 0x15),
+            SetSound { x } => fx(x, 0x18),
+            AddI { x } => fx(x, 0x1E),
+            Font { x } => fx(x, 0x29),
+            BigFont { x } => fx(x, 0x30),
+            Bcd { x } => fx(x, 0x33),
+            Pitch { x } => fx(x, 0x3A),
+            Store { x } => fx(x, 0x55),
+            Load { x } => fx(x, 0x65),
+            SaveFlags { x } => fx(x, 0x75),
+            LoadFlags { x } => fx(x, 0x85),
+        }
+    }
+
+    // Whether the instruction skips the one after it when its condition holds
+    pub fn is_skip(&self) -> bool {
+        matches!(self,
+            Instruction::SkipIfEqual { .. }
+            | Instruction::SkipIfNotEqual { .. }
+            | Instruction::SkipIfRegistersEqual { .. }
+            | Instruction::SkipIfRegistersNotEqual { .. }
+            | Instruction::SkipIfKey { .. }
+            | Instruction::SkipIfNotKey { .. })
+    }
+
+    // The address the instruction jumps to, calls or points I at
+    pub fn target(&self) -> Option<u16> {
+        match *self {
+            Instruction::Jump(nnn)
+            | Instruction::Call(nnn)
+            | Instruction::LoadI(nnn)
+            | Instruction::JumpOffset { nnn, .. } => Some(nnn),
+            _ => None,
+        }
+    }
+
+    // Displays the instruction with label in place of its target address
+    pub fn with_label<'a>(&'a self, label: &'a str) -> Labeled<'a> {
+        Labeled { instruction: self, label }
+    }
+
+    // Cowgod's mnemonics, or Octo's syntax when octo is set. target is written out in place of
+    // the address in jumps, calls and I loads.
+    fn write(&self, f: &mut Formatter, octo: bool, target: &dyn fmt::Display) -> fmt::Result {
+        use self::Instruction::*;
+        if octo {
+            return match *self {
+                Sys(nnn) => write!(f, "0x{:02X} 0x{:02X}", nnn >> 8, nnn & 0xFF),
+                Clear => write!(f, "clear"),
+                Return => write!(f, "return"),
+                ScrollDown(n) => write!(f, "scroll-down {}", n),
+                ScrollUp(n) => write!(f, "scroll-up {}", n),
+                ScrollRight => write!(f, "scroll-right"),
+                ScrollLeft => write!(f, "scroll-left"),
+                Exit => write!(f, "exit"),
+                Lores => write!(f, "lores"),
+                Hires => write!(f, "hires"),
+                Jump(_) => write!(f, "jump {}", target),
+                Call(_) => write!(f, ":call {}", target),
+                // Octo describes skips by the condition under which the next instruction runs
+                SkipIfEqual { x, nn } => write!(f, "if v{:x} != 0x{:02X} then", x, nn),
+                SkipIfNotEqual { x, nn } => write!(f, "if v{:x} == 0x{:02X} then", x, nn),
+                SkipIfRegistersEqual { x, y } => write!(f, "if v{:x} != v{:x} then", x, y),
+                SaveRange { x, y } => write!(f, "save v{:x} - v{:x}", x, y),
+                LoadRange { x, y } => write!(f, "load v{:x} - v{:x}", x, y),
+                Set { x, nn } => write!(f, "v{:x} := 0x{:02X}", x, nn),
+                AddImmediate { x, nn } => write!(f, "v{:x} += 0x{:02X}", x, nn),
+                Copy { x, y } => write!(f, "v{:x} := v{:x}", x, y),
+                Or { x, y } => write!(f, "v{:x} |= v{:x}", x, y),
+                And { x, y } => write!(f, "v{:x} &= v{:x}", x, y),
+                Xor { x, y } => write!(f, "v{:x} ^= v{:x}", x, y),
+                Add { x, y } => write!(f, "v{:x} += v{:x}", x, y),
+                Sub { x, y } => write!(f, "v{:x} -= v{:x}", x, y),
+                ShiftRight { x, y } => write!(f, "v{:x} >>= v{:x}", x, y),
+                SubReverse { x, y } => write!(f, "v{:x} =- v{:x}", x, y),
+                ShiftLeft { x, y } => write!(f, "v{:x} <<= v{:x}", x, y),
+                SkipIfRegistersNotEqual { x, y } => write!(f, "if v{:x} == v{:x} then", x, y),
+                LoadI(_) => write!(f, "i := {}", target),
+                JumpOffset { .. } => write!(f, "jump0 {}", target),
+                Random { x, nn } => write!(f, "v{:x} := random 0x{:02X}", x, nn),
+                Draw { x, y, n } => write!(f, "sprite v{:x} v{:x} {}", x, y, n),
+                SkipIfKey { x } => write!(f, "if v{:x} -key then", x),
+                SkipIfNotKey { x } => write!(f, "if v{:x} key then", x),
+                LoadILong => write!(f, "i := long"),
+                Plane(n) => write!(f, "plane {}", n),
+                Audio => write!(f, "audio"),
+                GetDelay { x } => write!(f, "v{:x} := delay", x),
+                WaitKey { x } => write!(f, "v{:x} := key", x),
+                SetDelay { x } => write!(f, "delay := v{:x}", x),
+                SetSound { x } => write!(f, "buzzer := v{:x}", x),
+                AddI { x } => write!(f, "i += v{:x}", x),
+                Font { x } => write!(f, "i := hex v{:x}", x),
+                BigFont { x } => write!(f, "i := bighex v{:x}", x),
+                Bcd { x } => write!(f, "bcd v{:x}", x),
+                Pitch { x } => write!(f, "pitch := v{:x}", x),
+                Store { x } => write!(f, "save v{:x}", x),
+                Load { x } => write!(f, "load v{:x}", x),
+                SaveFlags { x } => write!(f, "saveflags v{:x}", x),
+                LoadFlags { x } => write!(f, "loadflags v{:x}", x),
+            };
+        }
+        match *self {
+            Sys(_) => write!(f, "SYS {}", target),
+            Clear => write!(f, "CLS"),
+            Return => write!(f, "RET"),
+            ScrollDown(n) => write!(f, "SCD {}", n),
+            ScrollUp(n) => write!(f, "SCU {}", n),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Lores => write!(f, "LOW"),
+            Hires => write!(f, "HIGH"),
+            Jump(_) => write!(f, "JP {}", target),
+            Call(_) => write!(f, "CALL {}", target),
+            SkipIfEqual { x, nn } => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            SkipIfNotEqual { x, nn } => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            SkipIfRegistersEqual { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            SaveRange { x, y } => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            LoadRange { x, y } => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            Set { x, nn } => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            AddImmediate { x, nn } => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            Copy { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Add { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubReverse { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SkipIfRegistersNotEqual { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LoadI(_) => write!(f, "LD I, {}", target),
+            JumpOffset { .. } => write!(f, "JP V0, {}", target),
+            Random { x, nn } => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipIfKey { x } => write!(f, "SKP V{:X}", x),
+            SkipIfNotKey { x } => write!(f, "SKNP V{:X}", x),
+            LoadILong => write!(f, "LD I, LONG"),
+            Plane(n) => write!(f, "PLANE {}", n),
+            Audio => write!(f, "AUDIO"),
+            GetDelay { x } => write!(f, "LD V{:X}, DT", x),
+            WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            AddI { x } => write!(f, "ADD I, V{:X}", x),
+            Font { x } => write!(f, "LD F, V{:X}", x),
+            BigFont { x } => write!(f, "LD HF, V{:X}", x),
+            Bcd { x } => write!(f, "LD B, V{:X}", x),
+            Pitch { x } => write!(f, "PITCH V{:X}", x),
+            Store { x } => write!(f, "LD [I], V{:X}", x),
+            Load { x } => write!(f, "LD V{:X}, [I]", x),
+            SaveFlags { x } => write!(f, "LD R, V{:X}", x),
+            LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+// Formats an address the way both syntaxes write them
+struct Address(u16);
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "0x{:03X}", self.0)
+    }
+}
+
+// Cowgod style by default, {:#} gives Octo syntax
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let address = match *self {
+            Instruction::Sys(nnn) => nnn,
+            _ => self.target().unwrap_or(0),
+        };
+        let octo = f.alternate();
+        self.write(f, octo, &Address(address))
+    }
+}
+
+// An instruction shown with a label instead of its target address, see Instruction::with_label
+pub struct Labeled<'a> {
+    instruction: &'a Instruction,
+    label: &'a str,
+}
+
+impl<'a> fmt::Display for Labeled<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let octo = f.alternate();
+        self.instruction.write(f, octo, &self.label)
+    }
+}
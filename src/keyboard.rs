@@ -1,30 +1,66 @@
-use minifb::{Window, KeyRepeat, Key};
+use crate::error::StateError;
+use crate::state::{StateReader, StateWriter};
+
+// The chip8 keypad has 16 keys, 0x0 to 0xF
+pub const KEY_COUNT: u8 = 16;
 
 pub struct Keyboard{
-    key_pressed: Option<u8>,
+    // Bit n is set while key n is held down
+    pressed: u16,
 }
 
 impl Keyboard{
     pub fn new() -> Keyboard {
         Keyboard {
-            key_pressed: None,
+            pressed: 0,
         }
     }
 
+    // Keys outside the keypad are never pressed
     pub fn is_key_pressed(&self, key_code: u8) -> bool {
-        if let Some(key) = self.key_pressed {
-            key == key_code
-        } else {
-            false
+        key_code < KEY_COUNT && self.pressed & (1 << key_code) != 0
+    }
+
+    pub fn key_down(&mut self, key_code: u8) {
+        if key_code < KEY_COUNT {
+            self.pressed |= 1 << key_code;
         }
     }
 
-    pub fn set_key_pressed(&mut self, key: Option<u8>) {
-        self.key_pressed = key
+    pub fn key_up(&mut self, key_code: u8) {
+        if key_code < KEY_COUNT {
+            self.pressed &= !(1 << key_code);
+        }
     }
 
+    pub fn get_pressed_keys(&self) -> u16 {
+        self.pressed
+    }
+
+    pub fn set_pressed_keys(&mut self, pressed: u16) {
+        self.pressed = pressed;
+    }
 
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.pressed);
+    }
+
+    pub(crate) fn load_state(r: &mut StateReader) -> Result<Keyboard, StateError> {
+        Ok(Keyboard { pressed: r.read_u16()? })
+    }
+
+    // The lowest numbered key being held down, if any
     pub fn get_key_pressed(&self) -> Option<u8> {
-        self.key_pressed
+        if self.pressed == 0 {
+            None
+        } else {
+            Some(self.pressed.trailing_zeros() as u8)
+        }
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Keyboard {
+        Keyboard::new()
     }
 }
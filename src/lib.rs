@@ -0,0 +1,35 @@
+// Headless chip8 emulator core. Nothing in here knows about windows or host input, frontends
+// drive a `Chip8` by loading a rom, stepping it and reading back the display buffer.
+extern crate rand;
+
+pub mod assembler;
+pub mod audio;
+pub mod bus;
+pub mod chip8;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod error;
+pub mod headless;
+pub mod image;
+pub mod instruction;
+pub mod keyboard;
+pub mod octo;
+pub mod palette;
+pub mod phosphor;
+pub mod quirks;
+pub mod ram;
+pub mod rewind;
+pub mod rng;
+pub mod scale;
+pub mod screenshot;
+pub mod state;
+
+pub use crate::chip8::Chip8;
+pub use crate::cpu::StepOutcome;
+pub use crate::error::{AsmError, DecodeError, ExecError, StateError};
+pub use crate::instruction::Instruction;
+pub use crate::palette::{Palette, Theme};
+pub use crate::quirks::{Preset, Quirks};
+pub use crate::rng::{RandomSource, SeededRng};
@@ -1,123 +1,517 @@
 extern crate minifb;
-extern crate rand;
 
-use minifb::{Key, Window, WindowOptions, KeyRepeat};
-use std::fs::File;
-use std::io::Read;
-use chip8::Chip8;
-use display::Display;
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+use std::path::PathBuf;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use rust_chip8::{Chip8, Palette, Preset, StepOutcome};
+use rust_chip8::chip8::DEFAULT_INSTRUCTIONS_PER_FRAME;
+use rust_chip8::audio::{AudioBackend, Beeper, NullAudio};
+use rust_chip8::debugger::Debugger;
+use rust_chip8::display;
+use rust_chip8::keyboard::KEY_COUNT;
+use rust_chip8::octo::{self, Target};
+use rust_chip8::phosphor::{Persistence, Phosphor};
+use rust_chip8::rewind::{Rewind, DEFAULT_REWIND_FRAMES};
+use rust_chip8::scale;
+use rust_chip8::screenshot::ScreenshotOptions;
 use std::time::{Duration, Instant};
 use std::env;
+use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
 
-mod ram;
-mod chip8;
-mod cpu;
-mod bus;
-mod display;
-mod keyboard;
+// The window starts big enough for each low resolution chip8 pixel to be a DEFAULT_SCALE x
+// DEFAULT_SCALE square. After that the screen is scaled to whatever size the window is.
+const DEFAULT_SCALE: usize = 10;
+// minifb can't tell how big the monitor is, so fullscreen is a borderless window this big unless
+// --fullscreen-size says otherwise
+const DEFAULT_FULLSCREEN_SIZE: (usize, usize) = (1920, 1080);
+// Around the screen when the window isn't an exact multiple of it
+const LETTERBOX_COLOR: u32 = 0x000000;
+const TITLE: &str = "Rust chip8 emulator";
+// The window is only redrawn when the screen changed, except that minifb only repaints when it's
+// given a buffer, so it's redrawn this often anyway in case it was covered up
+const IDLE_REDRAW_TIME: Duration = Duration::from_millis(250);
+// The emulator runs one frame every 60th of a second
+const FRAME_TIME: Duration = Duration::from_micros(16_667);
+// Held down to step backwards through the rewind history, one frame per frame
+const REWIND_KEY: Key = Key::Backspace;
+const FULLSCREEN_KEY: Key = Key::F11;
+const SCREENSHOT_KEY: Key = Key::F12;
 
-fn get_chip8_keycode_for(key: Option<Key>) -> Option<u8> {
+fn get_chip8_keycode_for(key: Key) -> Option<u8> {
     match key   {
-        Some(Key::Key1) => Some(0x1),
-        Some(Key::Key2) => Some(0x2),
-        Some(Key::Key3) => Some(0x3),
-        Some(Key::Key4) => Some(0xC),
-
-        Some(Key::Q) => Some(0x4),
-        Some(Key::W) => Some(0x5),
-        Some(Key::E) => Some(0x6),
-        Some(Key::R) => Some(0xD),
-
-        Some(Key::A) => Some(0x7),
-        Some(Key::S) => Some(0x8),
-        Some(Key::D) => Some(0x9),
-        Some(Key::F) => Some(0xE),
-
-        Some(Key::Z) => Some(0xA),
-        Some(Key::X) => Some(0x0),
-        Some(Key::C) => Some(0xB),
-        Some(Key::V) => Some(0xF),
+        Key::Key1 => Some(0x1),
+        Key::Key2 => Some(0x2),
+        Key::Key3 => Some(0x3),
+        Key::Key4 => Some(0xC),
+
+        Key::Q => Some(0x4),
+        Key::W => Some(0x5),
+        Key::E => Some(0x6),
+        Key::R => Some(0xD),
+
+        Key::A => Some(0x7),
+        Key::S => Some(0x8),
+        Key::D => Some(0x9),
+        Key::F => Some(0xE),
+
+        Key::Z => Some(0xA),
+        Key::X => Some(0x0),
+        Key::C => Some(0xB),
+        Key::V => Some(0xF),
 
         _ => None
     }
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let file_name = match args.len() {
+// F1 to F9 pick a save state slot
+const SLOT_KEYS: [Key; 9] = [Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9];
+
+fn slot_path(rom: &str, slot: usize) -> String {
+    format!("{}.{}.state", rom, slot)
+}
+
+// Shift+F1..F9 saves to a slot, F1..F9 on their own load from it. Returns whether a state was
+// loaded.
+fn handle_slot_keys(window: &Window, chip8: &mut Chip8, rom: &str) -> bool {
+    let mut loaded = false;
+    let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+    for (i, key) in SLOT_KEYS.iter().enumerate() {
+        if !window.is_key_pressed(*key, KeyRepeat::No) {
+            continue;
+        }
+        let slot = i + 1;
+        let path = slot_path(rom, slot);
+        if shift {
+            match fs::write(&path, chip8.save_state()) {
+                Ok(()) => println!("Saved state to slot {}", slot),
+                Err(e) => eprintln!("Failed to save {}: {}", path, e),
+            }
+        } else {
+            match fs::read(&path) {
+                Ok(data) => match chip8.load_state(&data) {
+                    Ok(()) => {
+                        println!("Loaded state from slot {}", slot);
+                        loaded = true;
+                    }
+                    Err(e) => eprintln!("Failed to load {}: {}", path, e),
+                },
+                Err(e) => eprintln!("Failed to read {}: {}", path, e),
+            }
+        }
+    }
+    loaded
+}
+
+fn open_window(width: usize, height: usize, fullscreen: bool) -> Window {
+    let options = if fullscreen {
+        WindowOptions { borderless: true, title: false, resize: true, topmost: true, ..WindowOptions::default() }
+    } else {
+        WindowOptions { resize: true, ..WindowOptions::default() }
+    };
+    let mut window = Window::new(TITLE, width, height, options).unwrap_or_else(|e| {
+        panic!("Window creation failed: {:?}", e);
+    });
+    if fullscreen {
+        window.set_position(0, 0);
+    }
+    window
+}
+
+// Sends a key_down or key_up to the emulator for every keypad key that changed since last time
+fn update_keys(window: &Window, chip8: &mut Chip8) {
+    let mut pressed: u16 = 0;
+    if let Some(keys) = window.get_keys() {
+        for key in keys {
+            if let Some(code) = get_chip8_keycode_for(key) {
+                pressed |= 1 << code;
+            }
+        }
+    }
+
+    let changed = pressed ^ chip8.get_pressed_keys();
+    for code in 0..KEY_COUNT {
+        if changed & (1 << code) == 0 {
+            continue;
+        }
+        if pressed & (1 << code) != 0 {
+            chip8.key_down(code);
+        } else {
+            chip8.key_up(code);
+        }
+    }
+}
+
+// Reads the debugger's commands on another thread, so the window keeps updating while it waits
+fn spawn_command_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => if sender.send(line).is_err() { break },
+                Err(_) => break,
+            }
+        }
+    });
+    receiver
+}
+
+fn print_debugger_output(output: &str, debugger: &Debugger) {
+    print!("{}", output);
+    if debugger.is_paused() {
+        print!("(chip8) ");
+    }
+    io::stdout().flush().ok();
+}
+
+struct Options {
+    rom: String,
+    // None uses the preset the rom is known to need, or modern for roms that aren't known
+    quirks: Option<Preset>,
+    instructions_per_frame: u32,
+    seed: Option<u64>,
+    rewind_frames: usize,
+    debug: bool,
+    palette: Palette,
+    persistence: Persistence,
+    scale: usize,
+    fullscreen: bool,
+    fullscreen_size: (usize, usize),
+    screenshot: ScreenshotOptions,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: rust-chip8 [--quirks vip|chip48|schip|xochip|modern] [--ipf N] [--seed N] [--rewind FRAMES] [--debug]");
+    eprintln!("                  [--palette THEME|#RRGGBB,... | --palette-file FILE]");
+    eprintln!("                  [--persistence off|max|fade[:FRAMES]] [--scale N] [--fullscreen] [--fullscreen-size WxH]");
+    eprintln!("                  [--screenshot-dir DIR] [--screenshot-scale N] [ROM | SOURCE.8o]");
+    eprintln!("To run without a window, use chip8-headless.");
+    process::exit(1);
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
         // Change this next line to game of choice
-        0 | 1 => "data/TANK",
-        _ => args.get(1).unwrap(),
+        rom: String::from("data/TANK"),
+        quirks: None,
+        instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+        seed: None,
+        rewind_frames: DEFAULT_REWIND_FRAMES,
+        debug: false,
+        palette: Palette::default(),
+        persistence: Persistence::Off,
+        scale: DEFAULT_SCALE,
+        fullscreen: false,
+        fullscreen_size: DEFAULT_FULLSCREEN_SIZE,
+        screenshot: ScreenshotOptions::new(),
     };
-    let mut file = File::open(file_name).unwrap();
-    let mut data = Vec::<u8>::new();
-    file.read_to_end(&mut data).expect("File not found");
-
-    let width = 640;
-    let height = 320;
-
-    // ARGB buffer
-    let mut buffer: Vec<u32> = vec![0; width * height];
-
-    let mut window = Window::new(
-        "Rust chip8 emulator",
-        width,
-        height,
-        WindowOptions::default(),
-    ).unwrap_or_else(
-        |e| {
-            panic!("Window creation failed: {:?}", e);
-        });
-
-    let mut chip8 = Chip8::new();
-    chip8.load_rom(&data);
-
-    let mut last_key_update_time = Instant::now();
-    let mut last_instruction_runtime = Instant::now();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.quirks = Some(value.parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    usage()
+                }));
+            }
+            "--ipf" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.instructions_per_frame = value.parse().unwrap_or_else(|_| {
+                    eprintln!("--ipf expects a number of instructions, got '{}'", value);
+                    usage()
+                });
+            }
+            "--seed" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.seed = Some(value.parse().unwrap_or_else(|_| {
+                    eprintln!("--seed expects a number, got '{}'", value);
+                    usage()
+                }));
+            }
+            "--rewind" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.rewind_frames = value.parse().unwrap_or_else(|_| {
+                    eprintln!("--rewind expects a number of frames, got '{}'", value);
+                    usage()
+                });
+            }
+            "--debug" => options.debug = true,
+            "--headless" => {
+                eprintln!("--headless has moved to its own program: chip8-headless [OPTIONS] ROM");
+                process::exit(1);
+            }
+            "--palette" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.palette = value.parse().unwrap_or_else(|e| {
+                    eprintln!("--palette: {}", e);
+                    usage()
+                });
+            }
+            "--persistence" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.persistence = value.parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    usage()
+                });
+            }
+            "--scale" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.scale = match value.parse() {
+                    Ok(0) | Err(_) => {
+                        eprintln!("--scale expects a whole number above 0, got '{}'", value);
+                        usage()
+                    }
+                    Ok(scale) => scale,
+                };
+            }
+            "--fullscreen" => options.fullscreen = true,
+            "--fullscreen-size" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.fullscreen_size = match value.split_once('x').map(|(w, h)| (w.parse(), h.parse())) {
+                    Some((Ok(width), Ok(height))) if width > 0 && height > 0 => (width, height),
+                    _ => {
+                        eprintln!("--fullscreen-size expects WIDTHxHEIGHT, got '{}'", value);
+                        usage()
+                    }
+                };
+            }
+            "--screenshot-dir" => options.screenshot.directory = PathBuf::from(args.next().unwrap_or_else(|| usage())),
+            "--screenshot-scale" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                options.screenshot.scale = match value.parse() {
+                    Ok(0) | Err(_) => {
+                        eprintln!("--screenshot-scale expects a whole number above 0, got '{}'", value);
+                        usage()
+                    }
+                    Ok(scale) => scale,
+                };
+            }
+            "--palette-file" => {
+                let path = args.next().unwrap_or_else(|| usage());
+                let text = fs::read_to_string(&path).unwrap_or_else(|e| {
+                    eprintln!("Failed to read {}: {}", path, e);
+                    process::exit(1);
+                });
+                options.palette = Palette::parse_config(&text).unwrap_or_else(|e| {
+                    eprintln!("{}: {}", path, e);
+                    process::exit(1);
+                });
+            }
+            "-h" | "--help" => usage(),
+            _ if arg.starts_with('-') => {
+                eprintln!("unknown option '{}'", arg);
+                usage()
+            }
+            _ => options.rom = arg,
+        }
+    }
+    // Screenshots look like the window
+    options.screenshot.palette = options.palette;
+    options
+}
+
+fn main() {
+    let options = parse_args();
+    let file_name = options.rom.as_str();
+    // Octo source is compiled for whichever interpreter the quirks preset stands for
+    let data = if file_name.ends_with(".8o") {
+        octo::compile_file(file_name, Target::for_preset(options.quirks.unwrap_or(Preset::Modern))).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        })
+    } else {
+        fs::read(file_name).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", file_name, e);
+            process::exit(1);
+        })
+    };
+
+    let mut chip8 = match options.seed {
+        Some(seed) => Chip8::with_seed(seed),
+        None => Chip8::new(),
+    };
+    // Printed so a run can be replayed with --seed
+    if let Some(seed) = chip8.seed() {
+        eprintln!("Random seed: {}", seed);
+    }
+    let quirks = options.quirks.or_else(|| Preset::for_rom(&data)).unwrap_or(Preset::Modern);
+    if options.quirks.is_none() && quirks != Preset::Modern {
+        eprintln!("Using {} quirks for {}", quirks, file_name);
+    }
+    chip8.set_quirks(quirks.quirks());
+    chip8.set_instructions_per_frame(options.instructions_per_frame);
+    chip8.load_rom(&data).unwrap_or_else(|e| {
+        eprintln!("Failed to load {}: {}", file_name, e);
+        process::exit(1);
+    });
+
+    // Where to go back to when leaving fullscreen
+    let mut windowed_size = (display::WIDTH * options.scale, display::HEIGHT * options.scale);
+    let mut fullscreen = options.fullscreen;
+    let (width, height) = if fullscreen { options.fullscreen_size } else { windowed_size };
+    let mut window = open_window(width, height, fullscreen);
+    // The title changes when the rom stops, and has to be put back if the window is reopened
+    let mut title = String::from(TITLE);
+
+    // ARGB buffer, the size of the window
+    let mut buffer: Vec<u32> = Vec::new();
+    // What the buffer was last drawn at and when, to tell when it needs drawing again
+    let mut drawn_size = (0, 0);
+    let mut last_redraw_time = Instant::now();
+    let mut audio: Box<dyn AudioBackend> = match Beeper::new() {
+        Ok(beeper) => Box::new(beeper),
+        Err(e) => {
+            eprintln!("Audio disabled: {}", e);
+            Box::new(NullAudio)
+        }
+    };
+
+    // With --debug the rom starts paused and is driven from the terminal
+    let mut debugger = if options.debug {
+        let debugger = Debugger::new();
+        println!("Debugging {}, type help for the commands", file_name);
+        print_debugger_output("", &debugger);
+        Some((debugger, spawn_command_reader()))
+    } else {
+        None
+    };
+
+    let mut rewind = Rewind::new(options.rewind_frames);
+    rewind.record(&chip8);
+    let mut phosphor = Phosphor::new(options.persistence);
+
+    // Once the cpu hits an error it stops running, but the window stays open on the last frame
+    let mut halted = false;
+
+    let mut last_frame_time = Instant::now();
     let mut last_display_time = Instant::now();
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
-        let keys_pressed = window.get_keys_pressed(KeyRepeat::Yes);
-        let key = match keys_pressed {
-            Some(keys) => if !keys.is_empty() {
-                Some(keys[0])
-            } else {
-                None
-            },
-            None => None,
-        };
-
-        let chip8_key = get_chip8_keycode_for(key);
-        if chip8_key.is_some() || Instant::now() - last_key_update_time >= Duration::from_millis(200) {
-            last_key_update_time = Instant::now();
-            chip8.set_key_pressed(chip8_key);
+        if window.is_key_pressed(FULLSCREEN_KEY, KeyRepeat::No) {
+            if !fullscreen {
+                windowed_size = window.get_size();
+            }
+            fullscreen = !fullscreen;
+            let (width, height) = if fullscreen { options.fullscreen_size } else { windowed_size };
+            window = open_window(width, height, fullscreen);
+            window.set_title(&title);
+            drawn_size = (0, 0);
+        }
+        if window.is_key_pressed(SCREENSHOT_KEY, KeyRepeat::No) {
+            match chip8.screenshot(&options.screenshot) {
+                Ok(path) => println!("Saved screenshot to {}", path.display()),
+                Err(e) => eprintln!("Failed to save screenshot: {}", e),
+            }
+        }
+        update_keys(&window, &mut chip8);
+        if handle_slot_keys(&window, &mut chip8, file_name) {
+            // Loading a state picks up from before whatever stopped the cpu
+            halted = chip8.has_exited();
+            title = String::from(TITLE);
+            window.set_title(&title);
+            phosphor.reset();
         }
 
-        if Instant::now() - last_instruction_runtime > Duration::from_millis(2) {
-            chip8.run_instruction();
-            last_instruction_runtime = Instant::now();
+        if let Some((debugger, commands)) = debugger.as_mut() {
+            while let Ok(line) = commands.try_recv() {
+                let output = debugger.command(&mut chip8, &line);
+                if debugger.should_quit() {
+                    return;
+                }
+                print_debugger_output(&output, debugger);
+            }
         }
 
-        if Instant::now() - last_display_time > Duration::from_millis(10) {
-            let chip8_buffer = chip8.get_display_buffer();
-
-            for y in 0..height {
-                let y_coord = y / 10;
-                let offset = y * width;
-
-                for x in 0..width {
-                    let index = Display::get_index_from_coords(x / 10, y_coord);
-                    let pixel = chip8_buffer[index];
-                    let color_pixel = match pixel {
-                        0 => 0x0,
-                        1 => 0xffffff,
-                        _ => unreachable!(),
-                    };
-                    buffer[offset + x] = color_pixel;
+        let rewinding = window.is_key_down(REWIND_KEY);
+        if rewinding && Instant::now() - last_frame_time >= FRAME_TIME {
+            match rewind.step_back(&mut chip8) {
+                // Stepping back from an exit or an error carries on from before it
+                Ok(true) => {
+                    if halted && !chip8.has_exited() {
+                        title = String::from(TITLE);
+                        window.set_title(&title);
+                    }
+                    halted = chip8.has_exited();
+                    phosphor.end_frame(chip8.get_display_buffer(), chip8.display_width(), chip8.display_height());
+                }
+                Ok(false) => {}
+                Err(e) => eprintln!("Rewind failed: {}", e),
+            }
+            if let Err(e) = audio.update(false, None) {
+                eprintln!("Audio error: {}", e);
+            }
+            last_frame_time = Instant::now();
+        } else if let Some((debugger, _)) = debugger.as_mut() {
+            // The debugger stops on errors instead of halting, so they can be looked into
+            if !debugger.is_paused() && Instant::now() - last_frame_time >= FRAME_TIME {
+                if let Some(output) = debugger.run_frame(&mut chip8) {
+                    print_debugger_output(&output, debugger);
+                }
+                if !debugger.is_paused() {
+                    rewind.record(&chip8);
+                    phosphor.end_frame(chip8.get_display_buffer(), chip8.display_width(), chip8.display_height());
+                }
+                if let Err(e) = audio.update(!debugger.is_paused() && chip8.is_beeping(), chip8.audio_pattern().as_ref()) {
+                    eprintln!("Audio error: {}", e);
+                }
+                last_frame_time = Instant::now();
+            } else if debugger.is_paused() {
+                if let Err(e) = audio.update(false, None) {
+                    eprintln!("Audio error: {}", e);
+                }
+            }
+        } else if !rewinding && !halted && Instant::now() - last_frame_time >= FRAME_TIME {
+            match chip8.run_frame() {
+                Ok(StepOutcome::Exited) => {
+                    title = format!("{} - exited", TITLE);
+                    window.set_title(&title);
+                    halted = true;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Emulation halted: {}", e);
+                    title = format!("{} - halted: {}", TITLE, e);
+                    window.set_title(&title);
+                    halted = true;
                 }
             }
+            rewind.record(&chip8);
+            phosphor.end_frame(chip8.get_display_buffer(), chip8.display_width(), chip8.display_height());
+            let pattern = chip8.audio_pattern();
+            if let Err(e) = audio.update(!halted && chip8.is_beeping(), pattern.as_ref()) {
+                eprintln!("Audio error: {}", e);
+            }
+            last_frame_time = Instant::now();
+        }
 
-            window.update_with_buffer(&buffer, width, height);
+        if Instant::now() - last_display_time > Duration::from_millis(10) {
+            // Drawn at the window's size every time, so resizing it or the rom switching
+            // resolution just changes the scale
+            let (width, height) = window.get_size();
+            // Fading pixels change without the screen changing
+            let redraw = chip8.is_display_dirty()
+                || phosphor.persistence() != Persistence::Off
+                || (width, height) != drawn_size
+                || Instant::now() - last_redraw_time >= IDLE_REDRAW_TIME;
+            if width == 0 || height == 0 || !redraw {
+                // Minimized or nothing new to show, just keep up with input
+                window.update();
+            } else {
+                let chip8_width = chip8.display_width();
+                let chip8_height = chip8.display_height();
+                let colors = phosphor.render(chip8.get_display_buffer(), chip8_width, chip8_height, &options.palette);
+                buffer.resize(width * height, 0);
+                scale::blit(colors, chip8_width, chip8_height, &mut buffer, width, height, LETTERBOX_COLOR);
+                window.update_with_buffer(&buffer, width, height)
+                    .expect("Failed to update window");
+                chip8.clear_display_dirty();
+                drawn_size = (width, height);
+                last_redraw_time = Instant::now();
+            }
             last_display_time = Instant::now();
         }
     }
@@ -0,0 +1,1046 @@
+use crate::cpu::PROGRAM_START;
+use crate::error::AsmError;
+use crate::instruction::Instruction;
+use crate::quirks::Preset;
+use crate::ram::{RAM_SIZE, XO_CHIP_RAM_SIZE};
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts;
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+// A compiler for Octo, the high level assembly language most modern chip8 programs are written
+// in. Like Octo's own compiler it works in a single pass over whitespace separated tokens,
+// patching in forward references to labels once the whole program has been read.
+//
+// Programs start with a jump to the label main, which is left out when main is the very first
+// thing in the program.
+
+// Guards against macros that expand forever
+const MAX_EXPANDED_TOKENS: usize = 1_000_000;
+
+// Which interpreter a program is written for, deciding which instructions it can use and how
+// much memory it has
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Target {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Target {
+    // The target that runs correctly under a quirks preset. Only XO-CHIP has more than 4K of
+    // memory, so the modern preset gets SUPER-CHIP.
+    pub fn for_preset(preset: Preset) -> Target {
+        match preset {
+            Preset::CosmacVip => Target::Chip8,
+            Preset::Chip48 | Preset::SuperChip | Preset::Modern => Target::SuperChip,
+            Preset::XoChip => Target::XoChip,
+        }
+    }
+
+    // One past the last address a program can be loaded into
+    fn memory_end(self) -> u32 {
+        match self {
+            Target::Chip8 | Target::SuperChip => RAM_SIZE as u32,
+            Target::XoChip => XO_CHIP_RAM_SIZE as u32,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Target::Chip8 => write!(f, "CHIP-8"),
+            Target::SuperChip => write!(f, "SUPER-CHIP"),
+            Target::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+// The oldest target an instruction exists on
+fn required_target(instruction: Instruction) -> Target {
+    use crate::instruction::Instruction::*;
+    match instruction {
+        ScrollDown(_) | ScrollRight | ScrollLeft | Exit | Lores | Hires | BigFont { .. } | SaveFlags { .. }
+        | LoadFlags { .. } => Target::SuperChip,
+        ScrollUp(_) | SaveRange { .. } | LoadRange { .. } | LoadILong | Plane(_) | Audio | Pitch { .. } => Target::XoChip,
+        _ => Target::Chip8,
+    }
+}
+
+pub fn compile(source: &str, target: Target) -> Result<Vec<u8>, AsmError> {
+    Compiler::new(Rc::from("<input>"), source, target).run()
+}
+
+pub fn compile_file<P: AsRef<Path>>(path: P, target: Target) -> Result<Vec<u8>, AsmError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|e| AsmError {
+        file: path.display().to_string(),
+        line: 0,
+        column: 0,
+        message: format!("can't read file: {}", e),
+    })?;
+    Compiler::new(Rc::from(path.display().to_string()), &source, target).run()
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn is(&self, text: &str) -> bool {
+        self.text == text
+    }
+}
+
+// Splits source on whitespace, dropping # comments. A quoted string is one token.
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i].is_whitespace() {
+                i += 1;
+                continue;
+            }
+            if chars[i] == '#' {
+                break;
+            }
+            let start = i;
+            if chars[i] == '"' {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    i += 1;
+                }
+                i = (i + 1).min(chars.len());
+            } else {
+                while i < chars.len() && !chars[i].is_whitespace() {
+                    i += 1;
+                }
+            }
+            tokens.push_back(Token {
+                text: chars[start..i].iter().collect(),
+                line: index + 1,
+                column: start + 1,
+            });
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn register_number(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => digit.to_digit(16).map(|d| d as u8),
+        _ => None,
+    }
+}
+
+const KEYWORDS: [&str; 53] = [
+    ":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<=", "==", "!=", "<", ">", "<=", ">=", "key", "-key",
+    "hex", "bighex", "random", "delay", "buzzer", "pitch", "i", "long", "if", "then", "begin", "else", "end",
+    "loop", "again", "while", "jump", "jump0", "native", "return", ";", "clear", "bcd", "save", "load",
+    "saveflags", "loadflags", "sprite", "scroll-down", "scroll-up", "scroll-left", "scroll-right", "exit",
+    "lores", "hires", "plane",
+];
+
+fn is_reserved(text: &str) -> bool {
+    KEYWORDS.contains(&text) || text == "audio" || register_number(text).is_some() || text.starts_with(':')
+        || text.starts_with('{') || text.starts_with('}') || parse_number(text).is_some()
+}
+
+// A value that may be a label defined further on
+enum Value {
+    Known(i64),
+    Forward(String),
+}
+
+// How to patch in a label once it's defined
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    // The low 12 bits of an instruction, e.g. the NNN of a jump
+    Address,
+    // A whole 16 bit word, after i := long or from :pointer
+    Long,
+    // The pair of vN := NN instructions from :unpack, with the nibble that goes above the
+    // address, or None for :unpack long
+    Unpack(Option<u8>),
+}
+
+struct Fixup {
+    address: u16,
+    kind: FixupKind,
+    token: Token,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+    // How many times it's been invoked, available to the body as CALLS
+    calls: usize,
+}
+
+struct Loop {
+    start: u16,
+    token: Token,
+    // Jumps out of the loop from while, patched when again is reached
+    exits: Vec<u16>,
+}
+
+struct Compiler {
+    target: Target,
+    file: Rc<str>,
+    tokens: VecDeque<Token>,
+    // Where the last token came from, for errors at the end of the file
+    last: Token,
+    expanded: usize,
+    // From PROGRAM_START, with which bytes have been written so far to catch :org overlaps
+    rom: Vec<u8>,
+    written: Vec<bool>,
+    here: u32,
+    // Set until the first label or byte decides whether the program needs a jump to main
+    entry_pending: bool,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    // Jumps from begin and else waiting for their else or end
+    branches: Vec<(u16, Token)>,
+    loops: Vec<Loop>,
+}
+
+impl Compiler {
+    fn new(file: Rc<str>, source: &str, target: Target) -> Compiler {
+        Compiler {
+            target,
+            file,
+            tokens: tokenize(source),
+            last: Token { text: String::new(), line: 1, column: 1 },
+            expanded: 0,
+            rom: Vec::new(),
+            written: Vec::new(),
+            here: PROGRAM_START as u32,
+            entry_pending: true,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    fn error<S: Into<String>>(&self, token: &Token, message: S) -> AsmError {
+        AsmError {
+            file: self.file.to_string(),
+            line: token.line,
+            column: token.column,
+            message: message.into(),
+        }
+    }
+
+    fn next(&mut self) -> Result<Token, AsmError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.last = token.clone();
+                Ok(token)
+            }
+            None => {
+                let last = self.last.clone();
+                Err(self.error(&last, "unexpected end of file"))
+            }
+        }
+    }
+
+    fn peek(&self, ahead: usize) -> Option<&Token> {
+        self.tokens.get(ahead)
+    }
+
+    fn next_is(&self, text: &str) -> bool {
+        self.peek(0).is_some_and(|token| token.is(text))
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AsmError> {
+        let token = self.next()?;
+        if !token.is(text) {
+            return Err(self.error(&token, format!("expected '{}', got '{}'", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn register_of(&self, token: &Token) -> Option<u8> {
+        register_number(&token.text).or_else(|| self.aliases.get(&token.text).copied())
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        self.register_of(&token).ok_or_else(|| self.error(&token, format!("expected a register, got '{}'", token.text)))
+    }
+
+    // A name being defined by a label, constant, alias or macro
+    fn name(&mut self) -> Result<Token, AsmError> {
+        let token = self.next()?;
+        if is_reserved(&token.text) {
+            return Err(self.error(&token, format!("'{}' is a reserved name", token.text)));
+        }
+        Ok(token)
+    }
+
+    fn value(&self, token: &Token) -> Result<Value, AsmError> {
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(Value::Known(value));
+        }
+        if let Some(value) = self.constants.get(&token.text) {
+            return Ok(Value::Known(value.floor() as i64));
+        }
+        if let Some(address) = self.labels.get(&token.text) {
+            return Ok(Value::Known(*address as i64));
+        }
+        if is_reserved(&token.text) || self.aliases.contains_key(&token.text) {
+            return Err(self.error(token, format!("expected a value, got '{}'", token.text)));
+        }
+        Ok(Value::Forward(token.text.clone()))
+    }
+
+    // A value that has to be known already, unlike an address
+    fn known(&mut self, min: i64, max: i64, what: &str) -> Result<i64, AsmError> {
+        let token = self.next()?;
+        let value = match self.value(&token)? {
+            Value::Known(value) => value,
+            Value::Forward(name) => return Err(self.error(&token, format!("undefined name '{}'", name))),
+        };
+        if value < min || value > max {
+            return Err(self.error(&token, format!("{} doesn't fit in {}", value, what)));
+        }
+        Ok(value)
+    }
+
+    // Negative bytes wrap around, so v0 += -1 works
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        Ok(self.known(-0x80, 0xFF, "a byte")? as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AsmError> {
+        Ok(self.known(0, 0xF, "a nibble")? as u8)
+    }
+
+    // An address that may be a label further on, in which case at gets patched once it's known
+    fn address(&mut self, at: u32, kind: FixupKind) -> Result<u16, AsmError> {
+        let token = self.next()?;
+        let max = match kind {
+            FixupKind::Long | FixupKind::Unpack(None) => 0xFFFF,
+            FixupKind::Address | FixupKind::Unpack(Some(_)) => 0xFFF,
+        };
+        match self.value(&token)? {
+            Value::Known(value) if value < 0 || value > max => {
+                Err(self.error(&token, format!("{} doesn't fit in a {} bit address", value, if max == 0xFFF { 12 } else { 16 })))
+            }
+            Value::Known(value) => Ok(value as u16),
+            Value::Forward(_) => {
+                self.fixups.push(Fixup { address: at as u16, kind, token });
+                Ok(0)
+            }
+        }
+    }
+
+    // Whatever gets defined or emitted first decides whether main needs jumping to
+    fn settle_entry(&mut self, token: &Token, first_label: Option<&str>) -> Result<(), AsmError> {
+        if !self.entry_pending {
+            return Ok(());
+        }
+        self.entry_pending = false;
+        if first_label == Some("main") {
+            return Ok(());
+        }
+        let main = Token { text: String::from("main"), line: token.line, column: token.column };
+        self.fixups.push(Fixup { address: self.here as u16, kind: FixupKind::Address, token: main });
+        self.emit(token, Instruction::Jump(0))
+    }
+
+    fn write(&mut self, token: &Token, address: u32, byte: u8) -> Result<(), AsmError> {
+        if address >= self.target.memory_end() {
+            return Err(self.error(token, format!("program doesn't fit in {} memory", self.target)));
+        }
+        let offset = (address - PROGRAM_START as u32) as usize;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+            self.written.resize(offset + 1, false);
+        }
+        if self.written[offset] {
+            return Err(self.error(token, format!("overwrites the byte already at {:#05X}", address)));
+        }
+        self.rom[offset] = byte;
+        self.written[offset] = true;
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, token: &Token, byte: u8) -> Result<(), AsmError> {
+        self.settle_entry(token, None)?;
+        self.write(token, self.here, byte)?;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit_word(&mut self, token: &Token, word: u16) -> Result<(), AsmError> {
+        self.emit_byte(token, (word >> 8) as u8)?;
+        self.emit_byte(token, word as u8)
+    }
+
+    fn emit(&mut self, token: &Token, instruction: Instruction) -> Result<(), AsmError> {
+        let required = required_target(instruction);
+        if required > self.target {
+            return Err(self.error(token, format!("'{:#}' needs {}, compiling for {}", instruction, required, self.target)));
+        }
+        self.emit_word(token, instruction.encode())
+    }
+
+    // Points the jump at address to target
+    fn patch_jump(&mut self, address: u16, target: u32) {
+        let offset = (address - PROGRAM_START) as usize;
+        self.rom[offset] = (self.rom[offset] & 0xF0) | (target >> 8) as u8 & 0xF;
+        self.rom[offset + 1] = target as u8;
+    }
+
+    fn run(mut self) -> Result<Vec<u8>, AsmError> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if let Some((_, token)) = self.branches.last() {
+            return Err(self.error(token, "missing end"));
+        }
+        if let Some(open) = self.loops.last() {
+            return Err(self.error(&open.token, "loop without again"));
+        }
+        if !self.labels.contains_key("main") {
+            let first = Token { text: String::new(), line: 1, column: 1 };
+            return Err(self.error(&first, "the program doesn't define main"));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let value = match self.labels.get(&fixup.token.text) {
+                Some(value) => *value as u32,
+                None => return Err(self.error(&fixup.token, format!("undefined label '{}'", fixup.token.text))),
+            };
+            let offset = (fixup.address - PROGRAM_START) as usize;
+            match fixup.kind {
+                FixupKind::Address | FixupKind::Unpack(Some(_)) if value > 0xFFF => {
+                    return Err(self.error(&fixup.token, format!("'{}' is past the 12 bit address range", fixup.token.text)));
+                }
+                FixupKind::Address => self.patch_jump(fixup.address, value),
+                FixupKind::Long => {
+                    self.rom[offset] = (value >> 8) as u8;
+                    self.rom[offset + 1] = value as u8;
+                }
+                FixupKind::Unpack(nibble) => {
+                    self.rom[offset + 1] = match nibble {
+                        Some(nibble) => nibble << 4 | (value >> 8) as u8,
+                        None => (value >> 8) as u8,
+                    };
+                    self.rom[offset + 3] = value as u8;
+                }
+            }
+        }
+        Ok(self.rom)
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if let Some(x) = self.register_of(&token) {
+            return self.register_statement(&token, x);
+        }
+        if let Some(value) = parse_number(&token.text) {
+            // A bare number is a byte of data, which is how sprites are written
+            if !(-0x80..=0xFF).contains(&value) {
+                return Err(self.error(&token, format!("{} doesn't fit in a byte", value)));
+            }
+            return self.emit_byte(&token, value as u8);
+        }
+        if self.macros.contains_key(&token.text) {
+            return self.expand(&token);
+        }
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                self.settle_entry(&token, Some(&name.text))?;
+                self.define_label(&name, self.here)
+            }
+            ":next" => {
+                let name = self.name()?;
+                self.settle_entry(&token, None)?;
+                // The second byte of the next instruction, for self modifying code
+                self.define_label(&name, self.here + 1)
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.known(i64::MIN, i64::MAX, "a constant")?;
+                self.define_constant(&name, value as f64)
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                if self.labels.contains_key(&name.text) {
+                    return Err(self.error(&name, format!("'{}' is already a label", name.text)));
+                }
+                // Unlike :const, calc can redefine a constant, e.g. :calc x { x + 1 }
+                self.constants.insert(name.text, value);
+                Ok(())
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = if self.next_is("{") {
+                    let open = self.peek(0).cloned().unwrap();
+                    let value = self.calc()?;
+                    if !(0.0..16.0).contains(&value) {
+                        return Err(self.error(&open, format!("{} isn't a register number", value)));
+                    }
+                    value as u8
+                } else {
+                    self.register()?
+                };
+                self.aliases.insert(name.text, register);
+                Ok(())
+            }
+            ":macro" => self.define_macro(),
+            ":byte" => {
+                if self.next_is("{") {
+                    let value = self.calc()?;
+                    self.emit_byte(&token, value.floor() as i64 as u8)
+                } else {
+                    let byte = self.byte()?;
+                    self.emit_byte(&token, byte)
+                }
+            }
+            ":pointer" => {
+                self.settle_entry(&token, None)?;
+                let address = self.address(self.here, FixupKind::Long)?;
+                self.emit_word(&token, address)
+            }
+            ":org" => {
+                let address = self.known(PROGRAM_START as i64, self.target.memory_end() as i64 - 1, "program memory")?;
+                self.settle_entry(&token, None)?;
+                self.here = address as u32;
+                Ok(())
+            }
+            ":unpack" => {
+                let nibble = if self.next_is("long") {
+                    self.next()?;
+                    None
+                } else {
+                    Some(self.nibble()?)
+                };
+                self.settle_entry(&token, None)?;
+                let address = self.address(self.here, FixupKind::Unpack(nibble))?;
+                let high = match nibble {
+                    Some(nibble) => nibble << 4 | (address >> 8) as u8,
+                    None => (address >> 8) as u8,
+                };
+                self.emit(&token, Instruction::Set { x: 0, nn: high })?;
+                self.emit(&token, Instruction::Set { x: 1, nn: address as u8 })
+            }
+            ":call" => {
+                self.settle_entry(&token, None)?;
+                let address = self.address(self.here, FixupKind::Address)?;
+                self.emit(&token, Instruction::Call(address))
+            }
+            ":assert" => {
+                let message = match self.peek(0) {
+                    Some(t) if t.text.starts_with('"') => Some(self.next()?.text.trim_matches('"').to_string()),
+                    _ => None,
+                };
+                if self.calc()? == 0.0 {
+                    return Err(self.error(&token, message.unwrap_or_else(|| String::from("assertion failed"))));
+                }
+                Ok(())
+            }
+            // Debugging hints for Octo's own emulator
+            ":breakpoint" => self.next().map(|_| ()),
+            ":monitor" => {
+                self.next()?;
+                self.next().map(|_| ())
+            }
+            "return" | ";" => self.emit(&token, Instruction::Return),
+            "clear" => self.emit(&token, Instruction::Clear),
+            "exit" => self.emit(&token, Instruction::Exit),
+            "lores" => self.emit(&token, Instruction::Lores),
+            "hires" => self.emit(&token, Instruction::Hires),
+            "scroll-left" => self.emit(&token, Instruction::ScrollLeft),
+            "scroll-right" => self.emit(&token, Instruction::ScrollRight),
+            "audio" => self.emit(&token, Instruction::Audio),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(&token, Instruction::ScrollDown(n))
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(&token, Instruction::ScrollUp(n))
+            }
+            "plane" => {
+                let mask = self.known(0, 3, "a plane mask")?;
+                self.emit(&token, Instruction::Plane(mask as u8))
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(&token, Instruction::Bcd { x })
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(&token, Instruction::SaveFlags { x })
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(&token, Instruction::LoadFlags { x })
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let instruction = if self.next_is("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    if token.is("save") { Instruction::SaveRange { x, y } } else { Instruction::LoadRange { x, y } }
+                } else if token.is("save") {
+                    Instruction::Store { x }
+                } else {
+                    Instruction::Load { x }
+                };
+                self.emit(&token, instruction)
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(&token, Instruction::Draw { x, y, n })
+            }
+            "jump" | "jump0" | "native" => {
+                self.settle_entry(&token, None)?;
+                let address = self.address(self.here, FixupKind::Address)?;
+                let instruction = match token.text.as_str() {
+                    "jump" => Instruction::Jump(address),
+                    "jump0" => Instruction::JumpOffset { x: (address >> 8) as u8, nnn: address },
+                    _ => Instruction::Sys(address),
+                };
+                self.emit(&token, instruction)
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let instruction = match token.text.as_str() {
+                    "delay" => Instruction::SetDelay { x },
+                    "buzzer" => Instruction::SetSound { x },
+                    _ => Instruction::Pitch { x },
+                };
+                self.emit(&token, instruction)
+            }
+            "i" => self.i_statement(&token),
+            "if" => self.if_statement(&token),
+            "else" => {
+                let (begin, _) = self.branches.pop().ok_or_else(|| self.error(&token, "else without if ... begin"))?;
+                self.settle_entry(&token, None)?;
+                let jump = self.here as u16;
+                self.emit(&token, Instruction::Jump(0))?;
+                self.patch_jump(begin, self.here);
+                self.branches.push((jump, token));
+                Ok(())
+            }
+            "end" => {
+                let (jump, _) = self.branches.pop().ok_or_else(|| self.error(&token, "end without if ... begin"))?;
+                self.patch_jump(jump, self.here);
+                Ok(())
+            }
+            "loop" => {
+                self.settle_entry(&token, None)?;
+                self.loops.push(Loop { start: self.here as u16, token, exits: Vec::new() });
+                Ok(())
+            }
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(self.error(&token, "while outside of a loop"));
+                }
+                // Skips the jump out while the condition holds
+                self.conditional(true)?;
+                let exit = self.here as u16;
+                self.emit(&token, Instruction::Jump(0))?;
+                self.loops.last_mut().unwrap().exits.push(exit);
+                Ok(())
+            }
+            "again" => {
+                let open = self.loops.pop().ok_or_else(|| self.error(&token, "again without loop"))?;
+                self.emit(&token, Instruction::Jump(open.start))?;
+                for exit in open.exits {
+                    self.patch_jump(exit, self.here);
+                }
+                Ok(())
+            }
+            _ if is_reserved(&token.text) => Err(self.error(&token, format!("unexpected '{}'", token.text))),
+            // Anything else is the name of a subroutine to call
+            _ => {
+                self.settle_entry(&token, None)?;
+                self.tokens.push_front(token.clone());
+                let address = self.address(self.here, FixupKind::Address)?;
+                self.emit(&token, Instruction::Call(address))
+            }
+        }
+    }
+
+    fn define_label(&mut self, name: &Token, address: u32) -> Result<(), AsmError> {
+        if self.labels.contains_key(&name.text) || self.constants.contains_key(&name.text) {
+            return Err(self.error(name, format!("'{}' is already defined", name.text)));
+        }
+        self.labels.insert(name.text.clone(), address as u16);
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: &Token, value: f64) -> Result<(), AsmError> {
+        if self.labels.contains_key(&name.text) || self.constants.contains_key(&name.text) {
+            return Err(self.error(name, format!("'{}' is already defined", name.text)));
+        }
+        self.constants.insert(name.text.clone(), value);
+        Ok(())
+    }
+
+    // :macro name params... { body }
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.name()?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.is("{") {
+                break;
+            }
+            params.push(token.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            if token.is("{") {
+                depth += 1;
+            } else if token.is("}") {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            body.push(token);
+        }
+        self.macros.insert(name.text, Macro { params, body, calls: 0 });
+        Ok(())
+    }
+
+    // Replaces a macro invocation with the macro's body, its parameters swapped for the
+    // arguments that follow the name
+    fn expand(&mut self, name: &Token) -> Result<(), AsmError> {
+        let count = self.macros[&name.text].params.len();
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            args.push(self.next()?);
+        }
+        let m = self.macros.get_mut(&name.text).unwrap();
+        let calls = m.calls;
+        m.calls += 1;
+        let expansion: Vec<Token> = m.body.iter().map(|token| {
+            if let Some(i) = m.params.iter().position(|p| *p == token.text) {
+                args[i].clone()
+            } else if token.is("CALLS") {
+                Token { text: calls.to_string(), ..token.clone() }
+            } else {
+                token.clone()
+            }
+        }).collect();
+
+        self.expanded += expansion.len();
+        if self.expanded > MAX_EXPANDED_TOKENS {
+            return Err(self.error(name, "macros expand without end"));
+        }
+        for token in expansion.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, token: &Token, x: u8) -> Result<(), AsmError> {
+        let op = self.next()?;
+        let rhs = self.next()?;
+        let y = self.register_of(&rhs);
+        let instruction = match (op.text.as_str(), y) {
+            (":=", Some(y)) => Instruction::Copy { x, y },
+            (":=", None) if rhs.is("key") => Instruction::WaitKey { x },
+            (":=", None) if rhs.is("delay") => Instruction::GetDelay { x },
+            (":=", None) if rhs.is("random") => Instruction::Random { x, nn: self.byte()? },
+            ("+=", Some(y)) => Instruction::Add { x, y },
+            ("-=", Some(y)) => Instruction::Sub { x, y },
+            ("=-", Some(y)) => Instruction::SubReverse { x, y },
+            ("|=", Some(y)) => Instruction::Or { x, y },
+            ("&=", Some(y)) => Instruction::And { x, y },
+            ("^=", Some(y)) => Instruction::Xor { x, y },
+            (">>=", Some(y)) => Instruction::ShiftRight { x, y },
+            ("<<=", Some(y)) => Instruction::ShiftLeft { x, y },
+            (":=", None) | ("+=", None) | ("-=", None) => {
+                self.tokens.push_front(rhs);
+                let nn = self.byte()?;
+                match op.text.as_str() {
+                    ":=" => Instruction::Set { x, nn },
+                    "+=" => Instruction::AddImmediate { x, nn },
+                    _ => Instruction::AddImmediate { x, nn: nn.wrapping_neg() },
+                }
+            }
+            ("=-", None) | ("|=", None) | ("&=", None) | ("^=", None) | (">>=", None) | ("<<=", None) => {
+                return Err(self.error(&rhs, format!("{} needs a register, got '{}'", op.text, rhs.text)));
+            }
+            _ => return Err(self.error(&op, format!("unknown operator '{}'", op.text))),
+        };
+        self.emit(token, instruction)
+    }
+
+    fn i_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        let op = self.next()?;
+        if op.is("+=") {
+            let x = self.register()?;
+            return self.emit(token, Instruction::AddI { x });
+        }
+        if !op.is(":=") {
+            return Err(self.error(&op, format!("expected := or +=, got '{}'", op.text)));
+        }
+        let source = self.peek(0).map(|t| t.text.clone()).unwrap_or_default();
+        match source.as_str() {
+            "hex" | "bighex" => {
+                self.next()?;
+                let x = self.register()?;
+                let instruction = if source == "hex" { Instruction::Font { x } } else { Instruction::BigFont { x } };
+                self.emit(token, instruction)
+            }
+            "long" => {
+                self.next()?;
+                self.emit(token, Instruction::LoadILong)?;
+                let address = self.address(self.here, FixupKind::Long)?;
+                self.emit_word(token, address)
+            }
+            _ => {
+                self.settle_entry(token, None)?;
+                let address = self.address(self.here, FixupKind::Address)?;
+                self.emit(token, Instruction::LoadI(address))
+            }
+        }
+    }
+
+    // if COND then STATEMENT, or if COND begin ... [else ...] end
+    fn if_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        let keyword_at = match self.peek(1) {
+            Some(op) if op.is("key") || op.is("-key") => 2,
+            _ => 3,
+        };
+        let keyword = self.peek(keyword_at).cloned().ok_or_else(|| self.error(token, "if without then or begin"))?;
+        if keyword.is("then") {
+            // Skips the statement after then unless the condition holds
+            self.conditional(false)?;
+            self.expect("then")?;
+            Ok(())
+        } else if keyword.is("begin") {
+            // Skips the jump to else or end when the condition holds
+            self.conditional(true)?;
+            self.expect("begin")?;
+            let jump = self.here as u16;
+            self.emit(token, Instruction::Jump(0))?;
+            self.branches.push((jump, token.clone()));
+            Ok(())
+        } else {
+            Err(self.error(&keyword, format!("expected then or begin, got '{}'", keyword.text)))
+        }
+    }
+
+    // Emits an instruction that skips the next one when the condition is false, or when it's
+    // true if negated. <, >, <= and >= compare by subtracting in a temporary register, VF unless
+    // compare-temp is aliased to something else.
+    fn conditional(&mut self, negated: bool) -> Result<(), AsmError> {
+        let x = self.register()?;
+        let op_token = self.next()?;
+        let op = match (op_token.text.as_str(), negated) {
+            (op, false) => op,
+            ("==", true) => "!=",
+            ("!=", true) => "==",
+            ("key", true) => "-key",
+            ("-key", true) => "key",
+            ("<", true) => ">=",
+            (">", true) => "<=",
+            ("<=", true) => ">",
+            (">=", true) => "<",
+            (op, true) => op,
+        }.to_string();
+
+        match op.as_str() {
+            "key" => return self.emit(&op_token, Instruction::SkipIfNotKey { x }),
+            "-key" => return self.emit(&op_token, Instruction::SkipIfKey { x }),
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {}
+            _ => return Err(self.error(&op_token, format!("expected a comparison, got '{}'", op_token.text))),
+        }
+
+        let rhs = self.next()?;
+        let y = self.register_of(&rhs);
+        if op == "==" || op == "!=" {
+            let instruction = match (op.as_str(), y) {
+                ("==", Some(y)) => Instruction::SkipIfRegistersNotEqual { x, y },
+                ("==", None) => {
+                    self.tokens.push_front(rhs);
+                    Instruction::SkipIfNotEqual { x, nn: self.byte()? }
+                }
+                (_, Some(y)) => Instruction::SkipIfRegistersEqual { x, y },
+                (_, None) => {
+                    self.tokens.push_front(rhs);
+                    Instruction::SkipIfEqual { x, nn: self.byte()? }
+                }
+            };
+            return self.emit(&op_token, instruction);
+        }
+
+        let temp = self.aliases.get("compare-temp").copied().unwrap_or(0xF);
+        let load = match y {
+            Some(y) => Instruction::Copy { x: temp, y },
+            None => {
+                self.tokens.push_front(rhs);
+                Instruction::Set { x: temp, nn: self.byte()? }
+            }
+        };
+        self.emit(&op_token, load)?;
+        // VF ends up 1 when there was no borrow
+        let subtract = match op.as_str() {
+            ">" | "<=" => Instruction::Sub { x: temp, y: x },
+            _ => Instruction::SubReverse { x: temp, y: x },
+        };
+        self.emit(&op_token, subtract)?;
+        let skip = match op.as_str() {
+            ">" | "<" => Instruction::SkipIfEqual { x: 0xF, nn: 1 },
+            _ => Instruction::SkipIfNotEqual { x: 0xF, nn: 1 },
+        };
+        self.emit(&op_token, skip)
+    }
+
+    // { expression }, evaluated straight away. As in Octo, operators have no precedence and
+    // group from the right, so 2 * 3 + 1 is 8.
+    fn calc(&mut self) -> Result<f64, AsmError> {
+        let open = self.expect("{")?;
+        let value = self.calc_expression()?;
+        self.expect("}")?;
+        // e.g. log 0 or sqrt -1, which would otherwise turn into nonsense integers later
+        if !value.is_finite() {
+            return Err(self.error(&open, format!("{} isn't a finite number", value)));
+        }
+        Ok(value)
+    }
+
+    fn calc_expression(&mut self) -> Result<f64, AsmError> {
+        let left = self.calc_term()?;
+        match self.peek(0) {
+            Some(token) if token.is("}") || token.is(")") => return Ok(left),
+            None => return Ok(left),
+            _ => {}
+        }
+        let op = self.next()?;
+        let right = self.calc_expression()?;
+        if (op.is("/") || op.is("%")) && right == 0.0 {
+            return Err(self.error(&op, "division by zero"));
+        }
+        let int = |f: fn(i64, i64) -> i64| f(left as i64, right as i64) as f64;
+        let truth = |b: bool| if b { 1.0 } else { 0.0 };
+        Ok(match op.text.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => int(|a, b| a & b),
+            "|" => int(|a, b| a | b),
+            "^" => int(|a, b| a ^ b),
+            "<<" => int(|a, b| a.checked_shl(b as u32).unwrap_or(0)),
+            ">>" => int(|a, b| a.checked_shr(b as u32).unwrap_or(0)),
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => truth(left < right),
+            ">" => truth(left > right),
+            "<=" => truth(left <= right),
+            ">=" => truth(left >= right),
+            "==" => truth(left == right),
+            "!=" => truth(left != right),
+            _ => return Err(self.error(&op, format!("unknown operator '{}'", op.text))),
+        })
+    }
+
+    fn calc_term(&mut self) -> Result<f64, AsmError> {
+        let token = self.next()?;
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|v| -v),
+            "~" => Some(|v| !(v as i64) as f64),
+            "!" => Some(|v| if v == 0.0 { 1.0 } else { 0.0 }),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(f) = unary {
+            return Ok(f(self.calc_term()?));
+        }
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expression()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            // The byte already compiled at an address
+            "@" => {
+                let address = self.calc_term()? as i64;
+                let offset = address - PROGRAM_START as i64;
+                if offset < 0 || offset as usize >= self.rom.len() {
+                    return Err(self.error(&token, format!("nothing has been compiled at {:#X}", address)));
+                }
+                Ok(self.rom[offset as usize] as f64)
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(consts::PI),
+            "E" => Ok(consts::E),
+            _ => {
+                if let Some(value) = parse_number(&token.text) {
+                    return Ok(value as f64);
+                }
+                if let Some(value) = self.constants.get(&token.text) {
+                    return Ok(*value);
+                }
+                if let Some(address) = self.labels.get(&token.text) {
+                    return Ok(*address as f64);
+                }
+                Err(self.error(&token, format!("undefined name '{}'", token.text)))
+            }
+        }
+    }
+}
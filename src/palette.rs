@@ -0,0 +1,186 @@
+use crate::display::{ALL_PLANES, PLANE_COUNT};
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+// One color for every value a pixel can have: off, plane 1, plane 2 and both planes
+pub const COLOR_COUNT: usize = 1 << PLANE_COUNT;
+
+// The colors pixels are drawn in, as 0xRRGGBB. Pixel values index straight into it, so every
+// combination of XO-CHIP planes has its own color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    colors: [u32; COLOR_COUNT],
+}
+
+impl Palette {
+    pub fn new(colors: [u32; COLOR_COUNT]) -> Palette {
+        Palette { colors }
+    }
+
+    pub fn color(&self, pixel: u8) -> u32 {
+        self.colors[(pixel & ALL_PLANES) as usize]
+    }
+
+    pub fn set_color(&mut self, pixel: u8, color: u32) {
+        self.colors[(pixel & ALL_PLANES) as usize] = color & 0xFF_FFFF;
+    }
+
+    pub fn colors(&self) -> &[u32; COLOR_COUNT] {
+        &self.colors
+    }
+
+    // Reads a palette file. Each line sets one thing, starting from the classic theme:
+    //
+    //     ; comments start with a semicolon
+    //     theme = amber           ; start from a built-in theme instead
+    //     background = #1A1000
+    //     plane1 = #FFB000        ; pixels on the first plane, the only one before XO-CHIP
+    //     plane2 = #8C5A00
+    //     both = #FFE0A0          ; pixels on both planes
+    pub fn parse_config(text: &str) -> Result<Palette, String> {
+        let mut palette = Palette::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+                None => return Err(format!("line {}: expected KEY = VALUE", index + 1)),
+            };
+            let pixel = match key.as_str() {
+                "theme" => {
+                    let theme: Theme = value.parse().map_err(|e| format!("line {}: {}", index + 1, e))?;
+                    palette = theme.palette();
+                    continue;
+                }
+                "background" => 0,
+                "plane1" => 1,
+                "plane2" => 2,
+                "both" => 3,
+                _ => {
+                    return Err(format!(
+                        "line {}: unknown key '{}', expected theme, background, plane1, plane2 or both",
+                        index + 1,
+                        key
+                    ))
+                }
+            };
+            let color = parse_color(value).map_err(|e| format!("line {}: {}", index + 1, e))?;
+            palette.set_color(pixel, color);
+        }
+        Ok(palette)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Theme::Classic.palette()
+    }
+}
+
+// A theme name, or a comma separated list of colors starting from the background. With only two
+// colors every lit pixel gets the second.
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Palette, String> {
+        if !s.contains(',') {
+            if let Ok(theme) = s.parse::<Theme>() {
+                return Ok(theme.palette());
+            }
+            if parse_color(s).is_err() {
+                return Err(s.parse::<Theme>().unwrap_err());
+            }
+        }
+        let colors = s.split(',').map(|color| parse_color(color.trim())).collect::<Result<Vec<u32>, String>>()?;
+        match colors.len() {
+            2 => Ok(Palette::new([colors[0], colors[1], colors[1], colors[1]])),
+            COLOR_COUNT => Ok(Palette::new([colors[0], colors[1], colors[2], colors[3]])),
+            n => Err(format!("expected 2 or {} colors, got {}", COLOR_COUNT, n)),
+        }
+    }
+}
+
+// #RRGGBB, 0xRRGGBB or just RRGGBB
+fn parse_color(s: &str) -> Result<u32, String> {
+    let hex = s.strip_prefix('#').or_else(|| s.strip_prefix("0x")).or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+    if hex.len() != 6 {
+        return Err(format!("bad color '{}', expected #RRGGBB", s));
+    }
+    u32::from_str_radix(hex, 16).map_err(|_| format!("bad color '{}', expected #RRGGBB", s))
+}
+
+// The built-in palettes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    // White on black
+    Classic,
+    // Monochrome monitors, tinted like their phosphor
+    Amber,
+    Green,
+    // The greens of an old handheld's screen
+    Lcd,
+    HighContrast,
+    // The colors Octo uses, which a lot of XO-CHIP roms were made to look right in
+    Octo,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 6] = [
+        Theme::Classic,
+        Theme::Amber,
+        Theme::Green,
+        Theme::Lcd,
+        Theme::HighContrast,
+        Theme::Octo,
+    ];
+
+    pub fn palette(self) -> Palette {
+        Palette::new(match self {
+            Theme::Classic => [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555],
+            Theme::Amber => [0x1A1000, 0xFFB000, 0x8C5A00, 0xFFE0A0],
+            Theme::Green => [0x0A140A, 0x33FF33, 0x1A8C1A, 0xB0FFB0],
+            Theme::Lcd => [0x9BBC0F, 0x0F380F, 0x8BAC0F, 0x306230],
+            Theme::HighContrast => [0x000000, 0xFFFFFF, 0xFFFF00, 0x00FFFF],
+            Theme::Octo => [0x996600, 0xFFCC00, 0xFF6600, 0x662200],
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Classic => "classic",
+            Theme::Amber => "amber",
+            Theme::Green => "green",
+            Theme::Lcd => "lcd",
+            Theme::HighContrast => "high-contrast",
+            Theme::Octo => "octo",
+        }
+    }
+}
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Theme, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "classic" | "default" => Ok(Theme::Classic),
+            "amber" => Ok(Theme::Amber),
+            "green" | "phosphor" => Ok(Theme::Green),
+            "lcd" => Ok(Theme::Lcd),
+            "high-contrast" | "highcontrast" | "contrast" => Ok(Theme::HighContrast),
+            "octo" => Ok(Theme::Octo),
+            _ => {
+                let names: Vec<&str> = Theme::ALL.iter().map(|t| t.name()).collect();
+                Err(format!("unknown theme '{}', expected one of: {}", s, names.join(", ")))
+            }
+        }
+    }
+}
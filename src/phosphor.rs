@@ -0,0 +1,180 @@
+use crate::palette::Palette;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+// How many frames a pixel takes to fade out with plain "fade"
+pub const DEFAULT_FADE_FRAMES: u8 = 4;
+
+// Sprites are moved by XORing them off and drawing them again, so on the real thing they were
+// only half there most of the time and the slow phosphor of the screen hid it. These stand in
+// for that phosphor. Only what's shown changes, the emulation doesn't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Persistence {
+    // Pixels go dark as soon as they're cleared
+    Off,
+    // Cleared pixels dim towards the background over this many frames
+    Fade(u8),
+    // A pixel is lit if it was lit at the end of either of the last two frames
+    MaxOfTwo,
+}
+
+impl fmt::Display for Persistence {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Persistence::Off => write!(f, "off"),
+            Persistence::Fade(frames) => write!(f, "fade:{}", frames),
+            Persistence::MaxOfTwo => write!(f, "max"),
+        }
+    }
+}
+
+// off, max, fade or fade:FRAMES
+impl FromStr for Persistence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Persistence, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "none" => Ok(Persistence::Off),
+            "max" => Ok(Persistence::MaxOfTwo),
+            "fade" => Ok(Persistence::Fade(DEFAULT_FADE_FRAMES)),
+            other => match other.strip_prefix("fade:").map(str::parse) {
+                Some(Ok(0)) => Ok(Persistence::Off),
+                Some(Ok(frames)) => Ok(Persistence::Fade(frames)),
+                _ => Err(format!("unknown persistence '{}', expected off, max, fade or fade:FRAMES", s)),
+            },
+        }
+    }
+}
+
+// The last lit value of a pixel and how many frames ago it was cleared
+#[derive(Debug, Clone, Copy, Default)]
+struct Glow {
+    pixel: u8,
+    age: u16,
+}
+
+// Turns display buffers into colors with persistence applied. end_frame is called once per
+// emulated frame to age what's on screen, render as often as the window is redrawn.
+#[derive(Debug, Clone)]
+pub struct Phosphor {
+    persistence: Persistence,
+    width: usize,
+    height: usize,
+    glow: Vec<Glow>,
+    // The buffer at the end of the latest frame and the one before it, for MaxOfTwo
+    latest: Vec<u8>,
+    previous: Vec<u8>,
+    colors: Vec<u32>,
+}
+
+impl Phosphor {
+    pub fn new(persistence: Persistence) -> Phosphor {
+        Phosphor {
+            persistence,
+            width: 0,
+            height: 0,
+            glow: Vec::new(),
+            latest: Vec::new(),
+            previous: Vec::new(),
+            colors: Vec::new(),
+        }
+    }
+
+    pub fn persistence(&self) -> Persistence {
+        self.persistence
+    }
+
+    pub fn set_persistence(&mut self, persistence: Persistence) {
+        self.persistence = persistence;
+        self.reset();
+    }
+
+    // Forgets everything that was fading, e.g. after loading a state
+    pub fn reset(&mut self) {
+        self.glow.iter_mut().for_each(|glow| *glow = Glow::default());
+        self.latest.iter_mut().for_each(|pixel| *pixel = 0);
+        self.previous.iter_mut().for_each(|pixel| *pixel = 0);
+    }
+
+    // Starts over whenever the display changes resolution, there's nothing sensible to carry over
+    fn resize(&mut self, width: usize, height: usize) {
+        if self.width == width && self.height == height {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.glow = vec![Glow::default(); width * height];
+        self.latest = vec![0; width * height];
+        self.previous = vec![0; width * height];
+        self.colors = vec![0; width * height];
+    }
+
+    pub fn end_frame(&mut self, buffer: &[u8], width: usize, height: usize) {
+        self.resize(width, height);
+        match self.persistence {
+            Persistence::Off => {}
+            Persistence::Fade(frames) => {
+                for (glow, pixel) in self.glow.iter_mut().zip(buffer) {
+                    if *pixel != 0 {
+                        *glow = Glow { pixel: *pixel, age: 0 };
+                    } else if glow.age <= frames as u16 {
+                        glow.age += 1;
+                    }
+                }
+            }
+            Persistence::MaxOfTwo => {
+                std::mem::swap(&mut self.latest, &mut self.previous);
+                self.latest.copy_from_slice(buffer);
+            }
+        }
+    }
+
+    // The colors of buffer as they should be shown, one 0xRRGGBB per pixel at its native
+    // resolution
+    pub fn render(&mut self, buffer: &[u8], width: usize, height: usize, palette: &Palette) -> &[u32] {
+        self.resize(width, height);
+        match self.persistence {
+            Persistence::Off => {
+                for (color, pixel) in self.colors.iter_mut().zip(buffer) {
+                    *color = palette.color(*pixel);
+                }
+            }
+            Persistence::Fade(frames) => {
+                let background = palette.color(0);
+                let total = frames as u32 + 1;
+                for ((color, pixel), glow) in self.colors.iter_mut().zip(buffer).zip(&self.glow) {
+                    let age = glow.age as u32;
+                    *color = if *pixel != 0 || age == 0 || age >= total {
+                        palette.color(*pixel)
+                    } else {
+                        blend(background, palette.color(glow.pixel), total - age, total)
+                    };
+                }
+            }
+            Persistence::MaxOfTwo => {
+                // Planes are bits, so a pixel gets every plane it had in either frame
+                for ((color, pixel), previous) in self.colors.iter_mut().zip(buffer).zip(&self.previous) {
+                    *color = palette.color(*pixel | *previous);
+                }
+            }
+        }
+        &self.colors
+    }
+}
+
+impl Default for Phosphor {
+    fn default() -> Phosphor {
+        Phosphor::new(Persistence::Off)
+    }
+}
+
+// weight / total of the way from one 0xRRGGBB color to another
+fn blend(from: u32, to: u32, weight: u32, total: u32) -> u32 {
+    let channel = |shift: u32| {
+        let from = (from >> shift) & 0xFF;
+        let to = (to >> shift) & 0xFF;
+        ((from * (total - weight) + to * weight) / total) << shift
+    };
+    channel(16) | channel(8) | channel(0)
+}
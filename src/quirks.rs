@@ -0,0 +1,232 @@
+use crate::error::StateError;
+use crate::ram::{RAM_SIZE, XO_CHIP_RAM_SIZE};
+use crate::state::{self, StateReader, StateWriter};
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+// How far FX55 and FX65 move I after they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStoreIncrement {
+    // I is left alone
+    None,
+    // I += X
+    X,
+    // I += X + 1, the original COSMAC VIP behavior
+    XPlusOne,
+}
+
+// The opcodes whose behavior changed between interpreters over the years. Roms were written
+// against one particular interpreter, so the right set of quirks depends on the rom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY6 and 8XYE shift VY and store the result in VX, rather than shifting VX in place
+    pub shift_uses_vy: bool,
+    pub load_store_increment: LoadStoreIncrement,
+    // BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+    // Sprites are cut off at the edges of the screen instead of wrapping around to the other side
+    pub clip_sprites: bool,
+    // 8XY1, 8XY2 and 8XY3 set VF to 0
+    pub vf_reset: bool,
+    // DXYN waits for the next vblank before drawing, so only one sprite is drawn per frame
+    pub display_wait: bool,
+    // FX0A finishes when the key is released rather than as soon as it is pressed
+    pub key_wait_release: bool,
+    // 64KiB of ram as on XO-CHIP, instead of 4KiB
+    pub extended_memory: bool,
+}
+
+impl Quirks {
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increment: LoadStoreIncrement::XPlusOne,
+            jump_uses_vx: false,
+            clip_sprites: true,
+            vf_reset: true,
+            display_wait: true,
+            key_wait_release: true,
+            extended_memory: false,
+        }
+    }
+
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: LoadStoreIncrement::X,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+            key_wait_release: false,
+            extended_memory: false,
+        }
+    }
+
+    pub fn super_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: LoadStoreIncrement::None,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+            display_wait: false,
+            key_wait_release: false,
+            extended_memory: false,
+        }
+    }
+
+    // Octo's behavior, which XO-CHIP roms are written against
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increment: LoadStoreIncrement::XPlusOne,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            vf_reset: false,
+            display_wait: false,
+            key_wait_release: true,
+            extended_memory: true,
+        }
+    }
+
+    // What most roms written for current interpreters expect
+    pub fn modern() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: LoadStoreIncrement::XPlusOne,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            vf_reset: false,
+            display_wait: false,
+            key_wait_release: true,
+            extended_memory: false,
+        }
+    }
+
+    // How many bytes of ram the machine has
+    pub fn ram_size(&self) -> usize {
+        if self.extended_memory { XO_CHIP_RAM_SIZE } else { RAM_SIZE }
+    }
+}
+
+impl Quirks {
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.shift_uses_vy);
+        w.write_u8(match self.load_store_increment {
+            LoadStoreIncrement::None => 0,
+            LoadStoreIncrement::X => 1,
+            LoadStoreIncrement::XPlusOne => 2,
+        });
+        w.write_bool(self.jump_uses_vx);
+        w.write_bool(self.clip_sprites);
+        w.write_bool(self.vf_reset);
+        w.write_bool(self.display_wait);
+        w.write_bool(self.key_wait_release);
+        w.write_bool(self.extended_memory);
+    }
+
+    pub(crate) fn load_state(r: &mut StateReader) -> Result<Quirks, StateError> {
+        Ok(Quirks {
+            shift_uses_vy: r.read_bool()?,
+            load_store_increment: match r.read_u8()? {
+                0 => LoadStoreIncrement::None,
+                1 => LoadStoreIncrement::X,
+                2 => LoadStoreIncrement::XPlusOne,
+                _ => return Err(StateError::Invalid("load/store increment")),
+            },
+            jump_uses_vx: r.read_bool()?,
+            clip_sprites: r.read_bool()?,
+            vf_reset: r.read_bool()?,
+            display_wait: r.read_bool()?,
+            key_wait_release: r.read_bool()?,
+            extended_memory: r.read_bool()?,
+        })
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::modern()
+    }
+}
+
+// Named quirk sets, so a preset can be picked from the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    CosmacVip,
+    Chip48,
+    SuperChip,
+    XoChip,
+    Modern,
+}
+
+// Roms that only work with a particular preset, by the crc32 of the whole rom. The bundled roms
+// not listed here only differ in timing between presets, so they're left to the default.
+const ROM_PRESETS: [(u32, Preset); 2] = [
+    // BLINKY shifts VX in place, and its maze falls apart when 8XY6 shifts VY
+    (0x9D30_7E90, Preset::Chip48),
+    // BLITZ's buildings run off the bottom of the screen, wrapping them crashes the plane at once
+    (0xD106_C808, Preset::Chip48),
+];
+
+impl Preset {
+    pub const ALL: [Preset; 5] = [
+        Preset::CosmacVip,
+        Preset::Chip48,
+        Preset::SuperChip,
+        Preset::XoChip,
+        Preset::Modern,
+    ];
+
+    // The preset a known rom needs, None for roms that aren't known to need one
+    pub fn for_rom(data: &[u8]) -> Option<Preset> {
+        let hash = state::crc32(data);
+        ROM_PRESETS.iter().find(|(rom, _)| *rom == hash).map(|(_, preset)| *preset)
+    }
+
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Preset::CosmacVip => Quirks::cosmac_vip(),
+            Preset::Chip48 => Quirks::chip48(),
+            Preset::SuperChip => Quirks::super_chip(),
+            Preset::XoChip => Quirks::xo_chip(),
+            Preset::Modern => Quirks::modern(),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::CosmacVip => "vip",
+            Preset::Chip48 => "chip48",
+            Preset::SuperChip => "schip",
+            Preset::XoChip => "xochip",
+            Preset::Modern => "modern",
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Preset, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac" | "cosmac-vip" => Ok(Preset::CosmacVip),
+            "chip48" | "chip-48" => Ok(Preset::Chip48),
+            "schip" | "superchip" | "super-chip" => Ok(Preset::SuperChip),
+            "xochip" | "xo-chip" | "octo" => Ok(Preset::XoChip),
+            "modern" => Ok(Preset::Modern),
+            _ => {
+                let names: Vec<&str> = Preset::ALL.iter().map(|p| p.name()).collect();
+                Err(format!("unknown quirks preset '{}', expected one of: {}", s, names.join(", ")))
+            }
+        }
+    }
+}
@@ -1,14 +1,25 @@
+use crate::error::{ExecError, StateError};
+use crate::state::{StateReader, StateWriter};
+
+// The chip 8 architecture has ram composed of 4096 8 bit addresses
+pub const RAM_SIZE: usize = 0x1000;
+// XO-CHIP extends that to the full 64KiB a 16 bit I register can reach
+pub const XO_CHIP_RAM_SIZE: usize = 0x10000;
+// Where the 4x5 hex digit sprites live, 5 bytes each
+pub const FONT_START: u16 = 0;
+// Where the SUPER-CHIP 8x10 hex digit sprites live, 10 bytes each
+pub const BIG_FONT_START: u16 = 80;
+
 pub struct Ram {
-    // The chip 8 architecture has ram composed of 4096 8 but addresses
-    mem: [u8; 4096],
+    mem: Vec<u8>,
 }
 
 impl Ram {
     pub fn new() -> Ram {
-        let mut ram = Ram {mem: [0; 4096]};
+        let mut ram = Ram {mem: vec![0; RAM_SIZE]};
 
         // nested array of hex values that creates sprites for the hex following hex values
-        // These sprites get loaded into memory starting at position 0
+        // These sprites get loaded into memory starting at FONT_START
         let sprites: [[u8; 5]; 16] = [
             [0xF0, 0x90, 0x90, 0x90, 0xF0], //sprite for 0
             [0x20, 0x60, 0x20, 0x20, 0x70], //sprite for 1
@@ -30,23 +41,88 @@ impl Ram {
 
             // i is an interating position in memory used to load the sprites into mem starting at
             // position 0, that is what the nested for loop does
-        let mut i = 0;
+        let mut i = FONT_START as usize;
         for sprite in sprites.iter() {
             for ch in sprite {
                 ram.mem[i] = *ch;
                 i += 1;
             }
         }
+
+        // The same digits drawn 8 pixels wide and 10 tall, for the SUPER-CHIP FX30 instruction
+        let big_sprites: [[u8; 10]; 16] = [
+            [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF], //sprite for 0
+            [0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF], //sprite for 1
+            [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF], //sprite for 2
+            [0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF], //sprite for 3
+            [0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03], //sprite for 4
+            [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF], //sprite for 5
+            [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF], //sprite for 6
+            [0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18], //sprite for 7
+            [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF], //sprite for 8
+            [0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF], //sprite for 9
+            [0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3], //sprite for A
+            [0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC], //sprite for B
+            [0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C], //sprite for C
+            [0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC], //sprite for D
+            [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF], //sprite for E
+            [0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0] //sprite for F
+        ];
+        let mut i = BIG_FONT_START as usize;
+        for sprite in big_sprites.iter() {
+            for ch in sprite {
+                ram.mem[i] = *ch;
+                i += 1;
+            }
+        }
         ram
     }
+
+    pub fn size(&self) -> usize {
+        self.mem.len()
+    }
+
+    // Grows or shrinks ram to size bytes, keeping whatever fits. New memory starts out as 0.
+    pub fn resize(&mut self, size: usize) {
+        self.mem.resize(size, 0);
+    }
+
     // pretty much just getters & setters for memory
     // write_byte takes in an address and a value, and sets the given address to the given value
-    pub fn write_byte(&mut self, address: u16, value: u8) {
-        self.mem[address as usize] = value;
+    pub fn write_byte(&mut self, address: u16, value: u8) -> Result<(), ExecError> {
+        match self.mem.get_mut(address as usize) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            }
+            None => Err(ExecError::MemoryOutOfBounds { addr: address }),
+        }
+    }
+
+    pub(crate) fn save_state(&self, w: &mut StateWriter) {
+        w.write_u32(self.mem.len() as u32);
+        w.write_bytes(&self.mem);
+    }
+
+    pub(crate) fn load_state(r: &mut StateReader) -> Result<Ram, StateError> {
+        let size = r.read_u32()? as usize;
+        if size != RAM_SIZE && size != XO_CHIP_RAM_SIZE {
+            return Err(StateError::Invalid("ram size"));
+        }
+        Ok(Ram { mem: r.read_bytes(size)?.to_vec() })
     }
-    // read_byte takes in an address and a value, and sets value to the value currently in address
-    pub fn read_byte(&self, address: u16) -> u8 {
-        self.mem[address as usize]
+
+    // read_byte takes in an address and returns the value currently stored there
+    pub fn read_byte(&self, address: u16) -> Result<u8, ExecError> {
+        self.mem.get(address as usize)
+            .copied()
+            .ok_or(ExecError::MemoryOutOfBounds { addr: address })
     }
 
 }
+
+impl Default for Ram {
+    fn default() -> Ram {
+        Ram::new()
+    }
+}
@@ -0,0 +1,185 @@
+use crate::chip8::Chip8;
+use crate::error::StateError;
+use std::collections::VecDeque;
+
+// Ten seconds of frames
+pub const DEFAULT_REWIND_FRAMES: usize = 600;
+
+// How to get from a state back to the one recorded before it
+enum Delta {
+    // The previous state XORed with the next one and run length encoded. Consecutive frames
+    // mostly differ in a few registers and pixels, so this is usually tiny.
+    Xor(Vec<u8>),
+    // The whole previous state, for when the two aren't the same size (e.g. the display changed
+    // resolution in between)
+    Full(Vec<u8>),
+}
+
+impl Delta {
+    fn between(previous: &[u8], next: &[u8]) -> Delta {
+        if previous.len() != next.len() {
+            return Delta::Full(previous.to_vec());
+        }
+        // Pairs of (unchanged byte count, changed byte count) followed by the changed bytes XORed
+        let mut encoded = Vec::new();
+        let mut i = 0;
+        while i < previous.len() {
+            let start = i;
+            while i < previous.len() && previous[i] == next[i] {
+                i += 1;
+            }
+            let same = i - start;
+            let start = i;
+            while i < previous.len() && previous[i] != next[i] {
+                i += 1;
+            }
+            write_varint(&mut encoded, same);
+            write_varint(&mut encoded, i - start);
+            encoded.extend(previous[start..i].iter().zip(&next[start..i]).map(|(a, b)| a ^ b));
+        }
+        Delta::Xor(encoded)
+    }
+
+    // Turns state back into the state the delta was made from
+    fn undo(&self, state: &mut Vec<u8>) {
+        let encoded = match self {
+            Delta::Full(previous) => {
+                state.clone_from(previous);
+                return;
+            }
+            Delta::Xor(encoded) => encoded,
+        };
+        let mut pos = 0;
+        let mut i = 0;
+        while pos < encoded.len() {
+            i += read_varint(encoded, &mut pos);
+            let changed = read_varint(encoded, &mut pos);
+            for (byte, xor) in state[i..i + changed].iter_mut().zip(&encoded[pos..pos + changed]) {
+                *byte ^= xor;
+            }
+            i += changed;
+            pos += changed;
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Delta::Xor(bytes) | Delta::Full(bytes) => bytes.len(),
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+// A ring buffer of the last few seconds of frames, for stepping backwards. record is called
+// after every frame and step_back winds the emulator back one frame at a time. Only the newest
+// state is kept whole, the rest are stored as deltas going backwards from it.
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    // Newest at the back. Each delta turns the state after it into the one before.
+    history: VecDeque<Delta>,
+}
+
+impl Rewind {
+    // capacity is how many frames back can be stepped, 0 turns rewinding off
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            capacity,
+            latest: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Forgets the oldest frames if the history is now too long
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        if self.history.len() > capacity {
+            let excess = self.history.len() - capacity;
+            self.history.drain(..excess);
+        }
+        if capacity == 0 {
+            self.latest = None;
+        }
+    }
+
+    // How many frames back can currently be stepped
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    // Bytes used by the stored history, to get an idea of how well it compresses
+    pub fn memory_used(&self) -> usize {
+        self.latest.as_ref().map_or(0, |state| state.len())
+            + self.history.iter().map(Delta::len).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.history.clear();
+    }
+
+    pub fn record(&mut self, chip8: &Chip8) {
+        if self.capacity == 0 {
+            return;
+        }
+        let state = chip8.save_state();
+        if let Some(previous) = self.latest.take() {
+            self.history.push_back(Delta::between(&previous, &state));
+            if self.history.len() > self.capacity {
+                self.history.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    // Loads the frame before the newest one into chip8 and makes it the newest. Returns false,
+    // leaving chip8 alone, when there's no history left. The history is untouched if chip8
+    // rejects the state.
+    pub fn step_back(&mut self, chip8: &mut Chip8) -> Result<bool, StateError> {
+        let (delta, latest) = match (self.history.back(), self.latest.as_ref()) {
+            (Some(delta), Some(latest)) => (delta, latest),
+            _ => return Ok(false),
+        };
+        let mut previous = latest.clone();
+        delta.undo(&mut previous);
+        chip8.load_state(&previous)?;
+        self.history.pop_back();
+        self.latest = Some(previous);
+        Ok(true)
+    }
+}
+
+impl Default for Rewind {
+    fn default() -> Rewind {
+        Rewind::new(DEFAULT_REWIND_FRAMES)
+    }
+}
@@ -0,0 +1,71 @@
+use rand::Rng;
+
+// Where CXNN gets its random numbers from. Anything implementing this can be handed to
+// Chip8::set_random_source, e.g. to script the numbers a rom sees in a test.
+pub trait RandomSource: Send + Sync {
+    // Returns a byte anywhere in 0..=255
+    fn next_byte(&mut self) -> u8;
+
+    // The generator's position, so a save state can carry on the same sequence. Sources that
+    // can't be saved return None and are left alone when a state is loaded.
+    fn save_state(&self) -> Option<u64> {
+        None
+    }
+
+    fn load_state(&mut self, _state: u64) {}
+}
+
+// A small xorshift64* generator. The same seed always produces the same bytes, so a run can be
+// replayed exactly by reusing its seed.
+#[derive(Debug, Clone)]
+pub struct SeededRng {
+    seed: u64,
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> SeededRng {
+        // Run the seed through splitmix64 so similar seeds give unrelated sequences, and so the
+        // state is never 0, which xorshift can't escape from
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        SeededRng {
+            seed,
+            state: if z == 0 { 0x9E37_79B9_7F4A_7C15 } else { z },
+        }
+    }
+
+    // Picks a seed from the host's random number generator
+    pub fn random_seed() -> u64 {
+        rand::thread_rng().gen()
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl RandomSource for SeededRng {
+    fn next_byte(&mut self) -> u8 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        // The top bits of xorshift64* are the best distributed
+        (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn save_state(&self) -> Option<u64> {
+        Some(self.state)
+    }
+
+    fn load_state(&mut self, state: u64) {
+        // A zero state would only ever produce zeros
+        if state != 0 {
+            self.state = state;
+        }
+    }
+}
@@ -0,0 +1,61 @@
+// Where the screen goes when it's scaled up into a bigger area
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    // How many pixels across and down each chip8 pixel becomes
+    pub scale: usize,
+}
+
+impl Viewport {
+    // The largest whole number scale that fits a width x height screen into the target, centered
+    // with the rest left as borders. Whole numbers keep every pixel the same size. The scale is
+    // never below 1, so a target that's too small shows the top left of the screen.
+    pub fn fit(width: usize, height: usize, target_width: usize, target_height: usize) -> Viewport {
+        let scale = match (target_width.checked_div(width), target_height.checked_div(height)) {
+            (Some(across), Some(down)) => across.min(down).max(1),
+            _ => 1,
+        };
+        Viewport {
+            x: target_width.saturating_sub(width * scale) / 2,
+            y: target_height.saturating_sub(height * scale) / 2,
+            width: width * scale,
+            height: height * scale,
+            scale,
+        }
+    }
+}
+
+// Draws width x height colors into target as large as fits, filling around them with border.
+// Returns where they went.
+pub fn blit(colors: &[u32], width: usize, height: usize, target: &mut [u32], target_width: usize,
+            target_height: usize, border: u32) -> Viewport {
+    assert_eq!(colors.len(), width * height, "color count doesn't match the size");
+    assert_eq!(target.len(), target_width * target_height, "target size doesn't match");
+    let viewport = Viewport::fit(width, height, target_width, target_height);
+    target.fill(border);
+
+    for (y, row) in colors.chunks(width.max(1)).enumerate() {
+        let top = viewport.y + y * viewport.scale;
+        if top >= target_height {
+            break;
+        }
+        let line = &mut target[top * target_width..(top + 1) * target_width];
+        for (x, color) in row.iter().enumerate() {
+            let left = viewport.x + x * viewport.scale;
+            if left >= target_width {
+                break;
+            }
+            let right = (left + viewport.scale).min(target_width);
+            line[left..right].fill(*color);
+        }
+        // The rest of the rows of these pixels are the same as the first
+        let bottom = (top + viewport.scale).min(target_height);
+        for next in top + 1..bottom {
+            target.copy_within(top * target_width..(top + 1) * target_width, next * target_width);
+        }
+    }
+    viewport
+}
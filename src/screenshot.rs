@@ -0,0 +1,101 @@
+use crate::image::encode_png;
+use crate::palette::Palette;
+use crate::scale;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Where screenshots go and how they look
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScreenshotOptions {
+    // Made if it doesn't exist yet
+    pub directory: PathBuf,
+    // Each chip8 pixel becomes scale x scale pixels in the image, 1 keeps the native resolution
+    pub scale: usize,
+    pub palette: Palette,
+}
+
+impl ScreenshotOptions {
+    pub fn new() -> ScreenshotOptions {
+        ScreenshotOptions::default()
+    }
+}
+
+impl Default for ScreenshotOptions {
+    fn default() -> ScreenshotOptions {
+        ScreenshotOptions {
+            directory: PathBuf::from("."),
+            scale: 1,
+            palette: Palette::default(),
+        }
+    }
+}
+
+// A png of a display buffer, one byte per pixel as from Chip8::get_display_buffer
+pub fn encode(buffer: &[u8], width: usize, height: usize, scale: usize, palette: &Palette) -> Vec<u8> {
+    let colors: Vec<u32> = buffer.iter().map(|pixel| palette.color(*pixel)).collect();
+    let scale = scale.max(1);
+    if scale == 1 {
+        return encode_png(width, height, &colors);
+    }
+    let mut scaled = vec![0; colors.len() * scale * scale];
+    scale::blit(&colors, width, height, &mut scaled, width * scale, height * scale, 0);
+    encode_png(width * scale, height * scale, &scaled)
+}
+
+// Writes a png of the buffer into options.directory, named after the time it was taken, and
+// returns its path. Never overwrites anything, two taken in the same millisecond get numbered.
+pub fn save(buffer: &[u8], width: usize, height: usize, options: &ScreenshotOptions) -> io::Result<PathBuf> {
+    let png = encode(buffer, width, height, options.scale, &options.palette);
+    fs::create_dir_all(&options.directory)?;
+    let name = file_name(SystemTime::now());
+    let mut path = options.directory.join(format!("{}.png", name));
+    let mut attempt = 0;
+    loop {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(&png)?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                attempt += 1;
+                path = options.directory.join(format!("{}-{}.png", name, attempt));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// chip8-YYYYMMDD-HHMMSS-mmm in UTC, which sorts in the order they were taken
+fn file_name(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time_of_day = seconds % 86400;
+    format!(
+        "chip8-{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+// The date days after 1970-01-01, from Howard Hinnant's date algorithms
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months counted from March, so the leap day comes last
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
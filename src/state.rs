@@ -0,0 +1,161 @@
+use crate::error::StateError;
+
+// Save state layout, all numbers little endian:
+//   magic "CH8S" | version u16 | rom crc32 u32 | payload length u32 | payload | crc32 u32
+// The trailing crc32 covers everything before it.
+pub const MAGIC: &[u8; 4] = b"CH8S";
+pub const VERSION: u16 = 3;
+const HEADER_LEN: usize = 4 + 2 + 4 + 4;
+
+// Wraps a payload in the save state header and checksum
+pub fn encode(rom_hash: u32, payload: &[u8]) -> Vec<u8> {
+    let mut w = StateWriter::new();
+    w.write_bytes(MAGIC);
+    w.write_u16(VERSION);
+    w.write_u32(rom_hash);
+    w.write_u32(payload.len() as u32);
+    w.write_bytes(payload);
+    let checksum = crc32(&w.buffer);
+    w.write_u32(checksum);
+    w.into_inner()
+}
+
+// Checks the header and checksum of a save state and returns its payload. The state has to
+// have been made while the same rom was loaded.
+pub fn decode(data: &[u8], rom_hash: u32) -> Result<&[u8], StateError> {
+    let mut r = StateReader::new(data);
+    if r.read_bytes(4)? != MAGIC {
+        return Err(StateError::BadMagic);
+    }
+    let version = r.read_u16()?;
+    if version != VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    let saved_rom_hash = r.read_u32()?;
+    let len = r.read_u32()? as usize;
+    let payload = r.read_bytes(len)?;
+    let checksum = r.read_u32()?;
+    if checksum != crc32(&data[..HEADER_LEN + len]) {
+        return Err(StateError::ChecksumMismatch);
+    }
+    if saved_rom_hash != rom_hash {
+        return Err(StateError::RomMismatch);
+    }
+    Ok(payload)
+}
+
+// CRC-32 as used by zip and png
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+// Appends values to a save state payload
+pub struct StateWriter {
+    buffer: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { buffer: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+// Reads values back out of a save state payload in the order they were written
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data, position: 0 }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("bool")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.read_bytes(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self.position.checked_add(len).ok_or(StateError::Truncated)?;
+        let bytes = self.data.get(self.position..end).ok_or(StateError::Truncated)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    // Fails unless everything has been read, so leftover bytes don't go unnoticed
+    pub fn finish(&self) -> Result<(), StateError> {
+        if self.position == self.data.len() {
+            Ok(())
+        } else {
+            Err(StateError::Invalid("trailing data"))
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use rust_chip8::assembler::{assemble, assemble_file};
+use rust_chip8::disasm::Disassembly;
+use rust_chip8::AsmError;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+fn error(source: &str) -> AsmError {
+    assemble(source).expect_err("the source shouldn't assemble")
+}
+
+// A fresh directory for a test's files
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("chip8-asm-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn assembles_instructions() {
+    let rom = assemble("CLS\nld v1, 0x2A\nADD VA, VB\nDRW V0, V1, 5\nLD I, LONG 0xBEEF\nld [i], v3\n").unwrap();
+    assert_eq!(rom, [0x00, 0xE0, 0x61, 0x2A, 0x8A, 0xB4, 0xD0, 0x15, 0xF0, 0x00, 0xBE, 0xEF, 0xF3, 0x55]);
+}
+
+#[test]
+fn labels_can_be_used_before_they_are_defined() {
+    let rom = assemble("main:\n    CALL sub\n    JP main\nsub:\n    RET\n").unwrap();
+    assert_eq!(rom, [0x22, 0x04, 0x12, 0x00, 0x00, 0xEE]);
+}
+
+#[test]
+fn dollar_is_the_current_address() {
+    let rom = assemble("CLS\nJP $\nJP $ - 4\n").unwrap();
+    assert_eq!(rom, [0x00, 0xE0, 0x12, 0x02, 0x12, 0x00]);
+}
+
+#[test]
+fn constants_are_expressions() {
+    let source = "\
+SPEED = 2 * 3
+LIMIT equ SPEED + 1
+    LD V0, SPEED
+    LD V1, LIMIT
+    LD V2, -1
+";
+    assert_eq!(assemble(source).unwrap(), [0x60, 0x06, 0x61, 0x07, 0x62, 0xFF]);
+}
+
+#[test]
+fn constants_can_come_after_they_are_used() {
+    assert_eq!(assemble("LD V0, LATER\nLATER = 9\n").unwrap(), [0x60, 0x09]);
+}
+
+#[test]
+fn operators_follow_c_precedence() {
+    let rom = assemble("\
+db 1 + 2 * 3
+db (1 + 2) * 3
+db 1 | 6 ^ 3 & 6
+db 1 << 2 + 1
+db 100 / 7 % 4
+db 10 - 4 - 3
+db ~0 & 0xF0
+db 0b1010_0101
+").unwrap();
+    // 1 | (6 ^ (3 & 6)) rather than ((1 | 6) ^ 3) & 6
+    assert_eq!(rom, [7, 9, 5, 8, 2, 3, 0xF0, 0xA5]);
+}
+
+#[test]
+fn db_dw_and_strings() {
+    let rom = assemble("db 1, 0xFF, \"Hi\\n\"\ndw 0x1234, label\nlabel: db 'a'\n").unwrap();
+    assert_eq!(rom, [0x01, 0xFF, b'H', b'i', b'\n', 0x12, 0x34, 0x02, 0x09, b'a']);
+}
+
+#[test]
+fn includes_are_relative_to_the_including_file() {
+    let dir = scratch_dir("relative");
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(dir.join("main.asm"), "include \"lib/sprites.asm\"\nJP main\n").unwrap();
+    fs::write(dir.join("lib").join("sprites.asm"), "main: include \"digits.asm\"\n").unwrap();
+    fs::write(dir.join("lib").join("digits.asm"), "db 0xF0, 0x90\n").unwrap();
+    assert_eq!(assemble_file(dir.join("main.asm")).unwrap(), [0xF0, 0x90, 0x12, 0x00]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn including_itself_is_an_error() {
+    let dir = scratch_dir("recursive");
+    fs::write(dir.join("loop.asm"), "CLS\ninclude \"loop.asm\"\n").unwrap();
+    let e = assemble_file(dir.join("loop.asm")).unwrap_err();
+    assert_eq!((e.line, e.column, e.message.as_str()), (2, 1, "includes are nested too deeply"));
+    assert!(e.file.ends_with("loop.asm"), "{}", e.file);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn missing_include_names_the_file() {
+    let e = error("include \"no-such-file.asm\"");
+    assert_eq!((e.line, e.column), (1, 1));
+    assert!(e.message.starts_with("can't read no-such-file.asm"), "{}", e.message);
+}
+
+#[test]
+fn errors_point_at_the_line_and_column() {
+    let e = error("CLS\n    LD V0, 0x100\n");
+    assert_eq!(e, AsmError { file: String::from("<input>"), line: 2, column: 12, message: String::from("256 doesn't fit in a byte") });
+    assert_eq!(e.to_string(), "<input>:2:12: 256 doesn't fit in a byte");
+
+    let e = error("JP nowhere");
+    assert_eq!((e.line, e.column, e.message.as_str()), (1, 4, "undefined name 'nowhere'"));
+    let e = error("  FROB V0");
+    assert_eq!((e.line, e.column, e.message.as_str()), (1, 3, "unknown instruction 'FROB'"));
+    let e = error("DRW V0, V1");
+    assert_eq!((e.line, e.column, e.message.as_str()), (1, 1, "wrong operands for DRW"));
+    let e = error("a: CLS\na: CLS");
+    assert_eq!((e.line, e.column, e.message.as_str()), (2, 1, "'a' is already defined"));
+    let e = error("db 1 / 0");
+    assert_eq!((e.line, e.column, e.message.as_str()), (1, 6, "division by zero"));
+    let e = error("FOO = BAR\nBAR = FOO\nLD V0, FOO");
+    assert_eq!(e.message, "'FOO' is defined in terms of itself");
+    let e = error("db \"open");
+    assert_eq!((e.line, e.column, e.message.as_str()), (1, 4, "unterminated string"));
+}
+
+#[test]
+fn disassembly_assembles_back_to_the_same_rom() {
+    let source = "\
+main:
+    LD V0, 0
+    SE V0, 1
+    LD I, LONG sprite
+    CALL draw
+    JP main
+draw:
+    LD I, sprite
+    DRW V0, V1, 2
+    RET
+sprite:
+    db 0x3C, 0x42
+";
+    let rom = assemble(source).unwrap();
+    let listing = Disassembly::new(&rom).to_string();
+    assert_eq!(assemble(&listing).unwrap(), rom, "{}", listing);
+}
+
+#[test]
+fn bundled_roms_survive_a_round_trip() {
+    for name in ["PONG", "BRIX", "INVADERS"].iter() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("data").join(name);
+        let rom = fs::read(path).unwrap();
+        let listing = Disassembly::new(&rom).to_string();
+        assert_eq!(assemble(&listing).unwrap(), rom, "{}", name);
+    }
+}
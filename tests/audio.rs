@@ -0,0 +1,68 @@
+// The beeper as heard through WavSink, which is what headless runs record with --wav
+use rust_chip8::audio::{AudioBackend, WavSink, DEFAULT_VOLUME, FRAMES_PER_SECOND};
+use rust_chip8::headless::{self, InputScript, RunLength};
+use rust_chip8::Chip8;
+use std::io::Cursor;
+
+const SAMPLE_RATE: u32 = 44_100;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAMES_PER_SECOND) as usize;
+const HEADER_LEN: usize = 44;
+
+fn u32_at(wav: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([wav[offset], wav[offset + 1], wav[offset + 2], wav[offset + 3]])
+}
+
+fn samples(wav: &[u8]) -> Vec<i16> {
+    wav[HEADER_LEN..].chunks(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect()
+}
+
+#[test]
+fn empty_wav_has_a_valid_header() {
+    let sink = WavSink::new(Cursor::new(Vec::new()), SAMPLE_RATE).unwrap();
+    let wav = sink.finish().unwrap().into_inner();
+    assert_eq!(wav.len(), HEADER_LEN);
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(u32_at(&wav, 4), 36);
+    assert_eq!(&wav[8..16], b"WAVEfmt ");
+    assert_eq!(u32_at(&wav, 24), SAMPLE_RATE);
+    assert_eq!(&wav[36..40], b"data");
+    assert_eq!(u32_at(&wav, 40), 0);
+}
+
+#[test]
+fn finish_patches_the_sizes() {
+    let mut sink = WavSink::new(Cursor::new(Vec::new()), SAMPLE_RATE).unwrap();
+    for frame in 0..FRAMES_PER_SECOND {
+        sink.update(frame < 10, None).unwrap();
+    }
+    // A second of frames is exactly a second of samples
+    assert_eq!(sink.samples_written(), SAMPLE_RATE);
+    let wav = sink.finish().unwrap().into_inner();
+    let data_len = SAMPLE_RATE * 2;
+    assert_eq!(wav.len(), HEADER_LEN + data_len as usize);
+    assert_eq!(u32_at(&wav, 4), 36 + data_len);
+    assert_eq!(u32_at(&wav, 40), data_len);
+}
+
+#[test]
+fn rom_setting_the_sound_timer_beeps_until_it_runs_out() {
+    // VA := 30, ST := VA, then loop forever
+    let rom = [0x6A, 0x1E, 0xFA, 0x18, 0x12, 0x04];
+    let mut chip8 = Chip8::with_seed(0);
+    chip8.load_rom(&rom).unwrap();
+    let mut sink = WavSink::new(Cursor::new(Vec::new()), SAMPLE_RATE).unwrap();
+    headless::run(&mut chip8, RunLength::Frames(60), &InputScript::new(), |chip8, _| {
+        sink.update(chip8.is_beeping(), chip8.audio_pattern().as_ref()).unwrap();
+    })
+    .unwrap();
+    let samples = samples(&sink.finish().unwrap().into_inner());
+    assert_eq!(samples.len(), 60 * SAMPLES_PER_FRAME);
+
+    // The timer is set during the first frame and ticked at the end of each one, so it's heard
+    // for 29 frames
+    let loud = (DEFAULT_VOLUME * i16::MAX as f32) as i16;
+    let (beep, silence) = samples.split_at(29 * SAMPLES_PER_FRAME);
+    assert!(beep.iter().all(|sample| *sample == loud || *sample == -loud));
+    assert!(beep.contains(&loud) && beep.contains(&-loud));
+    assert!(silence.iter().all(|sample| *sample == 0));
+}
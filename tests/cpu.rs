@@ -0,0 +1,978 @@
+// One or more tests for every opcode, run against a bare Cpu and Bus. Each test loads a few
+// opcodes at PROGRAM_START, sets up the registers it needs and checks what one step did.
+use rust_chip8::bus::Bus;
+use rust_chip8::cpu::{Cpu, Registers, PROGRAM_START, STACK_SIZE};
+use rust_chip8::quirks::LoadStoreIncrement;
+use rust_chip8::ram::BIG_FONT_START;
+use rust_chip8::{Chip8, ExecError, Instruction, Quirks, RandomSource, StepOutcome};
+
+struct Machine {
+    cpu: Cpu,
+    bus: Bus,
+}
+
+impl Machine {
+    // The modern quirks with V registers set from pairs of (register, value)
+    fn new(program: &[u16], v: &[(usize, u8)]) -> Machine {
+        Machine::with(program, v, Quirks::modern())
+    }
+
+    fn with(program: &[u16], v: &[(usize, u8)], quirks: Quirks) -> Machine {
+        let mut registers = Registers::default();
+        for (x, value) in v {
+            registers.v[*x] = *value;
+        }
+        let mut cpu = Cpu::with_registers(registers);
+        cpu.set_quirks(quirks);
+        let mut bus = Bus::new();
+        bus.set_ram_size(quirks.ram_size());
+        let mut machine = Machine { cpu, bus };
+        for (i, opcode) in program.iter().enumerate() {
+            machine.poke(PROGRAM_START + i as u16 * 2, &opcode.to_be_bytes());
+        }
+        machine
+    }
+
+    fn step(&mut self) -> StepOutcome {
+        self.cpu.run_instruction(&mut self.bus).expect("the instruction should run")
+    }
+
+    fn try_step(&mut self) -> Result<StepOutcome, ExecError> {
+        self.cpu.run_instruction(&mut self.bus)
+    }
+
+    fn v(&self, x: usize) -> u8 {
+        self.cpu.registers().v[x]
+    }
+
+    fn pc(&self) -> u16 {
+        self.cpu.pc()
+    }
+
+    fn set_i(&mut self, i: u16) {
+        self.cpu.set_i(i);
+    }
+
+    fn poke(&mut self, address: u16, bytes: &[u8]) {
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.bus.ram_write_byte(address + offset as u16, *byte).unwrap();
+        }
+    }
+
+    fn peek(&self, address: u16, len: usize) -> Vec<u8> {
+        (0..len).map(|offset| self.bus.ram_read_byte(address + offset as u16).unwrap()).collect()
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.bus.get_display_buffer()[y * self.bus.display_width() + x]
+    }
+
+    fn lit(&self) -> usize {
+        self.bus.get_display_buffer().iter().filter(|pixel| **pixel != 0).count()
+    }
+}
+
+// Always returns the same byte
+struct Fixed(u8);
+
+impl RandomSource for Fixed {
+    fn next_byte(&mut self) -> u8 {
+        self.0
+    }
+}
+
+const DATA: u16 = 0x300;
+
+// 00E0, 00EE and the SUPER-CHIP / XO-CHIP 00XX instructions
+
+#[test]
+fn clear_screen() {
+    let mut m = Machine::new(&[0xA300, 0xD011, 0x00E0], &[]);
+    m.poke(DATA, &[0xFF]);
+    m.step();
+    m.step();
+    assert_eq!(m.lit(), 8);
+    m.step();
+    assert_eq!(m.lit(), 0);
+    assert_eq!(m.pc(), 0x206);
+}
+
+#[test]
+fn clear_only_touches_selected_planes() {
+    let mut m = Machine::new(&[0xA300, 0xF301, 0xD011, 0xF201, 0x00E0], &[]);
+    m.poke(DATA, &[0x80, 0x80]);
+    for _ in 0..5 {
+        m.step();
+    }
+    assert_eq!(m.pixel(0, 0), 1);
+}
+
+#[test]
+fn call_and_return() {
+    let mut m = Machine::new(&[0x2206, 0x0000, 0x0000, 0x00EE], &[]);
+    m.step();
+    assert_eq!(m.pc(), 0x206);
+    assert_eq!(m.cpu.stack(), &[0x202]);
+    m.step();
+    assert_eq!(m.pc(), 0x202);
+    assert!(m.cpu.stack().is_empty());
+}
+
+#[test]
+fn return_with_empty_stack_fails() {
+    let mut m = Machine::new(&[0x00EE], &[]);
+    assert_eq!(m.try_step(), Err(ExecError::StackUnderflow { pc: 0x200 }));
+}
+
+#[test]
+fn call_with_full_stack_fails() {
+    let registers = Registers { stack: vec![0x200; STACK_SIZE], ..Registers::default() };
+    let mut m = Machine::new(&[0x2200], &[]);
+    m.cpu.set_registers(registers);
+    assert_eq!(m.try_step(), Err(ExecError::StackOverflow { pc: 0x200 }));
+}
+
+#[test]
+fn sixteen_calls_fit_on_the_stack() {
+    let mut m = Machine::new(&[0x2200], &[]);
+    for _ in 0..STACK_SIZE {
+        m.step();
+    }
+    assert_eq!(m.cpu.stack().len(), STACK_SIZE);
+}
+
+#[test]
+fn scroll_down() {
+    let mut m = Machine::new(&[0xA300, 0xD011, 0x00C3], &[]);
+    m.poke(DATA, &[0x80]);
+    for _ in 0..3 {
+        m.step();
+    }
+    assert_eq!(m.pixel(0, 0), 0);
+    assert_eq!(m.pixel(0, 3), 1);
+}
+
+#[test]
+fn scroll_up() {
+    let mut m = Machine::new(&[0xA300, 0xD011, 0x00D2], &[(1, 5)]);
+    m.poke(DATA, &[0x80]);
+    for _ in 0..3 {
+        m.step();
+    }
+    assert_eq!(m.pixel(0, 5), 0);
+    assert_eq!(m.pixel(0, 3), 1);
+}
+
+#[test]
+fn scroll_right_and_left_move_four_pixels() {
+    let mut m = Machine::new(&[0xA300, 0xD011, 0x00FB, 0x00FC, 0x00FC], &[(0, 8)]);
+    m.poke(DATA, &[0x80]);
+    m.step();
+    m.step();
+    m.step();
+    assert_eq!(m.pixel(12, 0), 1);
+    m.step();
+    m.step();
+    assert_eq!(m.pixel(4, 0), 1);
+    assert_eq!(m.lit(), 1);
+}
+
+#[test]
+fn exit_stops_the_cpu() {
+    let mut m = Machine::new(&[0x00FD, 0x6001], &[]);
+    assert_eq!(m.step(), StepOutcome::Exited);
+    assert!(m.cpu.has_exited());
+    assert_eq!(m.step(), StepOutcome::Exited);
+    assert_eq!(m.v(0), 0);
+    assert_eq!(m.pc(), 0x200);
+}
+
+#[test]
+fn hires_and_lores() {
+    let mut m = Machine::new(&[0x00FF, 0x00FE], &[]);
+    m.step();
+    assert!(m.bus.is_hires());
+    assert_eq!((m.bus.display_width(), m.bus.display_height()), (128, 64));
+    m.step();
+    assert!(!m.bus.is_hires());
+    assert_eq!((m.bus.display_width(), m.bus.display_height()), (64, 32));
+}
+
+#[test]
+fn machine_code_routines_are_unknown() {
+    let mut m = Machine::new(&[0x0123], &[]);
+    assert_eq!(m.try_step(), Err(ExecError::UnknownOpcode { pc: 0x200, opcode: 0x0123 }));
+}
+
+#[test]
+fn executed_machine_code_routine_reports_its_own_opcode() {
+    let mut m = Machine::new(&[0x00E0], &[]);
+    let result = m.cpu.execute(&mut m.bus, Instruction::Sys(0x456));
+    assert_eq!(result, Err(ExecError::UnknownOpcode { pc: 0x200, opcode: 0x0456 }));
+}
+
+#[test]
+fn unknown_opcode_fails() {
+    let mut m = Machine::new(&[0x5001], &[]);
+    assert_eq!(m.try_step(), Err(ExecError::UnknownOpcode { pc: 0x200, opcode: 0x5001 }));
+    assert_eq!(m.pc(), 0x200);
+}
+
+#[test]
+fn pc_past_the_end_of_memory_fails() {
+    let mut m = Machine::new(&[], &[]);
+    m.cpu.set_pc(0xFFFE);
+    assert_eq!(m.try_step(), Err(ExecError::PcOutOfRange { pc: 0xFFFE }));
+}
+
+#[test]
+fn last_instruction_in_ram_runs() {
+    let mut m = Machine::new(&[], &[]);
+    m.poke(0xFFE, &[0x00, 0xE0]);
+    m.cpu.set_pc(0xFFE);
+    assert_eq!(m.step(), StepOutcome::Executed);
+    assert_eq!(m.try_step(), Err(ExecError::PcOutOfRange { pc: 0x1000 }));
+}
+
+#[test]
+fn long_load_past_the_end_of_memory_fails() {
+    let mut m = Machine::new(&[], &[]);
+    m.poke(0xFFE, &[0xF0, 0x00]);
+    m.cpu.set_pc(0xFFE);
+    assert_eq!(m.try_step(), Err(ExecError::PcOutOfRange { pc: 0xFFE }));
+}
+
+#[test]
+fn ram_is_4k_unless_extended() {
+    let m = Machine::new(&[], &[]);
+    assert!(m.bus.ram_read_byte(0xFFF).is_ok());
+    assert_eq!(m.bus.ram_read_byte(0x1000), Err(ExecError::MemoryOutOfBounds { addr: 0x1000 }));
+
+    let mut m = Machine::with(&[], &[], Quirks::xo_chip());
+    assert!(m.bus.ram_read_byte(0xFFFF).is_ok());
+    m.bus.set_ram_size(0x1000);
+    assert_eq!(m.bus.ram_write_byte(0x1000, 1), Err(ExecError::MemoryOutOfBounds { addr: 0x1000 }));
+}
+
+#[test]
+fn quirks_pick_the_ram_size() {
+    let big_rom = vec![0; 0x1000];
+    let mut chip8 = Chip8::with_seed(0);
+    assert_eq!(chip8.load_rom(&big_rom), Err(ExecError::MemoryOutOfBounds { addr: 0x1000 }));
+    assert!(chip8.save_state().len() < 0x2000);
+    chip8.set_quirks(Quirks::xo_chip());
+    assert_eq!(chip8.load_rom(&big_rom), Ok(()));
+}
+
+// 1NNN, BNNN
+
+#[test]
+fn jump() {
+    let mut m = Machine::new(&[0x1ABC], &[]);
+    m.step();
+    assert_eq!(m.pc(), 0xABC);
+}
+
+#[test]
+fn jump_with_offset_uses_v0() {
+    let mut m = Machine::new(&[0xB310], &[(0, 0x05), (3, 0x40)]);
+    m.step();
+    assert_eq!(m.pc(), 0x315);
+}
+
+#[test]
+fn jump_with_offset_uses_vx_with_quirk() {
+    let mut m = Machine::with(&[0xB310], &[(0, 0x05), (3, 0x40)], Quirks::super_chip());
+    m.step();
+    assert_eq!(m.pc(), 0x350);
+}
+
+// Skips
+
+#[test]
+fn skip_if_equal() {
+    let mut m = Machine::new(&[0x3042], &[(0, 0x42)]);
+    m.step();
+    assert_eq!(m.pc(), 0x204);
+    let mut m = Machine::new(&[0x3042], &[(0, 0x41)]);
+    m.step();
+    assert_eq!(m.pc(), 0x202);
+}
+
+#[test]
+fn skip_if_not_equal() {
+    let mut m = Machine::new(&[0x4042], &[(0, 0x41)]);
+    m.step();
+    assert_eq!(m.pc(), 0x204);
+    let mut m = Machine::new(&[0x4042], &[(0, 0x42)]);
+    m.step();
+    assert_eq!(m.pc(), 0x202);
+}
+
+#[test]
+fn skip_if_registers_equal() {
+    let mut m = Machine::new(&[0x5120], &[(1, 7), (2, 7)]);
+    m.step();
+    assert_eq!(m.pc(), 0x204);
+    let mut m = Machine::new(&[0x5120], &[(1, 7), (2, 8)]);
+    m.step();
+    assert_eq!(m.pc(), 0x202);
+}
+
+#[test]
+fn skip_if_registers_not_equal() {
+    let mut m = Machine::new(&[0x9120], &[(1, 7), (2, 8)]);
+    m.step();
+    assert_eq!(m.pc(), 0x204);
+    let mut m = Machine::new(&[0x9120], &[(1, 7), (2, 7)]);
+    m.step();
+    assert_eq!(m.pc(), 0x202);
+}
+
+#[test]
+fn skip_if_key() {
+    let mut m = Machine::new(&[0xE09E], &[(0, 0xA)]);
+    m.bus.key_down(0xA);
+    m.step();
+    assert_eq!(m.pc(), 0x204);
+    let mut m = Machine::new(&[0xE09E], &[(0, 0xA)]);
+    m.bus.key_down(0xB);
+    m.step();
+    assert_eq!(m.pc(), 0x202);
+}
+
+#[test]
+fn skip_if_not_key() {
+    let mut m = Machine::new(&[0xE0A1], &[(0, 0xA)]);
+    m.step();
+    assert_eq!(m.pc(), 0x204);
+    let mut m = Machine::new(&[0xE0A1], &[(0, 0xA)]);
+    m.bus.key_down(0xA);
+    m.step();
+    assert_eq!(m.pc(), 0x202);
+}
+
+#[test]
+fn skip_jumps_the_whole_long_load() {
+    let mut m = Machine::new(&[0x3000, 0xF000, 0x1234], &[]);
+    m.step();
+    assert_eq!(m.pc(), 0x206);
+}
+
+// 5XY2, 5XY3
+
+#[test]
+fn save_range() {
+    let mut m = Machine::new(&[0x5242], &[(1, 1), (2, 2), (3, 3), (4, 4), (5, 5)]);
+    m.set_i(DATA);
+    m.step();
+    assert_eq!(m.peek(DATA, 4), vec![2, 3, 4, 0]);
+    assert_eq!(m.cpu.i(), DATA);
+}
+
+#[test]
+fn save_range_backwards() {
+    let mut m = Machine::new(&[0x5422], &[(2, 2), (3, 3), (4, 4)]);
+    m.set_i(DATA);
+    m.step();
+    assert_eq!(m.peek(DATA, 3), vec![4, 3, 2]);
+}
+
+#[test]
+fn load_range() {
+    let mut m = Machine::new(&[0x5243, 0x5423], &[]);
+    m.poke(DATA, &[7, 8, 9]);
+    m.set_i(DATA);
+    m.step();
+    assert_eq!((m.v(1), m.v(2), m.v(3), m.v(4), m.v(5)), (0, 7, 8, 9, 0));
+    m.step();
+    assert_eq!((m.v(2), m.v(3), m.v(4)), (9, 8, 7));
+    assert_eq!(m.cpu.i(), DATA);
+}
+
+// 6XNN, 7XNN
+
+#[test]
+fn set_register() {
+    let mut m = Machine::new(&[0x6A5C], &[]);
+    m.step();
+    assert_eq!(m.v(0xA), 0x5C);
+    assert_eq!(m.pc(), 0x202);
+}
+
+#[test]
+fn add_immediate_wraps_without_touching_vf() {
+    let mut m = Machine::new(&[0x7002], &[(0, 0xFF), (0xF, 0x77)]);
+    m.step();
+    assert_eq!(m.v(0), 0x01);
+    assert_eq!(m.v(0xF), 0x77);
+}
+
+// 8XYN
+
+#[test]
+fn copy_register() {
+    let mut m = Machine::new(&[0x8120], &[(2, 0x33)]);
+    m.step();
+    assert_eq!(m.v(1), 0x33);
+}
+
+#[test]
+fn logic_operations() {
+    for (opcode, expected) in [(0x8121, 0b1110), (0x8122, 0b1000), (0x8123, 0b0110)] {
+        let mut m = Machine::new(&[opcode], &[(1, 0b1100), (2, 0b1010), (0xF, 0x55)]);
+        m.step();
+        assert_eq!(m.v(1), expected, "{:04X}", opcode);
+        assert_eq!(m.v(0xF), 0x55, "{:04X} leaves VF alone without the quirk", opcode);
+    }
+}
+
+#[test]
+fn logic_operations_reset_vf_with_quirk() {
+    for opcode in [0x8121, 0x8122, 0x8123] {
+        let mut m = Machine::with(&[opcode], &[(1, 0b1100), (2, 0b1010), (0xF, 0x55)], Quirks::cosmac_vip());
+        m.step();
+        assert_eq!(m.v(0xF), 0, "{:04X}", opcode);
+    }
+}
+
+#[test]
+fn add_sets_carry() {
+    let cases = [
+        (0xFF, 0x01, 0x00, 1),
+        (0xFE, 0x01, 0xFF, 0),
+        (0x80, 0x80, 0x00, 1),
+        (0x00, 0x00, 0x00, 0),
+        (0xFF, 0xFF, 0xFE, 1),
+    ];
+    for (a, b, sum, carry) in cases {
+        let mut m = Machine::new(&[0x8124], &[(1, a), (2, b), (0xF, 0x55)]);
+        m.step();
+        assert_eq!((m.v(1), m.v(0xF)), (sum, carry), "{:02X} + {:02X}", a, b);
+    }
+}
+
+#[test]
+fn add_into_vf_keeps_the_flag() {
+    // The flag is written after the result, so it wins when VF is the destination
+    let mut m = Machine::new(&[0x8F14], &[(1, 0x01), (0xF, 0xFF)]);
+    m.step();
+    assert_eq!(m.v(0xF), 1);
+    let mut m = Machine::new(&[0x8F14], &[(1, 0x01), (0xF, 0x10)]);
+    m.step();
+    assert_eq!(m.v(0xF), 0);
+}
+
+#[test]
+fn add_vf_as_operand() {
+    let mut m = Machine::new(&[0x81F4], &[(1, 0xFF), (0xF, 0x02)]);
+    m.step();
+    assert_eq!((m.v(1), m.v(0xF)), (0x01, 1));
+}
+
+#[test]
+fn sub_sets_not_borrow() {
+    let cases = [
+        (0x05, 0x03, 0x02, 1),
+        (0x03, 0x05, 0xFE, 0),
+        (0x05, 0x05, 0x00, 1),
+        (0x00, 0x01, 0xFF, 0),
+        (0xFF, 0x00, 0xFF, 1),
+    ];
+    for (a, b, diff, flag) in cases {
+        let mut m = Machine::new(&[0x8125], &[(1, a), (2, b), (0xF, 0x55)]);
+        m.step();
+        assert_eq!((m.v(1), m.v(0xF)), (diff, flag), "{:02X} - {:02X}", a, b);
+    }
+}
+
+#[test]
+fn sub_into_vf_keeps_the_flag() {
+    let mut m = Machine::new(&[0x8F15], &[(1, 0x01), (0xF, 0x10)]);
+    m.step();
+    assert_eq!(m.v(0xF), 1);
+    let mut m = Machine::new(&[0x8F15], &[(1, 0x20), (0xF, 0x10)]);
+    m.step();
+    assert_eq!(m.v(0xF), 0);
+}
+
+#[test]
+fn sub_reverse_sets_not_borrow() {
+    let cases = [
+        (0x03, 0x05, 0x02, 1),
+        (0x05, 0x03, 0xFE, 0),
+        (0x05, 0x05, 0x00, 1),
+        (0x01, 0x00, 0xFF, 0),
+    ];
+    for (a, b, diff, flag) in cases {
+        let mut m = Machine::new(&[0x8127], &[(1, a), (2, b), (0xF, 0x55)]);
+        m.step();
+        assert_eq!((m.v(1), m.v(0xF)), (diff, flag), "{:02X} =- {:02X}", a, b);
+    }
+}
+
+#[test]
+fn sub_reverse_into_vf_keeps_the_flag() {
+    let mut m = Machine::new(&[0x8F17], &[(1, 0x20), (0xF, 0x10)]);
+    m.step();
+    assert_eq!(m.v(0xF), 1);
+    let mut m = Machine::new(&[0x8F17], &[(1, 0x01), (0xF, 0x10)]);
+    m.step();
+    assert_eq!(m.v(0xF), 0);
+}
+
+#[test]
+fn shift_right_in_place() {
+    for (value, shifted, flag) in [(0b101, 0b10, 1), (0b100, 0b10, 0), (0x01, 0x00, 1), (0x00, 0x00, 0)] {
+        let mut m = Machine::new(&[0x8126], &[(1, value), (2, 0xFF), (0xF, 0x55)]);
+        m.step();
+        assert_eq!((m.v(1), m.v(0xF)), (shifted, flag), "{:08b} >> 1", value);
+    }
+}
+
+#[test]
+fn shift_right_from_vy_with_quirk() {
+    let mut m = Machine::with(&[0x8126], &[(1, 0xF0), (2, 0x03)], Quirks::cosmac_vip());
+    m.step();
+    assert_eq!((m.v(1), m.v(2), m.v(0xF)), (0x01, 0x03, 1));
+}
+
+#[test]
+fn shift_right_into_vf_keeps_the_flag() {
+    let mut m = Machine::new(&[0x8F06], &[(0xF, 0x02)]);
+    m.step();
+    assert_eq!(m.v(0xF), 0);
+    let mut m = Machine::new(&[0x8F06], &[(0xF, 0x03)]);
+    m.step();
+    assert_eq!(m.v(0xF), 1);
+}
+
+#[test]
+fn shift_left_in_place() {
+    for (value, shifted, flag) in [(0x81, 0x02, 1), (0x41, 0x82, 0), (0x80, 0x00, 1), (0x00, 0x00, 0)] {
+        let mut m = Machine::new(&[0x812E], &[(1, value), (2, 0xFF), (0xF, 0x55)]);
+        m.step();
+        assert_eq!((m.v(1), m.v(0xF)), (shifted, flag), "{:08b} << 1", value);
+    }
+}
+
+#[test]
+fn shift_left_from_vy_with_quirk() {
+    let mut m = Machine::with(&[0x812E], &[(1, 0x0F), (2, 0xC0)], Quirks::cosmac_vip());
+    m.step();
+    assert_eq!((m.v(1), m.v(2), m.v(0xF)), (0x80, 0xC0, 1));
+}
+
+#[test]
+fn shift_left_into_vf_keeps_the_flag() {
+    let mut m = Machine::new(&[0x8F0E], &[(0xF, 0x40)]);
+    m.step();
+    assert_eq!(m.v(0xF), 0);
+    let mut m = Machine::new(&[0x8F0E], &[(0xF, 0x80)]);
+    m.step();
+    assert_eq!(m.v(0xF), 1);
+}
+
+// ANNN, CXNN
+
+#[test]
+fn load_i() {
+    let mut m = Machine::new(&[0xA123], &[]);
+    m.step();
+    assert_eq!(m.cpu.i(), 0x123);
+}
+
+#[test]
+fn random_is_masked() {
+    let mut m = Machine::new(&[0xC30F, 0xC4F0], &[]);
+    m.cpu.set_random_source(Box::new(Fixed(0xAB)));
+    m.step();
+    m.step();
+    assert_eq!((m.v(3), m.v(4)), (0x0B, 0xA0));
+}
+
+#[test]
+fn seed_is_forgotten_when_the_random_source_is_replaced() {
+    let mut chip8 = Chip8::with_seed(7);
+    assert_eq!(chip8.seed(), Some(7));
+    chip8.set_random_source(Box::new(Fixed(0xAB)));
+    assert_eq!(chip8.seed(), None);
+    chip8.set_seed(9);
+    assert_eq!(chip8.seed(), Some(9));
+}
+
+#[test]
+fn chip8_can_move_between_threads() {
+    fn assert_send<T: Send>() {}
+    assert_send::<Chip8>();
+}
+
+// DXYN
+
+#[test]
+fn draw_sprite() {
+    let mut m = Machine::new(&[0xD012], &[(0, 3), (1, 4)]);
+    m.poke(DATA, &[0b1010_0000, 0b0100_0000]);
+    m.set_i(DATA);
+    m.step();
+    assert_eq!((m.pixel(3, 4), m.pixel(4, 4), m.pixel(5, 4), m.pixel(4, 5)), (1, 0, 1, 1));
+    assert_eq!(m.lit(), 3);
+    assert_eq!(m.v(0xF), 0);
+}
+
+#[test]
+fn draw_sets_vf_on_collision() {
+    let mut m = Machine::new(&[0xD011, 0xD011], &[(0xF, 0x55)]);
+    m.poke(DATA, &[0xFF]);
+    m.set_i(DATA);
+    m.step();
+    assert_eq!(m.v(0xF), 0);
+    m.step();
+    assert_eq!(m.v(0xF), 1);
+    assert_eq!(m.lit(), 0);
+}
+
+#[test]
+fn draw_only_collides_when_a_pixel_is_erased() {
+    let mut m = Machine::new(&[0xA300, 0xD011, 0xA301, 0xD011], &[]);
+    m.poke(DATA, &[0xF0, 0x0F]);
+    for _ in 0..4 {
+        m.step();
+    }
+    assert_eq!(m.v(0xF), 0);
+    assert_eq!(m.lit(), 8);
+}
+
+#[test]
+fn draw_into_vf_coordinates() {
+    // VF is read for the position before it's overwritten with the collision flag
+    let mut m = Machine::new(&[0xDFF1], &[(0xF, 10)]);
+    m.poke(DATA, &[0x80]);
+    m.set_i(DATA);
+    m.step();
+    assert_eq!(m.pixel(10, 10), 1);
+    assert_eq!(m.v(0xF), 0);
+}
+
+#[test]
+fn draw_wraps_starting_position() {
+    let mut m = Machine::new(&[0xD011], &[(0, 64 + 2), (1, 32 + 1)]);
+    m.poke(DATA, &[0x80]);
+    m.set_i(DATA);
+    m.step();
+    assert_eq!(m.pixel(2, 1), 1);
+}
+
+#[test]
+fn draw_clips_at_edges_with_quirk() {
+    let mut m = Machine::with(&[0xD012], &[(0, 60), (1, 31)], Quirks::super_chip());
+    m.poke(DATA, &[0xFF, 0xFF]);
+    m.set_i(DATA);
+    m.step();
+    assert_eq!(m.lit(), 4);
+    assert_eq!(m.pixel(0, 31), 0);
+}
+
+#[test]
+fn draw_wraps_at_edges_without_quirk() {
+    let mut m = Machine::with(&[0xD012], &[(0, 60), (1, 31)], Quirks::xo_chip());
+    m.poke(DATA, &[0xFF, 0xFF]);
+    m.set_i(DATA);
+    m.step();
+    assert_eq!(m.lit(), 16);
+    assert_eq!(m.pixel(0, 31), 1);
+    assert_eq!(m.pixel(0, 0), 1);
+}
+
+#[test]
+fn draw_big_sprite() {
+    let mut m = Machine::new(&[0x00FF, 0xD010], &[]);
+    let sprite = [0xFF; 32];
+    m.poke(DATA, &sprite);
+    m.set_i(DATA);
+    m.step();
+    m.step();
+    assert_eq!(m.lit(), 16 * 16);
+    assert_eq!(m.pixel(15, 15), 1);
+    assert_eq!(m.pixel(16, 0), 0);
+}
+
+#[test]
+fn draw_big_sprite_wraps_in_hires() {
+    let mut m = Machine::with(&[0x00FF, 0xD010], &[(0, 120), (1, 60)], Quirks::xo_chip());
+    m.poke(DATA, &[0xFF; 32]);
+    m.set_i(DATA);
+    m.step();
+    m.step();
+    assert_eq!(m.lit(), 16 * 16);
+    assert_eq!((m.pixel(127, 63), m.pixel(0, 63), m.pixel(7, 0), m.pixel(8, 0)), (1, 1, 1, 0));
+}
+
+#[test]
+fn draw_big_sprite_clips_in_hires() {
+    let mut m = Machine::with(&[0x00FF, 0xD010], &[(0, 120), (1, 60)], Quirks::super_chip());
+    m.poke(DATA, &[0xFF; 32]);
+    m.set_i(DATA);
+    m.step();
+    m.step();
+    assert_eq!(m.lit(), 8 * 4);
+    assert_eq!(m.pixel(0, 63), 0);
+}
+
+#[test]
+fn draw_marks_the_display_dirty() {
+    let mut m = Machine::new(&[0xD011, 0xD011], &[]);
+    m.poke(DATA, &[0x00, 0x80]);
+    m.set_i(DATA);
+    m.bus.clear_display_dirty();
+    m.step();
+    assert!(!m.bus.is_display_dirty());
+    m.set_i(DATA + 1);
+    m.step();
+    assert!(m.bus.is_display_dirty());
+}
+
+#[test]
+fn draw_on_both_planes_reads_consecutive_sprites() {
+    let mut m = Machine::new(&[0xF301, 0xD011], &[]);
+    m.poke(DATA, &[0x80, 0x40]);
+    m.set_i(DATA);
+    m.step();
+    m.step();
+    assert_eq!((m.pixel(0, 0), m.pixel(1, 0)), (1, 2));
+}
+
+#[test]
+fn draw_keeps_the_display_buffer_up_to_date() {
+    // The second draw wraps, so only the bottom and top rows get unpacked again
+    let mut m = Machine::with(&[0xD012, 0xD012], &[(0, 0), (1, 31)], Quirks::xo_chip());
+    m.poke(DATA, &[0x80, 0x80]);
+    m.set_i(DATA);
+    m.step();
+    assert_eq!((m.pixel(0, 31), m.pixel(0, 0), m.lit()), (1, 1, 2));
+    m.step();
+    assert_eq!(m.lit(), 0);
+}
+
+#[test]
+fn machines_can_be_shared_between_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Bus>();
+    assert_send_sync::<Chip8>();
+}
+
+#[test]
+fn draw_waits_for_vblank_with_quirk() {
+    let mut m = Machine::with(&[0xD011], &[], Quirks::cosmac_vip());
+    m.poke(DATA, &[0x80]);
+    m.set_i(DATA);
+    assert_eq!(m.step(), StepOutcome::WaitingForVblank);
+    assert_eq!(m.pc(), 0x200);
+    assert_eq!(m.lit(), 0);
+    m.bus.tick_timers();
+    assert_eq!(m.step(), StepOutcome::Executed);
+    assert_eq!(m.lit(), 1);
+}
+
+// FXNN
+
+#[test]
+fn long_load_i() {
+    let mut m = Machine::new(&[0xF000, 0xBEEF], &[]);
+    m.step();
+    assert_eq!(m.cpu.i(), 0xBEEF);
+    assert_eq!(m.pc(), 0x204);
+}
+
+#[test]
+fn select_planes() {
+    let mut m = Machine::new(&[0xF201, 0xF001], &[]);
+    assert_eq!(m.cpu.planes(), 1);
+    m.step();
+    assert_eq!(m.cpu.planes(), 2);
+    m.step();
+    assert_eq!(m.cpu.planes(), 0);
+}
+
+#[test]
+fn load_audio_pattern() {
+    let mut m = Machine::new(&[0xF002, 0xF13A], &[(1, 100)]);
+    let pattern: Vec<u8> = (0..16).collect();
+    m.poke(DATA, &pattern);
+    m.set_i(DATA);
+    m.step();
+    m.step();
+    let audio = m.bus.get_audio_pattern().expect("a pattern was loaded");
+    assert_eq!(audio.buffer.to_vec(), pattern);
+    assert_eq!(audio.pitch, 100);
+}
+
+#[test]
+fn timers() {
+    let mut m = Machine::new(&[0xF015, 0xF118, 0xF207], &[(0, 10), (1, 20)]);
+    m.step();
+    m.step();
+    assert_eq!((m.bus.get_delay_timer(), m.bus.get_sound_timer()), (10, 20));
+    m.bus.tick_timers();
+    m.step();
+    assert_eq!(m.v(2), 9);
+}
+
+#[test]
+fn wait_for_key_press() {
+    let mut m = Machine::with(&[0xF50A], &[], Quirks::chip48());
+    assert_eq!(m.step(), StepOutcome::WaitingForKey);
+    assert_eq!(m.step(), StepOutcome::WaitingForKey);
+    m.bus.key_down(0xC);
+    assert_eq!(m.step(), StepOutcome::Executed);
+    assert_eq!(m.v(5), 0xC);
+    assert_eq!(m.pc(), 0x202);
+}
+
+#[test]
+fn wait_for_key_release_with_quirk() {
+    let mut m = Machine::with(&[0xF50A], &[], Quirks::cosmac_vip());
+    m.step();
+    m.bus.key_down(0x3);
+    assert_eq!(m.step(), StepOutcome::WaitingForKey);
+    m.bus.key_up(0x3);
+    assert_eq!(m.step(), StepOutcome::Executed);
+    assert_eq!(m.v(5), 0x3);
+}
+
+#[test]
+fn wait_for_key_ignores_keys_already_held() {
+    let mut m = Machine::with(&[0xF50A], &[], Quirks::chip48());
+    m.bus.key_down(0x1);
+    m.step();
+    assert_eq!(m.step(), StepOutcome::WaitingForKey);
+    m.bus.key_up(0x1);
+    assert_eq!(m.step(), StepOutcome::WaitingForKey);
+    m.bus.key_down(0x1);
+    assert_eq!(m.step(), StepOutcome::Executed);
+    assert_eq!(m.v(5), 0x1);
+}
+
+#[test]
+fn add_to_i() {
+    let mut m = Machine::new(&[0xF01E], &[(0, 0x10), (0xF, 0x55)]);
+    m.set_i(0xFFF8);
+    m.step();
+    assert_eq!(m.cpu.i(), 0x0008);
+    assert_eq!(m.v(0xF), 0x55);
+}
+
+#[test]
+fn font_characters() {
+    let mut m = Machine::new(&[0xF029, 0xF130], &[(0, 0xA), (1, 0x3)]);
+    m.step();
+    assert_eq!(m.cpu.i(), 0xA * 5);
+    assert_eq!(m.peek(m.cpu.i(), 5), vec![0xF0, 0x90, 0xF0, 0x90, 0x90]);
+    m.step();
+    assert_eq!(m.cpu.i(), BIG_FONT_START + 3 * 10);
+}
+
+#[test]
+fn font_uses_the_low_digit_of_vx() {
+    let mut m = Machine::new(&[0xF029, 0xF130], &[(0, 0x1A), (1, 0x1A)]);
+    m.step();
+    assert_eq!(m.cpu.i(), 0xA * 5);
+    m.step();
+    assert_eq!(m.cpu.i(), BIG_FONT_START + 0xA * 10);
+}
+
+#[test]
+fn binary_coded_decimal() {
+    for (value, digits) in [(0, [0, 0, 0]), (9, [0, 0, 9]), (42, [0, 4, 2]), (100, [1, 0, 0]), (255, [2, 5, 5])] {
+        let mut m = Machine::new(&[0xF733], &[(7, value)]);
+        m.set_i(DATA);
+        m.step();
+        assert_eq!(m.peek(DATA, 3), digits.to_vec(), "{}", value);
+        assert_eq!(m.cpu.i(), DATA);
+    }
+}
+
+#[test]
+fn store_registers() {
+    let increments = [
+        (LoadStoreIncrement::None, DATA),
+        (LoadStoreIncrement::X, DATA + 2),
+        (LoadStoreIncrement::XPlusOne, DATA + 3),
+    ];
+    for (increment, i) in increments {
+        let quirks = Quirks { load_store_increment: increment, ..Quirks::modern() };
+        let mut m = Machine::with(&[0xF255], &[(0, 1), (1, 2), (2, 3), (3, 4)], quirks);
+        m.set_i(DATA);
+        m.step();
+        assert_eq!(m.peek(DATA, 4), vec![1, 2, 3, 0]);
+        assert_eq!(m.cpu.i(), i, "{:?}", increment);
+    }
+}
+
+#[test]
+fn store_only_v0() {
+    let mut m = Machine::new(&[0xF055], &[(0, 9), (1, 8)]);
+    m.set_i(DATA);
+    m.step();
+    assert_eq!(m.peek(DATA, 2), vec![9, 0]);
+    assert_eq!(m.cpu.i(), DATA + 1);
+}
+
+#[test]
+fn load_registers() {
+    let increments = [
+        (LoadStoreIncrement::None, DATA),
+        (LoadStoreIncrement::X, DATA + 2),
+        (LoadStoreIncrement::XPlusOne, DATA + 3),
+    ];
+    for (increment, i) in increments {
+        let quirks = Quirks { load_store_increment: increment, ..Quirks::modern() };
+        let mut m = Machine::with(&[0xF265], &[(3, 0x77)], quirks);
+        m.poke(DATA, &[5, 6, 7, 8]);
+        m.set_i(DATA);
+        m.step();
+        assert_eq!((m.v(0), m.v(1), m.v(2), m.v(3)), (5, 6, 7, 0x77));
+        assert_eq!(m.cpu.i(), i, "{:?}", increment);
+    }
+}
+
+#[test]
+fn load_into_vf() {
+    let mut m = Machine::new(&[0xFF65], &[]);
+    let values: Vec<u8> = (0x10..0x20).collect();
+    m.poke(DATA, &values);
+    m.set_i(DATA);
+    m.step();
+    assert_eq!(m.cpu.registers().v.to_vec(), values);
+}
+
+#[test]
+fn save_and_load_flags() {
+    let mut m = Machine::new(&[0xF275, 0x6000, 0x6100, 0x6200, 0xF285], &[(0, 1), (1, 2), (2, 3), (3, 4)]);
+    m.step();
+    assert_eq!(&m.cpu.rpl_flags()[..4], &[1, 2, 3, 0]);
+    for _ in 0..4 {
+        m.step();
+    }
+    assert_eq!((m.v(0), m.v(1), m.v(2), m.v(3)), (1, 2, 3, 4));
+}
+
+// The test API itself
+
+#[test]
+fn registers_round_trip() {
+    let registers = Registers {
+        v: [0xA; 16],
+        pc: 0x345,
+        i: 0x678,
+        stack: vec![0x202, 0x204],
+    };
+    let cpu = Cpu::with_registers(registers.clone());
+    assert_eq!(cpu.registers(), registers);
+}
+
+#[test]
+#[should_panic]
+fn registers_reject_an_overfull_stack() {
+    Cpu::with_registers(Registers { stack: vec![0; STACK_SIZE + 1], ..Registers::default() });
+}
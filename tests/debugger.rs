@@ -0,0 +1,197 @@
+use rust_chip8::debugger::{BreakpointTarget, Command, Debugger, OpcodePattern, Register};
+use rust_chip8::Chip8;
+
+// Adds 1 to V0, stores it at 0x300 and loops, drawing a digit each time round
+const ROM: [u8; 12] = [0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0xA0, 0x00, 0xD0, 0x15, 0x12, 0x00];
+
+fn parse(line: &str) -> Result<Command, String> {
+    line.parse()
+}
+
+fn machine() -> Chip8 {
+    let mut chip8 = Chip8::with_seed(0);
+    chip8.load_rom(&ROM).unwrap();
+    chip8
+}
+
+#[test]
+fn pattern_digits_match_exactly() {
+    let pattern = OpcodePattern::parse("D01F").unwrap();
+    assert!(pattern.matches(0xD01F));
+    assert!(!pattern.matches(0xD01E));
+    assert_eq!(pattern.to_string(), "D01F");
+}
+
+#[test]
+fn pattern_letters_match_anything() {
+    let pattern = OpcodePattern::parse("DXYN").unwrap();
+    assert!(pattern.matches(0xD000));
+    assert!(pattern.matches(0xDFFF));
+    assert!(!pattern.matches(0xC123));
+    assert_eq!(pattern.to_string(), "D???");
+
+    let pattern = OpcodePattern::parse("8xy6").unwrap();
+    assert!(pattern.matches(0x8126));
+    assert!(!pattern.matches(0x8127));
+}
+
+#[test]
+fn pattern_needs_four_characters() {
+    assert_eq!(OpcodePattern::parse("DXY"), None);
+    assert_eq!(OpcodePattern::parse("DXYNN"), None);
+    assert_eq!(OpcodePattern::parse(""), None);
+}
+
+#[test]
+fn parses_numbers_in_decimal_and_hex() {
+    assert_eq!(parse("break 0x200"), Ok(Command::Break(Some(0x200))));
+    assert_eq!(parse("b 512"), Ok(Command::Break(Some(0x200))));
+    assert_eq!(parse("b"), Ok(Command::Break(None)));
+    assert_eq!(parse("s"), Ok(Command::Step(1)));
+    assert_eq!(parse("step 0X10"), Ok(Command::Step(16)));
+}
+
+#[test]
+fn parses_breakop() {
+    assert_eq!(parse("bo DXYN"), Ok(Command::BreakOp(OpcodePattern::parse("DXYN").unwrap())));
+    assert_eq!(parse("breakop"), Err(String::from("breakop needs an opcode pattern, e.g. DXYN")));
+    assert_eq!(parse("breakop DXY"), Err(String::from("bad opcode pattern 'DXY'")));
+}
+
+#[test]
+fn parses_delete_as_an_address_or_a_pattern() {
+    let both = BreakpointTarget {
+        address: Some(200),
+        pattern: OpcodePattern::parse("0200"),
+        text: String::from("0200"),
+    };
+    assert_eq!(parse("d 0200"), Ok(Command::Delete(Some(both))));
+    let pattern = BreakpointTarget { address: None, pattern: OpcodePattern::parse("DXYN"), text: String::from("DXYN") };
+    assert_eq!(parse("delete DXYN"), Ok(Command::Delete(Some(pattern))));
+    assert_eq!(parse("delete"), Ok(Command::Delete(None)));
+    assert_eq!(parse("d nop"), Err(String::from("'nop' isn't an address or an opcode pattern")));
+}
+
+#[test]
+fn parses_watch() {
+    assert_eq!(parse("watch 0x300"), Ok(Command::Watch(0x300)));
+    assert_eq!(parse("w"), Err(String::from("watch needs an address")));
+    assert_eq!(parse("w 0x10000"), Err(String::from("0x10000 is past the end of memory")));
+    assert_eq!(parse("unwatch"), Ok(Command::Unwatch(None)));
+}
+
+#[test]
+fn parses_set() {
+    assert_eq!(parse("set v3 0x7F"), Ok(Command::Set(Register::V(3), 0x7F)));
+    assert_eq!(parse("set VF 255"), Ok(Command::Set(Register::V(0xF), 255)));
+    assert_eq!(parse("set pc 0x300"), Ok(Command::Set(Register::Pc, 0x300)));
+    assert_eq!(parse("set I 0xFFFF"), Ok(Command::Set(Register::I, 0xFFFF)));
+    assert_eq!(parse("set dt 60"), Ok(Command::Set(Register::Dt, 60)));
+    assert_eq!(parse("set V0 256"), Err(String::from("256 doesn't fit in a byte")));
+    assert_eq!(parse("set VG 1"), Err(String::from("unknown register 'VG', expected V0-VF, I, PC, DT or ST")));
+    assert_eq!(parse("set V10 1"), Err(String::from("unknown register 'V10', expected V0-VF, I, PC, DT or ST")));
+    assert_eq!(parse("set V0"), Err(String::from("usage: set REG VALUE")));
+    assert_eq!(parse("set V0 1 2"), Err(String::from("usage: set REG VALUE")));
+}
+
+#[test]
+fn parses_poke() {
+    assert_eq!(parse("poke 0x300 1 0xFF"), Ok(Command::Poke { address: 0x300, bytes: vec![1, 0xFF] }));
+    assert_eq!(parse("poke 0x300"), Ok(Command::Poke { address: 0x300, bytes: vec![] }));
+    assert_eq!(parse("poke"), Err(String::from("usage: poke ADDR BYTE...")));
+    assert_eq!(parse("poke 0x300 1 zz"), Err(String::from("'zz' isn't a number")));
+    assert_eq!(parse("poke 0x300 300"), Err(String::from("300 doesn't fit in a byte")));
+}
+
+#[test]
+fn parses_x_and_dis() {
+    assert_eq!(parse("x 0x300"), Ok(Command::Examine { address: 0x300, len: 16 }));
+    assert_eq!(parse("x 0x300 4"), Ok(Command::Examine { address: 0x300, len: 4 }));
+    assert_eq!(parse("x"), Err(String::from("x needs an address")));
+    assert_eq!(parse("x 0xZZ"), Err(String::from("'0xZZ' isn't a number")));
+    assert_eq!(parse("dis"), Ok(Command::Disassemble { address: None, count: 10 }));
+    assert_eq!(parse("u 0x200 3"), Ok(Command::Disassemble { address: Some(0x200), count: 3 }));
+    assert_eq!(parse("dis 0x200 -1"), Err(String::from("'-1' isn't a number")));
+}
+
+#[test]
+fn unknown_commands_are_errors() {
+    assert_eq!(parse("frob"), Err(String::from("unknown command 'frob', try help")));
+    assert_eq!(parse("STEP"), Err(String::from("unknown command 'STEP', try help")));
+    assert_eq!(parse("  "), Err(String::from("no command, try help")));
+}
+
+#[test]
+fn breakpoint_stops_the_program() {
+    let mut chip8 = machine();
+    let mut debugger = Debugger::new();
+    assert_eq!(debugger.command(&mut chip8, "break 0x208"), "Breakpoint at 0x208\n");
+    debugger.command(&mut chip8, "continue");
+    let message = debugger.run_frame(&mut chip8).unwrap();
+    assert!(message.starts_with("Breakpoint at 0x208\n"), "{}", message);
+    assert!(debugger.is_paused());
+    assert_eq!(chip8.pc(), 0x208);
+}
+
+#[test]
+fn opcode_breakpoint_stops_before_the_instruction() {
+    let mut chip8 = machine();
+    let mut debugger = Debugger::new();
+    debugger.command(&mut chip8, "bo F?55");
+    debugger.command(&mut chip8, "c");
+    let message = debugger.run_frame(&mut chip8).unwrap();
+    assert!(message.starts_with("Breakpoint on opcode F?55\n"), "{}", message);
+    assert_eq!(chip8.pc(), 0x204);
+}
+
+#[test]
+fn watch_reports_the_change() {
+    let mut chip8 = machine();
+    let mut debugger = Debugger::new();
+    debugger.command(&mut chip8, "watch 0x300");
+    debugger.command(&mut chip8, "c");
+    let message = debugger.run_frame(&mut chip8).unwrap();
+    assert_eq!(message, "Watch 0x300 changed from 0x00 to 0x01 by the instruction at 0x204\n");
+}
+
+#[test]
+fn set_poke_and_x() {
+    let mut chip8 = machine();
+    let mut debugger = Debugger::new();
+    assert_eq!(debugger.command(&mut chip8, "set V5 0x42"), "");
+    assert_eq!(chip8.register(5), 0x42);
+    assert_eq!(debugger.command(&mut chip8, "set pc 0x20A"), "");
+    assert_eq!(chip8.pc(), 0x20A);
+    assert_eq!(debugger.command(&mut chip8, "poke 0x300 0xAB 0xCD"), "");
+    assert_eq!(debugger.command(&mut chip8, "x 0x300 3"), "0300: AB CD 00\n");
+    assert_eq!(debugger.command(&mut chip8, "poke 0x1000 1"), "memory access out of bounds at 0x1000\n");
+}
+
+#[test]
+fn x_stops_at_the_size_of_memory() {
+    let mut chip8 = machine();
+    let mut debugger = Debugger::new();
+    let dump = debugger.command(&mut chip8, "x 0 70000");
+    assert_eq!(dump.lines().count(), 0x1000 / 16);
+    assert!(dump.starts_with("0000: F0 90 90 90 F0 20"), "{}", dump);
+    assert!(dump.ends_with("\n0FF0: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n"));
+}
+
+#[test]
+fn bad_command_leaves_the_machine_alone() {
+    let mut chip8 = machine();
+    let mut debugger = Debugger::new();
+    let before = chip8.save_state();
+    assert_eq!(debugger.command(&mut chip8, "poke 0x200 1 2 zz"), "'zz' isn't a number\n");
+    assert_eq!(chip8.save_state(), before);
+}
+
+#[test]
+fn empty_line_repeats_the_last_command() {
+    let mut chip8 = machine();
+    let mut debugger = Debugger::new();
+    debugger.command(&mut chip8, "step");
+    assert_eq!(chip8.pc(), 0x202);
+    debugger.command(&mut chip8, "");
+    assert_eq!(chip8.pc(), 0x204);
+}
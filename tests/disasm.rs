@@ -0,0 +1,87 @@
+use rust_chip8::disasm::Disassembly;
+use rust_chip8::Instruction;
+
+// Skips over a long load, calls a subroutine that draws the data at the end, then loops
+const ROM: [u8; 16] = [
+    0x30, 0x00, // SE V0, 0
+    0xF0, 0x00, 0x02, 0x0E, // LD I, LONG 0x20E
+    0x22, 0x0A, // CALL 0x20A
+    0x12, 0x08, // JP 0x208
+    0xD0, 0x15, // DRW V0, V1, 5
+    0x00, 0xEE, // RET
+    0x3C, 0x42, // data
+];
+
+#[test]
+fn follows_calls_skips_and_jumps() {
+    let disassembly = Disassembly::new(&ROM);
+    let addresses: Vec<u16> = disassembly.instructions().keys().copied().collect();
+    assert_eq!(addresses, [0x200, 0x202, 0x206, 0x208, 0x20A, 0x20C]);
+    assert_eq!(disassembly.instructions()[&0x202], Instruction::LoadILong);
+    assert_eq!(disassembly.label(0x200).as_deref(), Some("main"));
+    assert_eq!(disassembly.label(0x208).as_deref(), Some("label_208"));
+    assert_eq!(disassembly.label(0x20A).as_deref(), Some("sub_20A"));
+    assert_eq!(disassembly.label(0x20E).as_deref(), Some("data_20E"));
+    assert_eq!(disassembly.label(0x206), None);
+    // The font isn't part of the rom
+    assert_eq!(disassembly.label(0x050), None);
+}
+
+#[test]
+fn lists_cowgod_syntax() {
+    let expected = "\
+main:
+    SE V0, 0x00                 ; 200: 3000
+    LD I, LONG data_20E         ; 202: F000 020E
+    CALL sub_20A                ; 206: 220A
+label_208:
+    JP label_208                ; 208: 1208
+sub_20A:
+    DRW V0, V1, 5               ; 20A: D015
+    RET                         ; 20C: 00EE
+data_20E:
+    DB 0x3C, 0x42               ; 20E
+";
+    assert_eq!(Disassembly::new(&ROM).to_string(), expected);
+}
+
+#[test]
+fn lists_octo_syntax() {
+    let expected = "\
+: main
+    if v0 != 0x00 then          # 200: 3000
+    i := long data_20E          # 202: F000 020E
+    :call sub_20A               # 206: 220A
+: label_208
+    jump label_208              # 208: 1208
+: sub_20A
+    sprite v0 v1 5              # 20A: D015
+    return                      # 20C: 00EE
+: data_20E
+    0x3C 0x42                   # 20E
+";
+    assert_eq!(format!("{:#}", Disassembly::new(&ROM)), expected);
+}
+
+#[test]
+fn unreached_bytes_are_data() {
+    // Jumps over two bytes that would decode as CLS
+    let disassembly = Disassembly::new(&[0x12, 0x04, 0x00, 0xE0, 0x12, 0x04]);
+    assert!(!disassembly.instructions().contains_key(&0x202));
+    let listing = disassembly.to_string();
+    assert!(listing.contains("    DB 0x00, 0xE0               ; 202\n"), "{}", listing);
+}
+
+#[test]
+fn no_label_inside_an_instruction() {
+    // The long load points I at its own address operand, which the listing can't put a label on
+    let disassembly = Disassembly::new(&[0xF0, 0x00, 0x02, 0x02, 0x12, 0x04]);
+    assert_eq!(disassembly.label(0x202), None);
+    let expected = "\
+main:
+    LD I, LONG 0x0202           ; 200: F000 0202
+label_204:
+    JP label_204                ; 204: 1204
+";
+    assert_eq!(disassembly.to_string(), expected);
+}
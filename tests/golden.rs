@@ -0,0 +1,95 @@
+// Runs every rom in data/ for a fixed number of frames with scripted input and compares the
+// final screen against the png checked in under tests/golden/. Everything is seeded, so any
+// difference means the emulation changed.
+//
+// After a deliberate change, regenerate the images with
+//
+//     UPDATE_GOLDENS=1 cargo test --test golden
+//
+// and look over the new ones before committing them. A rom can have its own input script in
+// tests/golden/ROM.input, otherwise tests/golden/input.txt is used.
+use rust_chip8::headless::{self, InputScript, RunLength};
+use rust_chip8::image::{encode_display, ImageFormat};
+use rust_chip8::{Chip8, Palette, Preset};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FRAMES: u64 = 600;
+const SEED: u64 = 0x5EED;
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn roms() -> Vec<PathBuf> {
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+    let mut roms: Vec<PathBuf> = fs::read_dir(data)
+        .expect("data/ should be readable")
+        .map(|entry| entry.expect("data/ should be readable").path())
+        .collect();
+    roms.sort();
+    roms
+}
+
+fn input_for(name: &str) -> InputScript {
+    let own = golden_dir().join(format!("{}.input", name));
+    let path = if own.exists() { own } else { golden_dir().join("input.txt") };
+    let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("can't read {}: {}", path.display(), e));
+    InputScript::parse(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+// The final screen of a rom as a png
+fn render(rom: &Path, name: &str) -> Vec<u8> {
+    let data = fs::read(rom).unwrap_or_else(|e| panic!("can't read {}: {}", rom.display(), e));
+    let mut chip8 = Chip8::with_seed(SEED);
+    // The same quirks the emulator picks for the rom when none are asked for
+    if let Some(preset) = Preset::for_rom(&data) {
+        chip8.set_quirks(preset.quirks());
+    }
+    chip8.load_rom(&data).unwrap_or_else(|e| panic!("{} doesn't load: {}", name, e));
+    if let Err(e) = headless::run(&mut chip8, RunLength::Frames(FRAMES), &input_for(name), |_, _| {}) {
+        panic!("{} halted: {}", name, e);
+    }
+    encode_display(chip8.get_display_buffer(), chip8.display_width(), chip8.display_height(), ImageFormat::Png, &Palette::default())
+}
+
+#[test]
+fn bundled_roms_match_golden_images() {
+    let update = env::var_os("UPDATE_GOLDENS").is_some();
+    // Mismatches are written here to compare against the goldens
+    let actual_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden-actual");
+    let mut failures = Vec::new();
+
+    for rom in roms() {
+        let name = rom.file_name().unwrap().to_string_lossy().to_string();
+        let image = render(&rom, &name);
+        let golden = golden_dir().join(format!("{}.png", name));
+        if update {
+            fs::write(&golden, &image).unwrap_or_else(|e| panic!("can't write {}: {}", golden.display(), e));
+            continue;
+        }
+        match fs::read(&golden) {
+            Ok(expected) if expected == image => {}
+            Ok(_) => {
+                fs::create_dir_all(&actual_dir).unwrap();
+                let actual = actual_dir.join(format!("{}.png", name));
+                fs::write(&actual, &image).unwrap();
+                failures.push(format!("{} doesn't match {}, got {}", name, golden.display(), actual.display()));
+            }
+            Err(_) => failures.push(format!("{} has no golden image, run with UPDATE_GOLDENS=1 to make one", name)),
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}\n", failures.join("\n"));
+}
+
+#[test]
+fn roms_that_need_other_quirks_get_them() {
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+    let preset = |name: &str| Preset::for_rom(&fs::read(data.join(name)).unwrap());
+    assert_eq!(preset("BLITZ"), Some(Preset::Chip48));
+    assert_eq!(preset("BLINKY"), Some(Preset::Chip48));
+    assert_eq!(preset("PONG"), None);
+    assert_eq!(Preset::for_rom(&[0x12, 0x00]), None);
+}
@@ -0,0 +1,76 @@
+use rust_chip8::headless::{self, InputScript, RunLength};
+use rust_chip8::Chip8;
+use std::path::{Path, PathBuf};
+
+// JP 0x200, forever
+const SPIN: [u8; 2] = [0x12, 0x00];
+
+// The keys held after each frame of a run with script
+fn pressed_keys(script: &str, frames: u64) -> Vec<u16> {
+    let script = InputScript::parse(script).unwrap();
+    let mut chip8 = Chip8::with_seed(0);
+    chip8.load_rom(&SPIN).unwrap();
+    let mut pressed = Vec::new();
+    headless::run(&mut chip8, RunLength::Frames(frames), &script, |chip8, _| pressed.push(chip8.get_pressed_keys())).unwrap();
+    pressed
+}
+
+#[test]
+fn script_events_happen_at_the_start_of_their_frame() {
+    let script = "\
+# hold 3, then swap it for 4
+0 down 3
+2 up 3
+2 down 4
+";
+    assert_eq!(pressed_keys(script, 4), [1 << 3, 1 << 3, 1 << 4, 1 << 4]);
+}
+
+#[test]
+fn script_lines_can_be_in_any_order() {
+    assert_eq!(pressed_keys("3 up a\n1 down A   # comment\n", 4), [0, 1 << 10, 1 << 10, 0]);
+}
+
+#[test]
+fn events_on_the_same_frame_keep_their_order() {
+    assert_eq!(pressed_keys("1 down 2\n1 up 2\n", 2), [0, 0]);
+    assert_eq!(pressed_keys("1 up 2\n1 down 2\n", 2), [0, 1 << 2]);
+}
+
+#[test]
+fn bad_script_lines_are_errors() {
+    assert_eq!(InputScript::parse("0 down 5\n\nx down 5").unwrap_err(), "line 3: bad frame number 'x'");
+    assert_eq!(InputScript::parse("-1 down 5").unwrap_err(), "line 1: bad frame number '-1'");
+    assert_eq!(InputScript::parse("0 press 5").unwrap_err(), "line 1: expected down or up, got 'press'");
+    assert_eq!(InputScript::parse("0 down 10").unwrap_err(), "line 1: bad key '10', expected 0 to F");
+    assert_eq!(InputScript::parse("0 down G").unwrap_err(), "line 1: bad key 'G', expected 0 to F");
+    assert_eq!(InputScript::parse("0 down").unwrap_err(), "line 1: expected FRAME down|up KEY");
+}
+
+#[test]
+fn runs_for_a_number_of_instructions() {
+    let mut chip8 = Chip8::with_seed(0);
+    chip8.set_instructions_per_frame(10);
+    chip8.load_rom(&SPIN).unwrap();
+    let mut frames = Vec::new();
+    let summary = headless::run(&mut chip8, RunLength::Instructions(25), &InputScript::new(), |_, frame| frames.push(frame)).unwrap();
+    assert_eq!((summary.frames, summary.instructions, summary.exited), (2, 25, false));
+    // The third frame is cut short, so it isn't reported
+    assert_eq!(frames, [1, 2]);
+}
+
+#[test]
+fn stops_when_the_rom_exits() {
+    let mut chip8 = Chip8::with_seed(0);
+    // CLS, EXIT
+    chip8.load_rom(&[0x00, 0xE0, 0x00, 0xFD]).unwrap();
+    let summary = headless::run(&mut chip8, RunLength::Frames(10), &InputScript::new(), |_, _| {}).unwrap();
+    assert_eq!((summary.frames, summary.instructions, summary.exited), (0, 2, true));
+}
+
+#[test]
+fn frame_paths_number_the_frame_before_the_extension() {
+    assert_eq!(headless::frame_path(Path::new("out/run.png"), 120), PathBuf::from("out/run.000120.png"));
+    assert_eq!(headless::frame_path(Path::new("run.tar.pbm"), 7), PathBuf::from("run.tar.000007.pbm"));
+    assert_eq!(headless::frame_path(Path::new("run"), 1_234_567), PathBuf::from("run.1234567"));
+}
@@ -0,0 +1,84 @@
+use rust_chip8::image::{self, ImageFormat};
+use rust_chip8::state::crc32;
+use rust_chip8::Palette;
+
+// Splits a png into its chunks, checking each one's crc
+fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    let mut chunks = Vec::new();
+    let mut rest = &png[8..];
+    while !rest.is_empty() {
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let (kind_and_data, after) = rest[4..].split_at(4 + length);
+        let crc = u32::from_be_bytes([after[0], after[1], after[2], after[3]]);
+        assert_eq!(crc, crc32(kind_and_data));
+        chunks.push((String::from_utf8(kind_and_data[..4].to_vec()).unwrap(), kind_and_data[4..].to_vec()));
+        rest = &after[4..];
+    }
+    chunks
+}
+
+// Reads back a zlib stream of stored blocks
+fn unstore(zlib: &[u8]) -> Vec<u8> {
+    assert_eq!(&zlib[..2], [0x78, 0x01]);
+    let mut data = Vec::new();
+    let mut at = 2;
+    loop {
+        let last = zlib[at] == 1;
+        let length = u16::from_le_bytes([zlib[at + 1], zlib[at + 2]]) as usize;
+        assert_eq!(u16::from_le_bytes([zlib[at + 3], zlib[at + 4]]), !(length as u16));
+        data.extend_from_slice(&zlib[at + 5..at + 5 + length]);
+        at += 5 + length;
+        if last {
+            break;
+        }
+    }
+    // Only the adler32 checksum is left
+    assert_eq!(zlib.len(), at + 4);
+    data
+}
+
+#[test]
+fn png_has_a_header_and_rgb_rows() {
+    let png = image::encode_png(2, 2, &[0xFF0000, 0x00FF00, 0x0000FF, 0x123456]);
+    let chunks = chunks(&png);
+    let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+    assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+    assert_eq!(chunks[0].1, [0, 0, 0, 2, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+    assert_eq!(unstore(&chunks[1].1), [0, 0xFF, 0, 0, 0, 0xFF, 0, 0, 0, 0, 0xFF, 0x12, 0x34, 0x56]);
+    assert!(chunks[2].1.is_empty());
+}
+
+#[test]
+fn big_png_data_is_split_into_blocks() {
+    let (width, height) = (200, 200);
+    let pixels: Vec<u32> = (0..width * height).map(|i| i as u32).collect();
+    let raw = unstore(&chunks(&image::encode_png(width, height, &pixels))[1].1);
+    assert_eq!(raw.len(), height * (width * 3 + 1));
+    // The last pixel of the first row
+    assert_eq!(raw[1 + 199 * 3..1 + 200 * 3], [0, 0, 199]);
+}
+
+#[test]
+fn pbm_packs_rows_into_bits() {
+    let lit: Vec<bool> = "#.#.#.#.#\n.........".chars().filter(|c| *c != '\n').map(|c| c == '#').collect();
+    let pbm = image::encode_pbm(9, 2, &lit);
+    // Each row starts on a new byte
+    assert_eq!(pbm, b"P4\n9 2\n\xAA\x80\x00\x00");
+}
+
+#[test]
+fn display_formats_follow_the_extension() {
+    assert_eq!(ImageFormat::from_path("out/frame.PNG"), Some(ImageFormat::Png));
+    assert_eq!(ImageFormat::from_path("frame.pbm"), Some(ImageFormat::Pbm));
+    assert_eq!(ImageFormat::from_path("frame.gif"), None);
+    assert_eq!(ImageFormat::from_path("frame"), None);
+
+    let palette = Palette::default();
+    let pbm = image::encode_display(&[0, 1, 2, 3], 4, 1, ImageFormat::Pbm, &palette);
+    assert_eq!(pbm, b"P4\n4 1\n\x70");
+    let png = image::encode_display(&[0, 3], 2, 1, ImageFormat::Png, &palette);
+    let raw = unstore(&chunks(&png)[1].1);
+    let rgb = |color: u32| color.to_be_bytes()[1..].to_vec();
+    assert_eq!(raw, [vec![0], rgb(palette.color(0)), rgb(palette.color(3))].concat());
+}
@@ -0,0 +1,64 @@
+use rust_chip8::instruction::decode;
+use rust_chip8::{DecodeError, Instruction};
+
+#[test]
+fn every_opcode_encodes_back_to_itself() {
+    let mut decoded = 0;
+    for opcode in 0..=u16::MAX {
+        if let Ok(instruction) = decode(opcode) {
+            assert_eq!(instruction.encode(), opcode, "{:?}", instruction);
+            decoded += 1;
+        }
+    }
+    // All of 0, 1-4, 6, 7 and A-D, then 3 kinds of 5XYN, 9 of 8XYN, 9XY0, 2 of EXNN, and F000,
+    // FN01, F002 and 13 kinds of FXNN
+    assert_eq!(decoded, 4096 + 10 * 4096 + 3 * 256 + 9 * 256 + 256 + 2 * 16 + (1 + 16 + 1 + 13 * 16));
+}
+
+#[test]
+fn unknown_opcodes_fail_to_decode() {
+    for opcode in [0x5001, 0x8008, 0x9001, 0xE000, 0xF100, 0xF102, 0xF0FF].iter() {
+        assert_eq!(decode(*opcode), Err(DecodeError { opcode: *opcode }));
+    }
+}
+
+#[test]
+fn decodes_operands() {
+    assert_eq!(decode(0xD12F), Ok(Instruction::Draw { x: 1, y: 2, n: 0xF }));
+    assert_eq!(decode(0xB2AB), Ok(Instruction::JumpOffset { x: 2, nnn: 0x2AB }));
+    assert_eq!(decode(0x00C3), Ok(Instruction::ScrollDown(3)));
+    assert_eq!(decode(0x0123), Ok(Instruction::Sys(0x123)));
+    assert_eq!(decode(0xF000), Ok(Instruction::LoadILong));
+    assert_eq!(decode(0xF201), Ok(Instruction::Plane(2)));
+}
+
+#[test]
+fn displays_cowgod_mnemonics() {
+    let listing: Vec<String> = [0x00E0, 0x2ABC, 0x3A12, 0x8126, 0xA300, 0xB123, 0xD015, 0xE59E, 0xF355, 0xF000, 0x0123]
+        .iter()
+        .map(|opcode| decode(*opcode).unwrap().to_string())
+        .collect();
+    assert_eq!(listing, [
+        "CLS", "CALL 0xABC", "SE VA, 0x12", "SHR V1, V2", "LD I, 0x300", "JP V0, 0x123", "DRW V0, V1, 5",
+        "SKP V5", "LD [I], V3", "LD I, LONG", "SYS 0x123",
+    ]);
+}
+
+#[test]
+fn displays_octo_syntax() {
+    let listing: Vec<String> = [0x00E0, 0x2ABC, 0x3A12, 0x8126, 0xA300, 0xB123, 0xD015, 0xE59E, 0xF355, 0xF000, 0x5232]
+        .iter()
+        .map(|opcode| format!("{:#}", decode(*opcode).unwrap()))
+        .collect();
+    assert_eq!(listing, [
+        "clear", ":call 0xABC", "if va != 0x12 then", "v1 >>= v2", "i := 0x300", "jump0 0x123", "sprite v0 v1 5",
+        "if v5 -key then", "save v3", "i := long", "save v2 - v3",
+    ]);
+}
+
+#[test]
+fn labels_replace_the_target() {
+    let call = Instruction::Call(0x2AB);
+    assert_eq!(call.with_label("sub_2AB").to_string(), "CALL sub_2AB");
+    assert_eq!(format!("{:#}", call.with_label("sub_2AB")), ":call sub_2AB");
+}
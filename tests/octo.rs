@@ -0,0 +1,169 @@
+use rust_chip8::octo::{compile, Target};
+use rust_chip8::{AsmError, Chip8, Preset};
+
+fn xo(source: &str) -> Vec<u8> {
+    compile(source, Target::XoChip).unwrap_or_else(|e| panic!("{}", e))
+}
+
+fn error(source: &str, target: Target) -> AsmError {
+    compile(source, target).expect_err("the source shouldn't compile")
+}
+
+#[test]
+fn main_at_the_start_needs_no_jump() {
+    assert_eq!(xo(": main clear"), [0x00, 0xE0]);
+}
+
+#[test]
+fn main_further_on_is_jumped_to() {
+    assert_eq!(xo(": helper return\n: main helper"), [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+}
+
+#[test]
+fn main_is_required() {
+    let e = error(": start clear", Target::XoChip);
+    assert_eq!((e.line, e.column, e.message.as_str()), (1, 1, "the program doesn't define main"));
+}
+
+#[test]
+fn if_then_skips_the_next_statement_unless_the_condition_holds() {
+    assert_eq!(xo(": main if v0 == 5 then v1 := 1"), [0x40, 0x05, 0x61, 0x01]);
+    assert_eq!(xo(": main if v0 != v2 then v1 := 1"), [0x50, 0x20, 0x61, 0x01]);
+    assert_eq!(xo(": main if v3 key then v1 := 1"), [0xE3, 0xA1, 0x61, 0x01]);
+    assert_eq!(xo(": main if v3 -key then v1 := 1"), [0xE3, 0x9E, 0x61, 0x01]);
+}
+
+#[test]
+fn if_begin_skips_the_jump_when_the_condition_holds() {
+    assert_eq!(xo(": main if v0 == 1 begin v1 := 1 end"), [0x30, 0x01, 0x12, 0x06, 0x61, 0x01]);
+}
+
+#[test]
+fn else_jumps_over_the_other_branch() {
+    let rom = xo(": main if v0 == 1 begin v1 := 1 else v1 := 2 end clear");
+    assert_eq!(rom, [0x30, 0x01, 0x12, 0x08, 0x61, 0x01, 0x12, 0x0A, 0x61, 0x02, 0x00, 0xE0]);
+}
+
+#[test]
+fn comparisons_use_vf() {
+    // VF := 5, VF =- v0, which doesn't borrow when v0 >= 5, so that skips the clear
+    let rom = xo(": main if v0 < 5 then clear");
+    assert_eq!(rom, [0x6F, 0x05, 0x8F, 0x07, 0x3F, 0x01, 0x00, 0xE0]);
+}
+
+#[test]
+fn loop_while_again() {
+    let rom = xo(": main loop v0 += 1 while v0 != 10 again clear");
+    assert_eq!(rom, [0x70, 0x01, 0x40, 0x0A, 0x12, 0x08, 0x12, 0x00, 0x00, 0xE0]);
+}
+
+#[test]
+fn unbalanced_blocks_are_errors() {
+    assert_eq!(error(": main loop clear", Target::XoChip).message, "loop without again");
+    assert_eq!(error(": main again", Target::XoChip).message, "again without loop");
+    assert_eq!(error(": main while v0 == 1", Target::XoChip).message, "while outside of a loop");
+    assert_eq!(error(": main if v0 == 1 begin clear", Target::XoChip).message, "missing end");
+    assert_eq!(error(": main end", Target::XoChip).message, "end without if ... begin");
+}
+
+#[test]
+fn macros_substitute_their_arguments() {
+    let rom = xo(":macro bump X N { X += N X += N }\n: main bump v3 2 bump v4 1");
+    assert_eq!(rom, [0x73, 0x02, 0x73, 0x02, 0x74, 0x01, 0x74, 0x01]);
+}
+
+#[test]
+fn macros_count_their_calls() {
+    assert_eq!(xo(":macro id { CALLS }\n: main id id id"), [0, 1, 2]);
+}
+
+#[test]
+fn calc_groups_from_the_right() {
+    assert_eq!(xo(":calc Y { 2 * 3 + 1 }\n: main v0 := Y"), [0x60, 0x08]);
+    assert_eq!(xo(":calc Y { ( 2 * 3 ) + 1 }\n: main v0 := Y"), [0x60, 0x07]);
+    assert_eq!(xo(":calc Y { 7 / 2 }\n: main v0 := Y"), [0x60, 0x03]);
+}
+
+#[test]
+fn calc_can_redefine_a_constant() {
+    assert_eq!(xo(":calc X { 1 }\n:calc X { X + 1 }\n: main v0 := X"), [0x60, 0x02]);
+}
+
+#[test]
+fn calc_division_by_zero_is_an_error() {
+    let e = error(":calc Y { 1 / 0 }\n: main v0 := Y", Target::XoChip);
+    assert_eq!((e.line, e.column, e.message.as_str()), (1, 13, "division by zero"));
+    let e = error(":calc Y { 5 % 0 }", Target::XoChip);
+    assert_eq!(e.message, "division by zero");
+}
+
+#[test]
+fn calc_results_have_to_be_finite() {
+    let e = error(":calc Y { log 0 }", Target::XoChip);
+    assert_eq!((e.line, e.column, e.message.as_str()), (1, 9, "-inf isn't a finite number"));
+}
+
+#[test]
+fn aliases_name_registers() {
+    assert_eq!(xo(":alias x v3\n: main x := 7"), [0x63, 0x07]);
+    assert_eq!(xo(":alias y { 2 + 2 }\n: main y += 1"), [0x74, 0x01]);
+    let e = error(":alias z { 16 }", Target::XoChip);
+    assert_eq!(e.message, "16 isn't a register number");
+}
+
+#[test]
+fn compare_temp_alias_holds_the_operand() {
+    let rom = xo(":alias compare-temp ve\n: main if v0 < 5 then clear");
+    // The borrow still lands in VF
+    assert_eq!(rom, [0x6E, 0x05, 0x8E, 0x07, 0x3F, 0x01, 0x00, 0xE0]);
+}
+
+#[test]
+fn unpack_splits_an_address_into_v0_and_v1() {
+    assert_eq!(xo(": main :unpack 0xA data\n: data 1 2"), [0x60, 0xA2, 0x61, 0x04, 0x01, 0x02]);
+    assert_eq!(xo(": main :unpack long data\n: data 1 2"), [0x60, 0x02, 0x61, 0x04, 0x01, 0x02]);
+}
+
+#[test]
+fn chip8_target_rejects_super_chip_instructions() {
+    let e = error(": main hires", Target::Chip8);
+    assert_eq!((e.line, e.column, e.message.as_str()), (1, 8, "'hires' needs SUPER-CHIP, compiling for CHIP-8"));
+    assert_eq!(compile(": main hires", Target::SuperChip).unwrap(), [0x00, 0xFF]);
+}
+
+#[test]
+fn super_chip_target_rejects_xo_chip_instructions() {
+    let e = error(": main plane 1", Target::SuperChip);
+    assert_eq!(e.message, "'plane 1' needs XO-CHIP, compiling for SUPER-CHIP");
+    let e = error(": main i := long main", Target::SuperChip);
+    assert_eq!(e.message, "'i := long' needs XO-CHIP, compiling for SUPER-CHIP");
+    assert_eq!(xo(": main plane 1"), [0xF1, 0x01]);
+}
+
+#[test]
+fn memory_depends_on_the_target() {
+    let e = error(": main :org 0x1000 clear", Target::SuperChip);
+    assert_eq!(e.message, "4096 doesn't fit in program memory");
+    let rom = xo(": main :org 0x1000 clear");
+    assert_eq!(rom.len(), 0x1000 - 0x200 + 2);
+}
+
+#[test]
+fn target_follows_the_preset() {
+    assert_eq!(Target::for_preset(Preset::CosmacVip), Target::Chip8);
+    assert_eq!(Target::for_preset(Preset::SuperChip), Target::SuperChip);
+    assert_eq!(Target::for_preset(Preset::XoChip), Target::XoChip);
+    assert_eq!(Target::for_preset(Preset::Modern), Target::SuperChip);
+}
+
+#[test]
+fn programs_for_a_preset_load_under_its_quirks() {
+    for preset in Preset::ALL.iter() {
+        let ram_size = preset.quirks().ram_size();
+        let target = Target::for_preset(*preset);
+        // As far into memory as the machine goes, and no further
+        let rom = compile(&format!(": main :org {} clear", ram_size - 2), target).unwrap();
+        Chip8::with_quirks(preset.quirks()).load_rom(&rom).unwrap_or_else(|e| panic!("{}: {}", preset, e));
+        assert!(compile(&format!(": main :org {} clear", ram_size), target).is_err(), "{}", preset);
+    }
+}
@@ -0,0 +1,98 @@
+use rust_chip8::phosphor::{Persistence, Phosphor, DEFAULT_FADE_FRAMES};
+use rust_chip8::Palette;
+
+// Black background, plane 1 red, plane 2 green, so both planes together are yellow
+fn palette() -> Palette {
+    Palette::new([0x000000, 0xFF0000, 0x00FF00, 0xFFFF00])
+}
+
+// Ends a frame showing buffer, then renders it
+fn frame(phosphor: &mut Phosphor, buffer: &[u8]) -> Vec<u32> {
+    phosphor.end_frame(buffer, buffer.len(), 1);
+    phosphor.render(buffer, buffer.len(), 1, &palette()).to_vec()
+}
+
+#[test]
+fn persistence_parses() {
+    assert_eq!("off".parse(), Ok(Persistence::Off));
+    assert_eq!("None".parse(), Ok(Persistence::Off));
+    assert_eq!("MAX".parse(), Ok(Persistence::MaxOfTwo));
+    assert_eq!("fade".parse(), Ok(Persistence::Fade(DEFAULT_FADE_FRAMES)));
+    assert_eq!("fade:10".parse(), Ok(Persistence::Fade(10)));
+    assert_eq!("fade:0".parse(), Ok(Persistence::Off));
+    for bad in ["blur", "fade:", "fade:x", "fade:256", "fade:-1"].iter() {
+        let e = bad.parse::<Persistence>().unwrap_err();
+        assert_eq!(e, format!("unknown persistence '{}', expected off, max, fade or fade:FRAMES", bad));
+    }
+}
+
+#[test]
+fn persistence_displays_as_it_parses() {
+    for persistence in [Persistence::Off, Persistence::MaxOfTwo, Persistence::Fade(7)].iter() {
+        assert_eq!(persistence.to_string().parse(), Ok(*persistence));
+    }
+}
+
+#[test]
+fn off_shows_the_buffer_as_it_is() {
+    let mut phosphor = Phosphor::default();
+    assert_eq!(frame(&mut phosphor, &[1, 2]), [0xFF0000, 0x00FF00]);
+    assert_eq!(frame(&mut phosphor, &[0, 3]), [0x000000, 0xFFFF00]);
+}
+
+#[test]
+fn fade_dims_a_cleared_pixel_a_step_a_frame() {
+    let mut phosphor = Phosphor::new(Persistence::Fade(4));
+    assert_eq!(frame(&mut phosphor, &[1]), [0xFF0000]);
+    // 4/5, 3/5, 2/5 and 1/5 of the way from black to red, then black
+    let faded: Vec<u32> = (0..6).map(|_| frame(&mut phosphor, &[0])[0]).collect();
+    assert_eq!(faded, [0xCC0000, 0x990000, 0x660000, 0x330000, 0x000000, 0x000000]);
+}
+
+#[test]
+fn fade_starts_over_when_a_pixel_is_lit_again() {
+    let mut phosphor = Phosphor::new(Persistence::Fade(4));
+    frame(&mut phosphor, &[1]);
+    frame(&mut phosphor, &[0]);
+    frame(&mut phosphor, &[0]);
+    // Lit pixels show their own color, whatever was fading underneath
+    assert_eq!(frame(&mut phosphor, &[2]), [0x00FF00]);
+    assert_eq!(frame(&mut phosphor, &[0]), [0x00CC00]);
+}
+
+#[test]
+fn max_of_two_ors_the_planes_of_the_last_two_frames() {
+    let mut phosphor = Phosphor::new(Persistence::MaxOfTwo);
+    assert_eq!(frame(&mut phosphor, &[1, 0]), [0xFF0000, 0x000000]);
+    assert_eq!(frame(&mut phosphor, &[2, 0]), [0xFFFF00, 0x000000]);
+    assert_eq!(frame(&mut phosphor, &[0, 0]), [0x00FF00, 0x000000]);
+    assert_eq!(frame(&mut phosphor, &[0, 0]), [0x000000, 0x000000]);
+}
+
+#[test]
+fn changing_resolution_forgets_the_glow() {
+    let mut phosphor = Phosphor::new(Persistence::Fade(4));
+    frame(&mut phosphor, &[1]);
+    assert_eq!(frame(&mut phosphor, &[0, 0]), [0x000000, 0x000000]);
+
+    let mut phosphor = Phosphor::new(Persistence::MaxOfTwo);
+    frame(&mut phosphor, &[1, 1]);
+    phosphor.end_frame(&[0, 0], 1, 2);
+    assert_eq!(phosphor.render(&[0, 0], 1, 2, &palette()), [0x000000, 0x000000]);
+}
+
+#[test]
+fn reset_forgets_the_glow() {
+    let mut phosphor = Phosphor::new(Persistence::Fade(4));
+    frame(&mut phosphor, &[1]);
+    phosphor.end_frame(&[0], 1, 1);
+    phosphor.reset();
+    assert_eq!(phosphor.render(&[0], 1, 1, &palette()), [0x000000]);
+
+    phosphor.set_persistence(Persistence::MaxOfTwo);
+    frame(&mut phosphor, &[1]);
+    phosphor.end_frame(&[0], 1, 1);
+    phosphor.set_persistence(Persistence::MaxOfTwo);
+    assert_eq!(phosphor.persistence(), Persistence::MaxOfTwo);
+    assert_eq!(phosphor.render(&[0], 1, 1, &palette()), [0x000000]);
+}
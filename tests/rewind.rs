@@ -0,0 +1,90 @@
+use rust_chip8::rewind::Rewind;
+use rust_chip8::{Chip8, StateError};
+
+// Fills V0 and V1 with random numbers and draws a digit, forever
+const ROM: [u8; 10] = [0xC0, 0xFF, 0xC1, 0xFF, 0xA0, 0x00, 0xD0, 0x15, 0x12, 0x00];
+
+fn machine(rom: &[u8]) -> Chip8 {
+    let mut chip8 = Chip8::with_seed(42);
+    chip8.load_rom(rom).unwrap();
+    chip8
+}
+
+// Runs and records frames, after recording where it starts from
+fn record(rewind: &mut Rewind, chip8: &mut Chip8, frames: usize) {
+    rewind.record(chip8);
+    for _ in 0..frames {
+        chip8.run_frame().unwrap();
+        rewind.record(chip8);
+    }
+}
+
+#[test]
+fn stepping_back_every_frame_gets_to_the_start() {
+    let mut chip8 = machine(&ROM);
+    let start = chip8.save_state();
+    let mut rewind = Rewind::new(100);
+    record(&mut rewind, &mut chip8, 20);
+    assert_eq!(rewind.len(), 20);
+
+    for _ in 0..20 {
+        assert_eq!(rewind.step_back(&mut chip8), Ok(true));
+    }
+    assert_eq!(chip8.save_state(), start);
+    assert_eq!(rewind.step_back(&mut chip8), Ok(false));
+    assert_eq!(chip8.save_state(), start);
+}
+
+#[test]
+fn each_step_back_goes_one_frame() {
+    let mut chip8 = machine(&ROM);
+    let mut rewind = Rewind::new(100);
+    rewind.record(&chip8);
+    let mut states = vec![chip8.save_state()];
+    for _ in 0..5 {
+        chip8.run_frame().unwrap();
+        rewind.record(&chip8);
+        states.push(chip8.save_state());
+    }
+    states.pop();
+    while let Some(state) = states.pop() {
+        rewind.step_back(&mut chip8).unwrap();
+        assert_eq!(chip8.save_state(), state);
+    }
+}
+
+#[test]
+fn history_keeps_to_the_capacity() {
+    let mut chip8 = machine(&ROM);
+    let mut rewind = Rewind::new(5);
+    record(&mut rewind, &mut chip8, 10);
+    assert_eq!(rewind.len(), 5);
+
+    rewind.set_capacity(2);
+    assert_eq!(rewind.len(), 2);
+    record(&mut rewind, &mut chip8, 3);
+    assert_eq!(rewind.len(), 2);
+
+    rewind.set_capacity(0);
+    record(&mut rewind, &mut chip8, 3);
+    assert!(rewind.is_empty());
+    assert_eq!(rewind.step_back(&mut chip8), Ok(false));
+}
+
+#[test]
+fn rejected_state_leaves_the_history_alone() {
+    let mut chip8 = machine(&ROM);
+    let mut rewind = Rewind::new(10);
+    record(&mut rewind, &mut chip8, 2);
+    let previous = chip8.save_state();
+    chip8.run_frame().unwrap();
+    rewind.record(&chip8);
+
+    let mut other = machine(&[0x12, 0x00]);
+    assert_eq!(rewind.step_back(&mut other), Err(StateError::RomMismatch));
+    assert_eq!(rewind.len(), 3);
+
+    assert_eq!(rewind.step_back(&mut chip8), Ok(true));
+    assert_eq!(chip8.save_state(), previous);
+    assert_eq!(rewind.len(), 2);
+}
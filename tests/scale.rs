@@ -0,0 +1,68 @@
+use rust_chip8::scale::{self, Viewport};
+
+const BORDER: u32 = 9;
+
+fn viewport(x: usize, y: usize, width: usize, height: usize, scale: usize) -> Viewport {
+    Viewport { x, y, width, height, scale }
+}
+
+// Rows of a target, to make failures readable
+fn rows(target: &[u32], width: usize) -> Vec<Vec<u32>> {
+    target.chunks(width).map(|row| row.to_vec()).collect()
+}
+
+#[test]
+fn exact_multiples_fill_the_target() {
+    assert_eq!(Viewport::fit(64, 32, 640, 320), viewport(0, 0, 640, 320, 10));
+
+    let mut target = vec![0; 4 * 4];
+    assert_eq!(scale::blit(&[1, 2, 3, 4], 2, 2, &mut target, 4, 4, BORDER), viewport(0, 0, 4, 4, 2));
+    assert_eq!(rows(&target, 4), [[1, 1, 2, 2], [1, 1, 2, 2], [3, 3, 4, 4], [3, 3, 4, 4]]);
+}
+
+#[test]
+fn leftover_space_is_split_into_borders() {
+    // Letterboxed, then pillarboxed
+    assert_eq!(Viewport::fit(64, 32, 800, 600), viewport(16, 108, 768, 384, 12));
+    assert_eq!(Viewport::fit(64, 32, 1000, 320), viewport(180, 0, 640, 320, 10));
+
+    let mut target = vec![0; 7 * 5];
+    assert_eq!(scale::blit(&[1, 2], 2, 1, &mut target, 7, 5, BORDER), viewport(0, 1, 6, 3, 3));
+    assert_eq!(rows(&target, 7), [
+        [9, 9, 9, 9, 9, 9, 9],
+        [1, 1, 1, 2, 2, 2, 9],
+        [1, 1, 1, 2, 2, 2, 9],
+        [1, 1, 1, 2, 2, 2, 9],
+        [9, 9, 9, 9, 9, 9, 9],
+    ]);
+}
+
+#[test]
+fn small_targets_show_the_top_left_at_scale_1() {
+    assert_eq!(Viewport::fit(64, 32, 50, 20), viewport(0, 0, 64, 32, 1));
+    assert_eq!(Viewport::fit(64, 32, 0, 0), viewport(0, 0, 64, 32, 1));
+
+    let mut target = vec![0; 2];
+    assert_eq!(scale::blit(&[1, 2, 3, 4, 5, 6], 3, 2, &mut target, 2, 1, BORDER), viewport(0, 0, 3, 2, 1));
+    assert_eq!(target, [1, 2]);
+}
+
+#[test]
+fn hires_and_lores_cover_the_same_area() {
+    assert_eq!(Viewport::fit(128, 64, 640, 480), viewport(0, 80, 640, 320, 5));
+    assert_eq!(Viewport::fit(64, 32, 640, 480), viewport(0, 80, 640, 320, 10));
+
+    // Switching back to lores redraws the whole target, with nothing left over from hires
+    let mut target = vec![0; 8 * 6];
+    let hires: Vec<u32> = (1..=8).collect();
+    assert_eq!(scale::blit(&hires, 4, 2, &mut target, 8, 6, BORDER), viewport(0, 1, 8, 4, 2));
+    assert_eq!(scale::blit(&[1, 2], 2, 1, &mut target, 8, 6, BORDER), viewport(0, 1, 8, 4, 4));
+    assert_eq!(rows(&target, 8), [
+        [9, 9, 9, 9, 9, 9, 9, 9],
+        [1, 1, 1, 1, 2, 2, 2, 2],
+        [1, 1, 1, 1, 2, 2, 2, 2],
+        [1, 1, 1, 1, 2, 2, 2, 2],
+        [1, 1, 1, 1, 2, 2, 2, 2],
+        [9, 9, 9, 9, 9, 9, 9, 9],
+    ]);
+}
@@ -0,0 +1,91 @@
+use rust_chip8::state::{MAGIC, VERSION};
+use rust_chip8::{Chip8, StateError};
+
+// Fills V0 and V1 with random numbers and draws a digit, forever
+const ROM: [u8; 10] = [0xC0, 0xFF, 0xC1, 0xFF, 0xA0, 0x00, 0xD0, 0x15, 0x12, 0x00];
+// Where the payload starts, after the magic, version, rom hash and length
+const PAYLOAD_START: usize = 4 + 2 + 4 + 4;
+
+fn machine(rom: &[u8]) -> Chip8 {
+    let mut chip8 = Chip8::with_seed(42);
+    chip8.load_rom(rom).unwrap();
+    chip8
+}
+
+// The random registers after each of the next frames
+fn run(chip8: &mut Chip8, frames: usize) -> Vec<(u8, u8)> {
+    (0..frames)
+        .map(|_| {
+            chip8.run_frame().unwrap();
+            (chip8.register(0), chip8.register(1))
+        })
+        .collect()
+}
+
+#[test]
+fn loading_a_state_replays_the_same_run() {
+    let mut chip8 = machine(&ROM);
+    run(&mut chip8, 3);
+    let saved = chip8.save_state();
+    let first = run(&mut chip8, 5);
+    let after_first = chip8.save_state();
+
+    chip8.load_state(&saved).unwrap();
+    assert_eq!(chip8.save_state(), saved);
+    assert_eq!(run(&mut chip8, 5), first);
+    assert_eq!(chip8.save_state(), after_first);
+}
+
+#[test]
+fn state_loads_into_a_fresh_machine() {
+    let mut chip8 = machine(&ROM);
+    run(&mut chip8, 3);
+    let saved = chip8.save_state();
+    let expected = run(&mut chip8, 5);
+
+    let mut other = machine(&ROM);
+    other.load_state(&saved).unwrap();
+    assert_eq!(run(&mut other, 5), expected);
+}
+
+#[test]
+fn state_from_another_rom_is_rejected() {
+    let saved = machine(&ROM).save_state();
+    let mut other = machine(&[0x12, 0x00]);
+    let before = other.save_state();
+    assert_eq!(other.load_state(&saved), Err(StateError::RomMismatch));
+    assert_eq!(other.save_state(), before);
+}
+
+#[test]
+fn corrupted_state_is_rejected() {
+    let mut chip8 = machine(&ROM);
+    let mut saved = chip8.save_state();
+    saved[PAYLOAD_START + 20] ^= 0x01;
+    assert_eq!(chip8.load_state(&saved), Err(StateError::ChecksumMismatch));
+}
+
+#[test]
+fn bad_magic_is_rejected() {
+    let mut chip8 = machine(&ROM);
+    let mut saved = chip8.save_state();
+    assert_eq!(&saved[..4], MAGIC);
+    saved[0] = b'X';
+    assert_eq!(chip8.load_state(&saved), Err(StateError::BadMagic));
+}
+
+#[test]
+fn other_version_is_rejected() {
+    let mut chip8 = machine(&ROM);
+    let mut saved = chip8.save_state();
+    saved[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+    assert_eq!(chip8.load_state(&saved), Err(StateError::UnsupportedVersion(VERSION + 1)));
+}
+
+#[test]
+fn truncated_state_is_rejected() {
+    let mut chip8 = machine(&ROM);
+    let saved = chip8.save_state();
+    assert_eq!(chip8.load_state(&saved[..saved.len() - 1]), Err(StateError::Truncated));
+    assert_eq!(chip8.load_state(&[]), Err(StateError::Truncated));
+}